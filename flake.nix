@@ -203,6 +203,14 @@
           };
         };
 
+        # Same agent with the in-process emulated card compiled in, for the
+        # hermetic bats suite (pivy_agent_rust_emulated.bats)
+        pivy-rust-emulated = pivy-rust.overrideAttrs (old: {
+          pname = "pivy-agent-emulated";
+          cargoBuildFeatures = [ "emulator" ];
+          cargoCheckFeatures = [ "emulator" ];
+        });
+
         pivy-agent-conformance = pkgs.buildGoModule {
           pname = "pivy-agent-conformance";
           version = "0.1.0";
@@ -372,6 +380,7 @@
         packages.default = pivy;
         packages.pivy = pivy;
         packages.pivy-rust = pivy-rust;
+        packages.pivy-rust-emulated = pivy-rust-emulated;
         packages.pivy-agent-conformance = pivy-agent-conformance;
         packages.libressl = libressl;
         packages.openssh = openssh;
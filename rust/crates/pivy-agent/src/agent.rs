@@ -1,16 +1,18 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
 use ssh_agent_lib::{
     agent::Session,
     error::AgentError,
-    proto::{signature, Identity, SignRequest},
+    proto::{signature, Extension, Identity, ProtoError, SignRequest},
+    ssh_encoding::{Decode, Encode},
 };
 use ssh_key::{public::KeyData, Algorithm, Signature};
+use zeroize::Zeroizing;
+
+use pivy_piv::cert::pkey_from_ssh_public;
+use pivy_piv::{EcdhBox, Guid, PivAlgorithm};
 
-use pivy_piv::{Guid, PivAlgorithm, PivContext};
+use crate::card::CardManager;
 
-/// Cached key info from a PIV token (populated at startup)
+/// Cached key info from a PIV token (refreshed when the card is inserted)
 #[derive(Clone)]
 pub struct CachedKey {
     pub guid: Guid,
@@ -18,36 +20,184 @@ pub struct CachedKey {
     pub slot_id: u8,
     pub algorithm: PivAlgorithm,
     pub public_key: KeyData,
+    /// The slot's certificate; empty for keys found without one
+    pub cert_der: Vec<u8>,
     pub comment: String,
 }
 
 #[derive(Clone)]
 pub struct PivyAgent {
-    keys: Arc<Mutex<Vec<CachedKey>>>,
-    pin: Arc<Mutex<Option<String>>>,
+    cards: CardManager,
 }
 
 impl PivyAgent {
-    pub fn new(keys: Vec<CachedKey>) -> Self {
-        Self {
-            keys: Arc::new(Mutex::new(keys)),
-            pin: Arc::new(Mutex::new(None)),
+    pub fn new(cards: CardManager) -> Self {
+        Self { cards }
+    }
+
+    fn find_key(keys: &[CachedKey], pubkey: &KeyData) -> Option<CachedKey> {
+        keys.iter().find(|k| k.public_key == *pubkey).cloned()
+    }
+
+    /// The cached PIN, if the key's slot needs one (9E doesn't). Taken
+    /// before touching the card so no transaction is held open across an
+    /// await.
+    async fn pin_for(&self, key: &CachedKey) -> Result<Option<String>, AgentError> {
+        if key.slot_id == 0x9E {
+            return Ok(None);
         }
+        let pin = self
+            .cards
+            .pin()
+            .await
+            .ok_or_else(|| AgentError::Other("PIN required (use ssh-add -X)".into()))?;
+        Ok(Some(pin))
     }
 
-    pub fn pin_handle(&self) -> Arc<Mutex<Option<String>>> {
-        self.pin.clone()
+    /// ecdh@joyent.com: ECDH between a card key and a partner EC key,
+    /// returning the shared secret.
+    async fn ecdh(&self, request: &Extension) -> Result<Extension, AgentError> {
+        let (pubkey, partner, flags) = parse_ecdh_request(request.details.as_ref())?;
+        if flags != 0 {
+            return Err(AgentError::Other(format!("unsupported flags {flags:#x}").into()));
+        }
+        let keys = self.cards.keys().await;
+        let key = Self::find_key(&keys, &pubkey)
+            .ok_or_else(|| AgentError::Other("key not found".into()))?;
+        if !matches!(pubkey, KeyData::Ecdsa(_)) || !matches!(partner, KeyData::Ecdsa(_)) {
+            return Err(AgentError::Other(
+                format!(
+                    "keys are not both EC keys ({} and {})",
+                    pubkey.algorithm(),
+                    partner.algorithm()
+                )
+                .into(),
+            ));
+        }
+        tracing::debug!(
+            reader = %key.reader_name,
+            slot = format_args!("{:02X}", key.slot_id),
+            "ECDH request"
+        );
+
+        let partner = pkey_from_ssh_public(&partner).map_err(AgentError::other)?;
+        let pin = self.pin_for(&key).await?;
+        let slot_id = key.slot_id;
+        let secret = self
+            .cards
+            .with_token(&key.guid, move |token| {
+                let mut txn = token.begin_transaction()?;
+                if let Some(pin) = &pin {
+                    txn.verify_pin(pin)?;
+                }
+                txn.ecdh(slot_id, &partner)
+            })
+            .await
+            .map_err(|e| AgentError::Other(e.to_string().into()))?;
+        tracing::info!(slot = format_args!("{:02X}", key.slot_id), "performed ECDH operation");
+
+        // Sized up front so encoding never reallocates and leaves a copy of
+        // the secret behind. The buffer itself is moved out rather than
+        // copied, and from there it's up to ssh-agent-lib.
+        let mut details = Zeroizing::new(Vec::with_capacity(4 + secret.len()));
+        secret.as_slice().encode(&mut *details).map_err(ProtoError::from)?;
+        Ok(Extension {
+            name: EXT_ECDH.into(),
+            details: std::mem::take(&mut *details).into(),
+        })
     }
 
-    fn find_key(keys: &[CachedKey], pubkey: &KeyData) -> Option<CachedKey> {
-        keys.iter().find(|k| k.public_key == *pubkey).cloned()
+    /// ecdh-rebox@joyent.com: open an ECDH box sealed to a card key and
+    /// seal its contents to a new recipient, so the secret never leaves
+    /// the agent.
+    ///
+    /// The C agent runs try_confirm_client first. This agent has no -C
+    /// confirmation mode, so it always behaves like C's default (C_NEVER)
+    /// and lets the client through, the same as for sign and ecdh.
+    async fn rebox(&self, request: &Extension) -> Result<Extension, AgentError> {
+        let req = parse_rebox_request(request.details.as_ref())?;
+        if req.flags != 0 {
+            return Err(AgentError::Other(format!("unsupported flags {:#x}", req.flags).into()));
+        }
+        let sealed = EcdhBox::decode(&req.ecdh_box).map_err(AgentError::other)?;
+        let new_guid = match req.guid.as_slice() {
+            [] => None,
+            guid => Some(Guid::from_bytes(guid).map_err(AgentError::other)?),
+        };
+
+        // The same key may be on more than one card (e.g. imported); use
+        // the card the box names if it's here
+        let recipient = sealed.recipient();
+        let keys = self.cards.keys().await;
+        let mut candidates = keys.iter().filter(|k| k.public_key == recipient);
+        let key = candidates
+            .clone()
+            .find(|k| sealed.guid() == Some(&k.guid))
+            .or_else(|| candidates.next())
+            .cloned()
+            .ok_or_else(|| AgentError::Other("box is not sealed to any key here".into()))?;
+        tracing::debug!(
+            reader = %key.reader_name,
+            slot = format_args!("{:02X}", key.slot_id),
+            "ECDH rebox request"
+        );
+
+        let pin = self.pin_for(&key).await?;
+        let slot_id = key.slot_id;
+        let data = self
+            .cards
+            .with_token(&key.guid, move |token| {
+                let mut txn = token.begin_transaction()?;
+                if let Some(pin) = &pin {
+                    txn.verify_pin(pin)?;
+                }
+                txn.open_box(slot_id, &sealed)
+            })
+            .await
+            .map_err(|e| AgentError::Other(e.to_string().into()))?;
+        tracing::info!(slot = format_args!("{:02X}", key.slot_id), "opened ECDH box");
+
+        let mut reboxed = EcdhBox::seal(&req.partner, &data).map_err(AgentError::other)?;
+        if let Some(guid) = new_guid {
+            reboxed.set_guid_slot(guid, req.slot_id);
+        }
+        let mut details = Vec::new();
+        reboxed.encode().encode(&mut details).map_err(ProtoError::from)?;
+        Ok(Extension {
+            name: EXT_REBOX.into(),
+            details: details.into(),
+        })
+    }
+
+    /// x509-certs@joyent.com: the certificate for a card key, from the
+    /// cache so the card isn't touched.
+    async fn x509_cert(&self, request: &Extension) -> Result<Extension, AgentError> {
+        let (pubkey, flags) = parse_x509_certs_request(request.details.as_ref())?;
+        if flags != 0 {
+            return Err(AgentError::Other(format!("unsupported flags {flags:#x}").into()));
+        }
+        let keys = self.cards.keys().await;
+        let key = Self::find_key(&keys, &pubkey)
+            .ok_or_else(|| AgentError::Other("key not found".into()))?;
+        if key.cert_der.is_empty() {
+            return Err(AgentError::Other(
+                format!("slot {:02X} has no certificate", key.slot_id).into(),
+            ));
+        }
+
+        let mut details = Vec::new();
+        key.cert_der.encode(&mut details).map_err(ProtoError::from)?;
+        Ok(Extension {
+            name: EXT_X509_CERTS.into(),
+            details: details.into(),
+        })
     }
 }
 
 #[ssh_agent_lib::async_trait]
 impl Session for PivyAgent {
     async fn request_identities(&mut self) -> Result<Vec<Identity>, AgentError> {
-        let keys = self.keys.lock().await;
+        let keys = self.cards.keys().await;
         let identities = keys
             .iter()
             .map(|k| Identity {
@@ -59,57 +209,123 @@ impl Session for PivyAgent {
     }
 
     async fn sign(&mut self, request: SignRequest) -> Result<Signature, AgentError> {
-        let keys = self.keys.lock().await;
+        let keys = self.cards.keys().await;
         let key = Self::find_key(&keys, &request.pubkey)
             .ok_or_else(|| AgentError::Other("key not found".into()))?;
-        drop(keys);
+        tracing::debug!(
+            reader = %key.reader_name,
+            slot = format_args!("{:02X}", key.slot_id),
+            "sign request"
+        );
 
-        // Reconnect to card for signing
-        let ctx = PivContext::new().map_err(|e| AgentError::Other(e.to_string().into()))?;
-        let tokens = ctx
-            .enumerate_tokens()
-            .map_err(|e| AgentError::Other(e.to_string().into()))?;
-        let token = tokens
-            .iter()
-            .find(|t| t.guid() == &key.guid)
-            .ok_or_else(|| AgentError::Other("PIV token no longer available".into()))?;
-
-        // Verify PIN if needed (slot 9E doesn't require PIN)
-        if key.slot_id != 0x9E {
-            let pin_guard = self.pin.lock().await;
-            let pin = pin_guard
-                .as_ref()
-                .ok_or_else(|| AgentError::Other("PIN required (use ssh-add -X)".into()))?;
-            token
-                .verify_pin(pin)
-                .map_err(|e| AgentError::Other(e.to_string().into()))?;
-        }
+        let pin = self.pin_for(&key).await?;
 
         // Prepare data for signing based on algorithm
         let sign_data = prepare_sign_data(key.algorithm, &request.data, request.flags)?;
 
-        // Sign via card
-        let sig_bytes = token
-            .sign_prehash(key.slot_id, &sign_data)
+        // VERIFY and GENERAL AUTHENTICATE in one transaction, so no other
+        // client can reset the card in between
+        let slot_id = key.slot_id;
+        let sig_bytes = self
+            .cards
+            .with_token(&key.guid, move |token| {
+                let mut txn = token.begin_transaction()?;
+                if let Some(pin) = &pin {
+                    txn.verify_pin(pin)?;
+                }
+                txn.sign_prehash(slot_id, &sign_data)
+            })
+            .await
             .map_err(|e| AgentError::Other(e.to_string().into()))?;
 
         // Convert raw signature bytes to ssh_key::Signature
         to_ssh_signature(key.algorithm, &sig_bytes, request.flags)
     }
 
+    async fn extension(&mut self, extension: Extension) -> Result<Option<Extension>, AgentError> {
+        let response = match extension.name.as_str() {
+            EXT_ECDH => self.ecdh(&extension).await,
+            EXT_REBOX => self.rebox(&extension).await,
+            EXT_X509_CERTS => self.x509_cert(&extension).await,
+            _ => return Err(ProtoError::UnsupportedCommand { command: 27 }.into()),
+        };
+        // Extension failures carry no details on the wire, so log them
+        match response {
+            Ok(response) => Ok(Some(response)),
+            Err(e) => {
+                tracing::warn!(extension = %extension.name, "extension failed: {e}");
+                Err(AgentError::ExtensionFailure)
+            }
+        }
+    }
+
     async fn lock(&mut self, _key: String) -> Result<(), AgentError> {
-        let mut pin = self.pin.lock().await;
-        *pin = None;
+        self.cards.set_pin(None).await;
         Ok(())
     }
 
     async fn unlock(&mut self, key: String) -> Result<(), AgentError> {
-        let mut pin = self.pin.lock().await;
-        *pin = Some(key);
+        self.cards.set_pin(Some(key)).await;
         Ok(())
     }
 }
 
+const EXT_ECDH: &str = "ecdh@joyent.com";
+const EXT_REBOX: &str = "ecdh-rebox@joyent.com";
+const EXT_X509_CERTS: &str = "x509-certs@joyent.com";
+
+/// Parse an ecdh@joyent.com request: a string wrapping the card key, the
+/// partner key and flags.
+fn parse_ecdh_request(details: &[u8]) -> Result<(KeyData, KeyData, u32), ProtoError> {
+    let mut outer = details;
+    let inner = Vec::<u8>::decode(&mut outer)?;
+    let reader = &mut inner.as_slice();
+    let pubkey = read_sshkey(reader)?;
+    let partner = read_sshkey(reader)?;
+    let flags = u32::decode(reader)?;
+    Ok((pubkey, partner, flags))
+}
+
+/// An ecdh-rebox@joyent.com request.
+struct ReboxRequest {
+    ecdh_box: Vec<u8>,
+    /// GUID and slot to record in the new box; empty for none
+    guid: Vec<u8>,
+    slot_id: u8,
+    partner: KeyData,
+    flags: u32,
+}
+
+/// Parse an ecdh-rebox@joyent.com request: a string wrapping the box, the
+/// new GUID and slot, the partner key and flags.
+fn parse_rebox_request(details: &[u8]) -> Result<ReboxRequest, ProtoError> {
+    let mut outer = details;
+    let inner = Vec::<u8>::decode(&mut outer)?;
+    let reader = &mut inner.as_slice();
+    Ok(ReboxRequest {
+        ecdh_box: Vec::<u8>::decode(reader)?,
+        guid: Vec::<u8>::decode(reader)?,
+        slot_id: u8::decode(reader)?,
+        partner: read_sshkey(reader)?,
+        flags: u32::decode(reader)?,
+    })
+}
+
+/// Parse an x509-certs@joyent.com request: the key and flags, not
+/// wrapped in a string.
+fn parse_x509_certs_request(details: &[u8]) -> Result<(KeyData, u32), ProtoError> {
+    let reader = &mut &details[..];
+    let pubkey = read_sshkey(reader)?;
+    let flags = u32::decode(reader)?;
+    Ok((pubkey, flags))
+}
+
+/// Read an SSH public key blob in a length-prefixed string.
+fn read_sshkey(reader: &mut &[u8]) -> Result<KeyData, ProtoError> {
+    let blob = Vec::<u8>::decode(reader)?;
+    Ok(KeyData::decode(&mut blob.as_slice())?)
+}
+
 /// Hash data and prepare it for the PIV card's GENERAL AUTHENTICATE.
 /// For ECDSA: returns the hash digest.
 /// For RSA: returns PKCS#1 v1.5 DigestInfo padded to key size.
@@ -152,6 +368,7 @@ fn prepare_sign_data(alg: PivAlgorithm, data: &[u8], flags: u32) -> Result<Vec<u
             // Ed25519 does its own hashing on card; pass raw data
             Ok(data.to_vec())
         }
+        PivAlgorithm::X25519 => Err(AgentError::Other("X25519 keys can't sign".into())),
     }
 }
 
@@ -219,10 +436,9 @@ fn to_ssh_signature(
             Signature::new(algo, ssh_sig).map_err(AgentError::other)
         }
         PivAlgorithm::Rsa1024 | PivAlgorithm::Rsa2048 => {
+            // rsa-sha2-256 is also the default when no flag is set
             let algo_name = if flags & signature::RSA_SHA2_512 != 0 {
                 "rsa-sha2-512"
-            } else if flags & signature::RSA_SHA2_256 != 0 {
-                "rsa-sha2-256"
             } else {
                 "rsa-sha2-256"
             };
@@ -233,6 +449,7 @@ fn to_ssh_signature(
             let algo = Algorithm::new("ssh-ed25519").map_err(AgentError::other)?;
             Signature::new(algo, sig_bytes.to_vec()).map_err(AgentError::other)
         }
+        PivAlgorithm::X25519 => Err(AgentError::Other("X25519 keys can't sign".into())),
     }
 }
 
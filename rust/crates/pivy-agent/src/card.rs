@@ -1,40 +1,403 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError};
+use std::time::Instant;
 use tokio::sync::Mutex;
-use tokio::time::{interval, Duration};
+use tokio::time::Duration;
 
-use pivy_piv::{Guid, PivContext};
+use pivy_piv::{
+    CardTransport, Guid, PcscTransport, PivContext, PivError, PivToken, ReaderEvent,
+    ReaderMonitor,
+};
 
-const PROBE_INTERVAL: Duration = Duration::from_secs(60);
-const PROBE_FAIL_LIMIT: u32 = 3;
+use crate::agent::CachedKey;
 
-/// Background task that periodically probes the PIV card.
-/// Forgets the cached PIN if the card disappears.
-pub async fn probe_loop(guid: Guid, pin: Arc<Mutex<Option<String>>>) {
-    let mut failures: u32 = 0;
-    let mut interval = interval(PROBE_INTERVAL);
+/// How long the monitor waits for card events before checking on the PIN
+const MONITOR_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the primary card may be gone before the agent forgets its PIN
+const PIN_FORGET_DELAY: Duration = Duration::from_secs(180);
+/// Pause before retrying when PC/SC is unavailable (e.g. pcscd restarting)
+const PCSC_RETRY_DELAY: Duration = Duration::from_secs(10);
 
-    loop {
-        interval.tick().await;
+/// Token type used throughout the agent, so the card can be reached over
+/// PC/SC or (with the `emulator` feature) an in-process emulated reader.
+pub type AgentToken = PivToken<Box<dyn CardTransport + Send + Sync>>;
 
-        let card_present = match PivContext::new() {
-            Ok(ctx) => match ctx.enumerate_tokens() {
-                Ok(tokens) => tokens.iter().any(|t| *t.guid() == guid),
-                Err(_) => false,
-            },
-            Err(_) => false,
+/// A connected card and the reader it sits in.
+struct Card {
+    reader: String,
+    /// Locked for each operation on the card. It has its own lock so that
+    /// a slow operation (waiting for a touch, say) holds up neither the
+    /// other cards nor the key list.
+    token: Arc<std::sync::Mutex<AgentToken>>,
+    /// Every key on the card, ahead of the slot filter, so the card can
+    /// start being served without talking to it
+    keys: Vec<CachedKey>,
+}
+
+/// Which cards the agent serves keys from (-g and -A).
+pub struct CardSelection {
+    /// Only cards with this GUID or short ID
+    pub guid: Option<String>,
+    /// Every matching card rather than just the first one
+    pub all_cards: bool,
+}
+
+impl CardSelection {
+    fn matches(&self, guid: &Guid) -> bool {
+        self.guid
+            .as_ref()
+            .is_none_or(|want| guid.to_hex() == *want || guid.short_id() == *want)
+    }
+}
+
+struct CardState {
+    /// Cards currently inserted, by GUID
+    cards: HashMap<Guid, Card>,
+    /// Keys served from the selected cards that are present
+    keys: Vec<CachedKey>,
+    selection: CardSelection,
+    /// The first matching card present; without -A the only one served.
+    /// When it goes, another matching card takes over.
+    primary: Option<Guid>,
+    /// Slots to serve keys from (-S); all of them if unset
+    allowed_slots: Option<Vec<u8>>,
+    /// PIN given with ssh-add -X
+    pin: Option<String>,
+    /// The primary card the PIN was given for. It stays set while that
+    /// card is out, so the PIN survives the card being reinserted.
+    pin_card: Option<Guid>,
+}
+
+impl CardState {
+    fn is_selected(&self, guid: &Guid) -> bool {
+        self.selection.matches(guid)
+            && (self.selection.all_cards || self.primary.as_ref().is_none_or(|p| p == guid))
+    }
+
+    /// Take a newly connected card, serving its keys (read beforehand
+    /// with `read_keys`) if it's selected.
+    fn add_card(&mut self, reader: &str, token: AgentToken, keys: Vec<CachedKey>) {
+        let guid = token.guid().clone();
+        let reader = reader.to_string();
+        let token = Arc::new(std::sync::Mutex::new(token));
+        self.cards.insert(guid.clone(), Card { reader, token, keys });
+        if self.primary.is_none() && self.selection.matches(&guid) {
+            self.set_primary(&guid);
+        }
+        if self.is_selected(&guid) {
+            self.serve(&guid);
+        }
+    }
+
+    fn remove_card(&mut self, guid: &Guid) {
+        self.cards.remove(guid);
+        self.keys.retain(|k| k.guid != *guid);
+        if self.primary.as_ref() != Some(guid) {
+            return;
+        }
+        // Fall back to another matching card, as the C agent does by
+        // searching for a card again when its card goes away
+        self.primary = None;
+        let next = self
+            .cards
+            .keys()
+            .find(|guid| self.selection.matches(guid))
+            .cloned();
+        if let Some(next) = next {
+            self.set_primary(&next);
+            self.serve(&next);
+        }
+    }
+
+    /// Make `guid` the primary card, forgetting any PIN given for
+    /// another card.
+    fn set_primary(&mut self, guid: &Guid) {
+        if self.pin_card.as_ref().is_some_and(|card| card != guid) && self.pin.take().is_some() {
+            tracing::info!(guid = %guid.short_id(), "card changed, forgetting PIN");
+        }
+        self.primary = Some(guid.clone());
+        self.pin_card = Some(guid.clone());
+    }
+
+    /// Serve the keys of a connected card, honouring the slot filter.
+    fn serve(&mut self, guid: &Guid) {
+        let Some(card) = self.cards.get(guid) else {
+            return;
+        };
+        let keys: Vec<CachedKey> = card
+            .keys
+            .iter()
+            .filter(|k| {
+                self.allowed_slots
+                    .as_ref()
+                    .is_none_or(|allowed| allowed.contains(&k.slot_id))
+            })
+            .cloned()
+            .collect();
+        self.keys.retain(|k| k.guid != *guid);
+        self.keys.extend(keys);
+    }
+
+    /// Drop the cards in `reader`.
+    fn remove_reader(&mut self, reader: &str) {
+        let gone: Vec<Guid> = self
+            .cards
+            .iter()
+            .filter(|(_, card)| card.reader == reader)
+            .map(|(guid, _)| guid.clone())
+            .collect();
+        for guid in gone {
+            tracing::info!(reader, guid = %guid.short_id(), "card removed");
+            self.remove_card(&guid);
+        }
+    }
+}
+
+/// Read every key off a card. Done before taking the state lock, as it
+/// talks to the card.
+fn read_keys(reader: &str, token: &mut AgentToken) -> Vec<CachedKey> {
+    let guid = token.guid().clone();
+    let slots = token.read_all_slots().unwrap_or_else(|e| {
+        tracing::warn!(reader, "failed to read slots: {e}");
+        Vec::new()
+    });
+    slots
+        .iter()
+        .map(|slot| CachedKey {
+            guid: guid.clone(),
+            reader_name: reader.to_string(),
+            slot_id: slot.id(),
+            algorithm: slot.algorithm(),
+            public_key: slot.public_key().key_data().clone(),
+            cert_der: slot.cert_der().to_vec(),
+            comment: format!("PIV_slot_{:02X} {}", slot.id(), guid.short_id()),
+        })
+        .collect()
+}
+
+/// The cards the agent holds connections to and the keys it serves from
+/// them. Cards stay connected between requests; a background monitor
+/// follows them being inserted and removed, so the keys on offer are
+/// always those of the selected cards present.
+#[derive(Clone)]
+pub struct CardManager {
+    state: Arc<Mutex<CardState>>,
+}
+
+impl CardManager {
+    pub fn new(selection: CardSelection, allowed_slots: Option<Vec<u8>>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CardState {
+                cards: HashMap::new(),
+                keys: Vec::new(),
+                selection,
+                primary: None,
+                allowed_slots,
+                pin: None,
+                pin_card: None,
+            })),
+        }
+    }
+
+    /// Connect to every PIV card currently present, in reader order.
+    /// Silently skips readers that don't have PIV cards.
+    pub async fn connect_present(&self) -> Result<(), PivError> {
+        let mut cards = Vec::new();
+        #[cfg(feature = "emulator")]
+        if let Some(reader) = crate::emulator::reader() {
+            if let Ok(token) = open(reader.name()) {
+                cards.push((reader.name().to_string(), token));
+            }
+        }
+        if !emulated() {
+            let ctx = PivContext::new()?;
+            for reader in ctx.list_readers()? {
+                if let Ok(token) = connect(&ctx, &reader) {
+                    cards.push((reader, token));
+                }
+            }
+        }
+        for (reader, token) in cards {
+            self.add_card(&reader, token).await?;
+        }
+        Ok(())
+    }
+
+    /// Take a newly connected card, serving its keys if it's selected.
+    pub async fn add_card(&self, reader: &str, mut token: AgentToken) -> Result<(), PivError> {
+        let reader = reader.to_string();
+        let (reader, token, keys) = tokio::task::spawn_blocking(move || {
+            let keys = read_keys(&reader, &mut token);
+            (reader, token, keys)
+        })
+        .await
+        .map_err(|e| PivError::Other(format!("card task failed: {e}")))?;
+        self.state.lock().await.add_card(&reader, token, keys);
+        Ok(())
+    }
+
+    /// Forget the cards in a reader, once they've been pulled out.
+    pub async fn remove_reader(&self, reader: &str) {
+        self.state.lock().await.remove_reader(reader);
+    }
+
+    pub async fn keys(&self) -> Vec<CachedKey> {
+        self.state.lock().await.keys.clone()
+    }
+
+    /// The PIN given for the primary card, if any.
+    pub async fn pin(&self) -> Option<String> {
+        self.state.lock().await.pin.clone()
+    }
+
+    /// Remember (or with `None`, forget) the PIN for the primary card.
+    pub async fn set_pin(&self, pin: Option<String>) {
+        let mut state = self.state.lock().await;
+        state.pin_card = state.primary.clone();
+        state.pin = pin;
+    }
+
+    /// Run `f` against a card's connected token on a blocking thread,
+    /// holding only that card's lock. If the card was reset or pulled and
+    /// reinserted since the last use, reconnect and run it once more.
+    pub async fn with_token<R: Send + 'static>(
+        &self,
+        guid: &Guid,
+        mut f: impl FnMut(&mut AgentToken) -> Result<R, PivError> + Send + 'static,
+    ) -> Result<R, PivError> {
+        let (reader, token) = {
+            let state = self.state.lock().await;
+            let card = state.cards.get(guid).ok_or(PivError::CardNotFound)?;
+            (card.reader.clone(), card.token.clone())
         };
+        let expected = guid.clone();
+        let (result, gone) = tokio::task::spawn_blocking(move || {
+            let mut token = token.lock().unwrap_or_else(PoisonError::into_inner);
+            match f(&mut token) {
+                Err(e) if e.is_disconnect() => {
+                    tracing::debug!(reader, "lost card connection ({e}), reconnecting");
+                    match open(&reader) {
+                        Ok(new) if *new.guid() == expected => {
+                            *token = new;
+                            (f(&mut token), false)
+                        }
+                        _ => (Err(PivError::CardNotFound), true),
+                    }
+                }
+                r => (r, false),
+            }
+        })
+        .await
+        .map_err(|e| PivError::Other(format!("card task failed: {e}")))?;
+        if gone {
+            self.state.lock().await.remove_card(guid);
+        }
+        result
+    }
 
-        if card_present {
-            failures = 0;
-        } else {
-            failures += 1;
-            if failures >= PROBE_FAIL_LIMIT {
-                let mut pin_guard = pin.lock().await;
-                if pin_guard.is_some() {
-                    tracing::warn!("card unavailable after {} probes, forgetting PIN", failures);
-                    *pin_guard = None;
+    /// Start the background task following card insertion and removal.
+    /// It forgets the cached PIN once the primary card has been gone for
+    /// a while.
+    pub fn spawn_monitor(&self) {
+        if emulated() {
+            return;
+        }
+        let cards = self.clone();
+        tokio::task::spawn_blocking(move || cards.monitor());
+    }
+
+    fn monitor(&self) {
+        let mut gone_since: Option<Instant> = None;
+        loop {
+            let mut monitor = match ReaderMonitor::new() {
+                Ok(monitor) => monitor,
+                Err(e) => {
+                    tracing::debug!("card monitor: PCSC not available: {e}");
+                    std::thread::sleep(PCSC_RETRY_DELAY);
+                    continue;
+                }
+            };
+            loop {
+                let events = match monitor.wait(MONITOR_TIMEOUT) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        tracing::warn!("card monitor: {e}");
+                        break;
+                    }
+                };
+                for event in events {
+                    match event {
+                        ReaderEvent::Inserted(reader) => {
+                            self.card_inserted(monitor.context(), &reader)
+                        }
+                        ReaderEvent::Removed(reader) => self.card_removed(&reader),
+                    }
                 }
+
+                let mut state = self.state.blocking_lock();
+                let Some(pin_card) = &state.pin_card else {
+                    continue;
+                };
+                if state.cards.contains_key(pin_card) {
+                    gone_since = None;
+                    continue;
+                }
+                let gone = gone_since.get_or_insert_with(Instant::now);
+                if gone.elapsed() >= PIN_FORGET_DELAY && state.pin.take().is_some() {
+                    tracing::warn!("card unavailable, forgetting PIN");
+                }
+            }
+            std::thread::sleep(PCSC_RETRY_DELAY);
+        }
+    }
+
+    fn card_inserted(&self, ctx: &PivContext, reader: &str) {
+        let connected = |state: &CardState| state.cards.values().any(|c| c.reader == reader);
+        if connected(&self.state.blocking_lock()) {
+            return; // Connected at startup
+        }
+        let mut token = match connect(ctx, reader) {
+            Ok(token) => token,
+            Err(e) => {
+                tracing::debug!(reader, "ignoring card: {e}");
+                return;
             }
+        };
+        let keys = read_keys(reader, &mut token);
+
+        let mut state = self.state.blocking_lock();
+        if connected(&state) {
+            return;
         }
+        tracing::info!(reader, guid = %token.guid().short_id(), "card inserted");
+        state.add_card(reader, token, keys);
+    }
+
+    fn card_removed(&self, reader: &str) {
+        self.state.blocking_lock().remove_reader(reader);
+    }
+}
+
+/// Whether the agent is serving the emulated card instead of PC/SC.
+fn emulated() -> bool {
+    #[cfg(feature = "emulator")]
+    if crate::emulator::reader().is_some() {
+        return true;
+    }
+    false
+}
+
+fn connect(ctx: &PivContext, reader: &str) -> Result<AgentToken, PivError> {
+    let transport = PcscTransport::connect(ctx, reader)?;
+    PivToken::open(Box::new(transport) as Box<dyn CardTransport + Send + Sync>)
+}
+
+/// Open a fresh connection to the card in `reader`.
+fn open(reader: &str) -> Result<AgentToken, PivError> {
+    #[cfg(feature = "emulator")]
+    if let Some(emulated) = crate::emulator::reader() {
+        let transport = emulated.connect()?;
+        return PivToken::open(Box::new(transport) as Box<dyn CardTransport + Send + Sync>);
     }
+    connect(&PivContext::new()?, reader)
 }
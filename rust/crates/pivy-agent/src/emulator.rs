@@ -0,0 +1,30 @@
+use std::sync::OnceLock;
+
+use pivy_piv::apdu::alg;
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+
+/// Environment variable that switches the agent to the emulated card. Its
+/// value may be a 32-digit hex GUID to give the card a fixed identity.
+const EMULATOR_ENV: &str = "PIVY_AGENT_EMULATOR";
+
+static READER: OnceLock<Option<VirtualReader>> = OnceLock::new();
+
+/// The emulated reader, if PIVY_AGENT_EMULATOR is set. The card carries
+/// P-256 keys in 9A and 9E and the default PIN (123456).
+pub fn reader() -> Option<&'static VirtualReader> {
+    READER
+        .get_or_init(|| {
+            let value = std::env::var(EMULATOR_ENV).ok()?;
+            let mut card = VirtualCard::new();
+            if let Ok(guid) = hex::decode(&value) {
+                if let Ok(guid) = guid.try_into() {
+                    card = card.with_guid(guid);
+                }
+            }
+            card.generate_with_cert(0x9A, alg::ECCP256);
+            card.generate_with_cert(0x9E, alg::ECCP256);
+            tracing::warn!("using emulated PIV card (testing only)");
+            Some(VirtualReader::with_card("Virtual PIV Reader 00", card))
+        })
+        .as_ref()
+}
@@ -0,0 +1,7 @@
+//! PIV-backed SSH agent: serves the keys on the PIV cards present over the
+//! SSH agent protocol, along with pivy's agent extensions.
+
+pub mod agent;
+pub mod card;
+#[cfg(feature = "emulator")]
+pub mod emulator;
@@ -2,10 +2,8 @@ use clap::Parser;
 use ssh_agent_lib::agent::listen;
 use tokio::net::UnixListener;
 
-mod agent;
-mod card;
-
-use agent::{CachedKey, PivyAgent};
+use pivy_agent::agent::PivyAgent;
+use pivy_agent::card::{CardManager, CardSelection};
 
 #[derive(Parser, Debug)]
 #[command(name = "pivy-agent", about = "PIV-backed SSH agent")]
@@ -81,60 +79,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .collect()
     });
 
-    // Enumerate PIV tokens and cache their keys.
-    // If PCSC is unavailable (e.g. no pcscd), start with zero keys.
-    let (tokens, ctx_available) = match pivy_piv::PivContext::new() {
-        Ok(ctx) => match ctx.enumerate_tokens() {
-            Ok(tokens) => (tokens, true),
-            Err(e) => {
-                tracing::warn!("Failed to enumerate PIV tokens: {e}");
-                (Vec::new(), false)
-            }
-        },
-        Err(e) => {
-            tracing::warn!("PCSC not available: {e}");
-            (Vec::new(), false)
-        }
+    // Connect to the PIV tokens present and cache their keys. Cards
+    // inserted later are picked up by the monitor below, so if PCSC is
+    // unavailable (e.g. no pcscd) just start with zero keys.
+    let selection = CardSelection {
+        guid: cli.guid.clone(),
+        all_cards: cli.all_cards,
     };
-
-    let mut cached_keys = Vec::new();
-    let mut primary_guid = None;
-    for token in &tokens {
-        let guid = token.guid().clone();
-
-        if let Some(ref filter_guid) = cli.guid {
-            if guid.to_hex() != *filter_guid && guid.short_id() != *filter_guid {
-                continue;
-            }
-        }
-
-        if primary_guid.is_none() {
-            primary_guid = Some(guid.clone());
-        }
-
-        let slots = token.read_all_slots().unwrap_or_default();
-        for slot in &slots {
-            // Filter by slot spec
-            if let Some(ref allowed) = allowed_slots {
-                if !allowed.contains(&slot.id()) {
-                    continue;
-                }
-            }
-
-            cached_keys.push(CachedKey {
-                guid: guid.clone(),
-                reader_name: token.reader_name().to_string(),
-                slot_id: slot.id(),
-                algorithm: slot.algorithm(),
-                public_key: slot.public_key().key_data().clone(),
-                comment: format!("PIV_slot_{:02X} {}", slot.id(), guid.short_id()),
-            });
-        }
-
-        if !cli.all_cards {
-            break;
-        }
+    let cards = CardManager::new(selection, allowed_slots);
+    if let Err(e) = cards.connect_present().await {
+        tracing::warn!("PCSC not available: {e}");
     }
+    let cached_keys = cards.keys().await;
 
     // Handle -i (info mode)
     if cli.info {
@@ -165,7 +121,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Detect shell output format
     let use_csh = cli.csh_format
-        || (!cli.sh_format && std::env::var("SHELL").map_or(false, |s| s.ends_with("csh")));
+        || (!cli.sh_format && std::env::var("SHELL").is_ok_and(|s| s.ends_with("csh")));
 
     if use_csh {
         println!("setenv SSH_AUTH_SOCK {};", socket_path);
@@ -181,13 +137,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let listener = UnixListener::bind(&socket_path)?;
-    let agent = PivyAgent::new(cached_keys);
+    let agent = PivyAgent::new(cards.clone());
 
-    // Spawn card probe loop if we have a primary card
-    if let Some(guid) = primary_guid {
-        let pin_handle = agent.pin_handle();
-        tokio::spawn(card::probe_loop(guid, pin_handle));
-    }
+    // Follow cards coming and going
+    cards.spawn_monitor();
 
     // If a command was given, run it with the agent env, then exit
     if !cli.command.is_empty() {
@@ -0,0 +1,257 @@
+use openssl::derive::Deriver;
+use openssl::nid::Nid;
+use pivy_agent::agent::{CachedKey, PivyAgent};
+use pivy_agent::card::{CardManager, CardSelection};
+use pivy_piv::apdu::alg;
+use pivy_piv::cert::pkey_from_ssh_public;
+use pivy_piv::slot::slot_to_cert_tag;
+use pivy_piv::{EcdhBox, Guid};
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::Extension;
+use ssh_agent_lib::ssh_encoding::{Decode, Encode};
+use ssh_key::public::{Ed25519PublicKey, KeyData};
+
+mod common;
+use common::{ec_key, ssh_public, token};
+
+/// An agent serving the keys on `card`, along with the keys and the
+/// reader the card sits in.
+async fn agent(card: VirtualCard) -> (VirtualReader, PivyAgent, Vec<CachedKey>) {
+    let reader = VirtualReader::with_card("Virtual Reader", card);
+    let selection = CardSelection {
+        guid: None,
+        all_cards: false,
+    };
+    let cards = CardManager::new(selection, None);
+    cards.add_card(reader.name(), token(&reader)).await.unwrap();
+    let keys = cards.keys().await;
+    (reader, PivyAgent::new(cards), keys)
+}
+
+fn slot_key(keys: &[CachedKey], slot_id: u8) -> &CachedKey {
+    keys.iter().find(|k| k.slot_id == slot_id).unwrap()
+}
+
+fn encoded(value: &impl Encode) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode(&mut out).unwrap();
+    out
+}
+
+/// An ecdh@joyent.com request: the keys and flags wrapped in a string.
+fn ecdh_request(key: &KeyData, partner: &KeyData, flags: u32) -> Extension {
+    let mut inner = Vec::new();
+    encoded(key).encode(&mut inner).unwrap();
+    encoded(partner).encode(&mut inner).unwrap();
+    flags.encode(&mut inner).unwrap();
+    Extension {
+        name: "ecdh@joyent.com".into(),
+        details: encoded(&inner).into(),
+    }
+}
+
+/// An ecdh-rebox@joyent.com request, asking for the box to be resealed to
+/// `partner` and tagged with `guid` (if not empty) and `slot_id`.
+fn rebox_request(sealed: &EcdhBox, guid: &[u8], slot_id: u8, partner: &KeyData) -> Extension {
+    let mut inner = Vec::new();
+    sealed.encode().encode(&mut inner).unwrap();
+    guid.encode(&mut inner).unwrap();
+    slot_id.encode(&mut inner).unwrap();
+    encoded(partner).encode(&mut inner).unwrap();
+    0u32.encode(&mut inner).unwrap();
+    Extension {
+        name: "ecdh-rebox@joyent.com".into(),
+        details: encoded(&inner).into(),
+    }
+}
+
+/// An x509-certs@joyent.com request: the key and flags, not wrapped.
+fn x509_certs_request(key: &KeyData, flags: u32) -> Extension {
+    let mut details = Vec::new();
+    encoded(key).encode(&mut details).unwrap();
+    flags.encode(&mut details).unwrap();
+    Extension {
+        name: "x509-certs@joyent.com".into(),
+        details: details.into(),
+    }
+}
+
+fn ecdh_card() -> VirtualCard {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9A, alg::ECCP256);
+    card.generate_with_cert(0x9D, alg::ECCP256);
+    card
+}
+
+#[tokio::test]
+async fn ecdh() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+
+    for slot_id in [0x9A, 0x9D] {
+        let key = slot_key(&keys, slot_id);
+        let partner = ec_key(Nid::X9_62_PRIME256V1);
+        let partner_public = ssh_public(&partner);
+        let response = agent
+            .extension(ecdh_request(&key.public_key, &partner_public, 0))
+            .await
+            .unwrap()
+            .unwrap();
+        let secret = Vec::<u8>::decode(&mut response.details.as_ref()).unwrap();
+
+        let card_public = pkey_from_ssh_public(&key.public_key).unwrap();
+        let mut deriver = Deriver::new(&partner).unwrap();
+        deriver.set_peer(&card_public).unwrap();
+        assert_eq!(secret, deriver.derive_to_vec().unwrap());
+    }
+}
+
+#[tokio::test]
+async fn ecdh_rejects_flags() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let request = ecdh_request(&slot_key(&keys, 0x9D).public_key, &partner, 1);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn ecdh_needs_ec_partner() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let partner = KeyData::Ed25519(Ed25519PublicKey([7; 32]));
+    let request = ecdh_request(&slot_key(&keys, 0x9D).public_key, &partner, 0);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn ecdh_needs_pin() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let request = ecdh_request(&slot_key(&keys, 0x9D).public_key, &partner, 0);
+    assert!(matches!(
+        agent.extension(request.clone()).await,
+        Err(AgentError::ExtensionFailure)
+    ));
+    agent.unlock("123456".into()).await.unwrap();
+    assert!(agent.extension(request).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn ecdh_unknown_key() {
+    let (_reader, mut agent, _) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let other = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let request = ecdh_request(&other, &partner, 0);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn ecdh_truncated_request() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let mut request = ecdh_request(&slot_key(&keys, 0x9D).public_key, &partner, 0);
+    let details = request.details.as_ref();
+    request.details = details[..details.len() - 2].to_vec().into();
+    assert!(agent.extension(request).await.is_err());
+}
+
+#[tokio::test]
+async fn rebox() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let key = slot_key(&keys, 0x9D);
+    let mut sealed = EcdhBox::seal(&key.public_key, b"reboxed secret").unwrap();
+    sealed.set_guid_slot(key.guid.clone(), 0x9D);
+
+    let partner = ec_key(Nid::X9_62_PRIME256V1);
+    let partner_public = ssh_public(&partner);
+    let new_guid = [0x11; 16];
+    let request = rebox_request(&sealed, &new_guid, 0x9C, &partner_public);
+    let response = agent.extension(request).await.unwrap().unwrap();
+    let encoded = Vec::<u8>::decode(&mut response.details.as_ref()).unwrap();
+
+    let reboxed = EcdhBox::decode(&encoded).unwrap();
+    assert_eq!(reboxed.recipient(), partner_public);
+    assert_eq!(reboxed.guid(), Some(&Guid::from_bytes(&new_guid).unwrap()));
+    assert_eq!(reboxed.slot(), Some(0x9C));
+    assert_eq!(reboxed.open_offline(&partner).unwrap().as_slice(), b"reboxed secret");
+}
+
+#[tokio::test]
+async fn rebox_without_guid() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let sealed = EcdhBox::seal(&slot_key(&keys, 0x9A).public_key, b"secret").unwrap();
+
+    let partner = ec_key(Nid::X9_62_PRIME256V1);
+    let partner_public = ssh_public(&partner);
+    let request = rebox_request(&sealed, &[], 0, &partner_public);
+    let response = agent.extension(request).await.unwrap().unwrap();
+    let encoded = Vec::<u8>::decode(&mut response.details.as_ref()).unwrap();
+
+    let reboxed = EcdhBox::decode(&encoded).unwrap();
+    assert!(reboxed.guid().is_none());
+    assert_eq!(reboxed.open_offline(&partner).unwrap().as_slice(), b"secret");
+}
+
+#[tokio::test]
+async fn rebox_needs_key_here() {
+    let (_reader, mut agent, _) = agent(ecdh_card()).await;
+    agent.unlock("123456".into()).await.unwrap();
+    let elsewhere = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let sealed = EcdhBox::seal(&elsewhere, b"secret").unwrap();
+
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let request = rebox_request(&sealed, &[], 0, &partner);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn rebox_needs_pin() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    let sealed = EcdhBox::seal(&slot_key(&keys, 0x9D).public_key, b"secret").unwrap();
+
+    let partner = ssh_public(&ec_key(Nid::X9_62_PRIME256V1));
+    let request = rebox_request(&sealed, &[], 0, &partner);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn x509_certs() {
+    let mut card = VirtualCard::new();
+    let cert_9a = card.generate_with_cert(0x9A, alg::ECCP256);
+    let cert_9e = card.generate_with_cert(0x9E, alg::ECCP384);
+    let (_reader, mut agent, keys) = agent(card).await;
+
+    for (slot_id, cert) in [(0x9A, cert_9a), (0x9E, cert_9e)] {
+        let request = x509_certs_request(&slot_key(&keys, slot_id).public_key, 0);
+        let response = agent.extension(request).await.unwrap().unwrap();
+        let der = Vec::<u8>::decode(&mut response.details.as_ref()).unwrap();
+        assert_eq!(der, cert);
+    }
+}
+
+#[tokio::test]
+async fn x509_certs_without_cert() {
+    // A key with its certificate deleted, found through GET METADATA
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9D, alg::ECCP256);
+    card.remove_object(slot_to_cert_tag(0x9D).unwrap());
+    let (_reader, mut agent, keys) = agent(card).await;
+
+    let key = slot_key(&keys, 0x9D);
+    assert!(key.cert_der.is_empty());
+    let request = x509_certs_request(&key.public_key, 0);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
+
+#[tokio::test]
+async fn x509_certs_rejects_flags() {
+    let (_reader, mut agent, keys) = agent(ecdh_card()).await;
+    let request = x509_certs_request(&slot_key(&keys, 0x9A).public_key, 1);
+    assert!(matches!(agent.extension(request).await, Err(AgentError::ExtensionFailure)));
+}
@@ -0,0 +1,89 @@
+use pivy_agent::card::{CardManager, CardSelection};
+use pivy_piv::apdu::alg;
+use pivy_piv::Guid;
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+
+mod common;
+use common::token;
+
+/// A card with keys in 9A and 9E and every GUID byte set to `id`.
+fn reader(name: &str, id: u8) -> VirtualReader {
+    let mut card = VirtualCard::new().with_guid([id; 16]);
+    card.generate_with_cert(0x9A, alg::ECCP256);
+    card.generate_with_cert(0x9E, alg::ECCP256);
+    VirtualReader::with_card(name, card)
+}
+
+fn manager(guid: Option<&str>) -> CardManager {
+    let selection = CardSelection {
+        guid: guid.map(str::to_string),
+        all_cards: false,
+    };
+    CardManager::new(selection, None)
+}
+
+/// The distinct cards whose keys are being served.
+async fn served(cards: &CardManager) -> Vec<Guid> {
+    let mut guids: Vec<Guid> = cards.keys().await.into_iter().map(|k| k.guid).collect();
+    guids.dedup();
+    guids
+}
+
+fn guid(id: u8) -> Guid {
+    Guid::from_bytes(&[id; 16]).unwrap()
+}
+
+#[tokio::test]
+async fn serves_card_inserted_after_first_is_removed() {
+    let (a, b) = (reader("Reader A", 0xAA), reader("Reader B", 0xBB));
+    let cards = manager(None);
+    cards.add_card("Reader A", token(&a)).await.unwrap();
+    cards.set_pin(Some("123456".into())).await;
+    assert_eq!(served(&cards).await, vec![guid(0xAA)]);
+
+    cards.remove_reader("Reader A").await;
+    assert!(cards.keys().await.is_empty());
+
+    cards.add_card("Reader B", token(&b)).await.unwrap();
+    assert_eq!(served(&cards).await, vec![guid(0xBB)]);
+    // The PIN was for card A
+    assert_eq!(cards.pin().await, None);
+}
+
+#[tokio::test]
+async fn present_card_takes_over_from_removed_one() {
+    let (a, b) = (reader("Reader A", 0xAA), reader("Reader B", 0xBB));
+    let cards = manager(None);
+    cards.add_card("Reader A", token(&a)).await.unwrap();
+    cards.add_card("Reader B", token(&b)).await.unwrap();
+    assert_eq!(served(&cards).await, vec![guid(0xAA)]);
+
+    cards.remove_reader("Reader A").await;
+    assert_eq!(served(&cards).await, vec![guid(0xBB)]);
+}
+
+#[tokio::test]
+async fn pin_kept_when_same_card_returns() {
+    let a = reader("Reader A", 0xAA);
+    let cards = manager(None);
+    cards.add_card("Reader A", token(&a)).await.unwrap();
+    cards.set_pin(Some("123456".into())).await;
+
+    cards.remove_reader("Reader A").await;
+    cards.add_card("Reader A", token(&a)).await.unwrap();
+    assert_eq!(served(&cards).await, vec![guid(0xAA)]);
+    assert_eq!(cards.pin().await.as_deref(), Some("123456"));
+}
+
+#[tokio::test]
+async fn guid_selection_ignores_other_cards() {
+    let (a, b) = (reader("Reader A", 0xAA), reader("Reader B", 0xBB));
+    let cards = manager(Some(&guid(0xAA).to_hex()));
+    cards.add_card("Reader B", token(&b)).await.unwrap();
+    assert!(cards.keys().await.is_empty());
+
+    cards.add_card("Reader A", token(&a)).await.unwrap();
+    assert_eq!(served(&cards).await, vec![guid(0xAA)]);
+    cards.remove_reader("Reader A").await;
+    assert!(cards.keys().await.is_empty());
+}
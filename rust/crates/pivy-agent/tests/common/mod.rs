@@ -0,0 +1,36 @@
+//! Fixtures shared by the integration tests.
+
+// Each test crate uses only some of these
+#![allow(dead_code)]
+
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use pivy_agent::card::AgentToken;
+use pivy_piv::{CardTransport, PivToken};
+use pivy_piv_emu::VirtualReader;
+use ssh_key::public::{EcdsaPublicKey, KeyData};
+
+/// Open a token on the card in `reader`, boxed the way the agent keeps it.
+pub fn token(reader: &VirtualReader) -> AgentToken {
+    let transport = reader.connect().unwrap();
+    PivToken::open(Box::new(transport) as Box<dyn CardTransport + Send + Sync>).unwrap()
+}
+
+/// A fresh EC key pair on the curve `nid`.
+pub fn ec_key(nid: Nid) -> PKey<Private> {
+    let group = EcGroup::from_curve_name(nid).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+/// The public half of an EC key, as SSH sees it.
+pub fn ssh_public(key: &PKey<Private>) -> KeyData {
+    let ec = key.ec_key().unwrap();
+    let mut ctx = BigNumContext::new().unwrap();
+    let point = ec
+        .public_key()
+        .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+        .unwrap();
+    KeyData::Ecdsa(EcdsaPublicKey::from_sec1_bytes(&point).unwrap())
+}
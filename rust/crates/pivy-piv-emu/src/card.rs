@@ -0,0 +1,1068 @@
+use std::collections::HashMap;
+
+use openssl::pkey::{PKey, Private};
+use openssl::symm::Mode;
+use openssl::x509::X509;
+use zeroize::Zeroizing;
+
+use pivy_piv::apdu::{alg, cla, ins, sw, yk_ins, PIV_AID, YKPIV_AID};
+use pivy_piv::tlv::{TlvReader, TlvWriter};
+
+use crate::crypto;
+
+/// YubiKey PIN policy values (tag 0xAA)
+pub mod pin_policy {
+    pub const DEFAULT: u8 = 0x00;
+    pub const NEVER: u8 = 0x01;
+    pub const ONCE: u8 = 0x02;
+    pub const ALWAYS: u8 = 0x03;
+}
+
+/// YubiKey touch policy values (tag 0xAB)
+pub mod touch_policy {
+    pub const DEFAULT: u8 = 0x00;
+    pub const NEVER: u8 = 0x01;
+}
+
+/// Default YubiKey card management key (3DES)
+pub const DEFAULT_ADMIN_KEY: [u8; 24] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+];
+
+pub const DEFAULT_PIN: &str = "123456";
+pub const DEFAULT_PUK: &str = "12345678";
+
+const DEFAULT_PIN_RETRIES: u8 = 3;
+const DEFAULT_PUK_RETRIES: u8 = 3;
+
+const GLOBAL_PIN_REF: u8 = 0x00;
+const PIV_PIN_REF: u8 = 0x80;
+const PIV_PUK_REF: u8 = 0x81;
+const ADMIN_SLOT: u8 = 0x9B;
+const ATTESTATION_SLOT: u8 = 0xF9;
+
+/// Form factor reported in attestations (USB-A keychain)
+const FORM_FACTOR: u8 = 0x01;
+
+/// Data object tags used when building a default card
+const TAG_CHUID: u32 = 0x5FC102;
+const TAG_DISCOVERY: u32 = 0x7E;
+const TAG_BITGT: u32 = 0x7F61;
+const TAG_ATTESTATION_CERT: u32 = 0x5FFF01;
+
+/// Objects only readable after PIN verification (SP 800-73-4 table 3):
+/// fingerprints, facial image, printed information and iris images
+const PIN_PROTECTED_OBJECTS: &[u32] = &[0x5FC103, 0x5FC108, 0x5FC109, 0x5FC121];
+
+/// FASC-N used by unprovisioned cards ("nobody"), as written by pivy-tool
+const DEFAULT_FASCN: [u8; 25] = [
+    0xD4, 0xE7, 0x39, 0xDA, 0x73, 0x9C, 0xED, 0x39, 0xCE, 0x73, 0x9D, 0x83, 0x68, 0x58, 0x21,
+    0x08, 0x42, 0x10, 0x84, 0x21, 0xC8, 0x42, 0x10, 0xC3, 0xEB,
+];
+
+/// A key held in one of the emulated slots.
+pub struct SlotKey {
+    pub algorithm: u8,
+    pub pin_policy: u8,
+    pub touch_policy: u8,
+    /// true if generated on-card, false if imported
+    pub generated: bool,
+    pub(crate) pkey: PKey<Private>,
+}
+
+impl SlotKey {
+    /// DER SubjectPublicKeyInfo of the key, for comparing with what the
+    /// host saw.
+    pub fn public_key_der(&self) -> Vec<u8> {
+        self.pkey.public_key_to_der().expect("encoding public key")
+    }
+}
+
+struct PinCounter {
+    value: Zeroizing<[u8; 8]>,
+    retries: u8,
+    max_retries: u8,
+}
+
+impl PinCounter {
+    fn new(pin: &str, max_retries: u8) -> Self {
+        Self {
+            value: Zeroizing::new(pad_pin(pin.as_bytes())),
+            retries: max_retries,
+            max_retries,
+        }
+    }
+
+    fn is_blocked(&self) -> bool {
+        self.retries == 0
+    }
+
+    /// Check a candidate value, updating the retry counter. Returns the SW
+    /// to report on failure.
+    fn check(&mut self, candidate: &[u8]) -> Result<(), u16> {
+        if self.is_blocked() {
+            return Err(sw::AUTH_METHOD_BLOCKED);
+        }
+        if candidate != self.value.as_slice() {
+            self.retries -= 1;
+            return Err(self.retries_sw());
+        }
+        self.retries = self.max_retries;
+        Ok(())
+    }
+
+    fn retries_sw(&self) -> u16 {
+        if self.is_blocked() {
+            sw::AUTH_METHOD_BLOCKED
+        } else {
+            0x63C0 | self.retries as u16
+        }
+    }
+}
+
+fn pad_pin(pin: &[u8]) -> [u8; 8] {
+    let mut padded = [0xFF_u8; 8];
+    let len = pin.len().min(8);
+    padded[..len].copy_from_slice(&pin[..len]);
+    padded
+}
+
+/// Length of a padded PIN/PUK with the 0xFF filler removed
+fn unpadded_len(value: &[u8]) -> usize {
+    value.iter().position(|&b| b == 0xFF).unwrap_or(value.len())
+}
+
+/// A parsed command APDU
+struct Command {
+    cla: u8,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: Vec<u8>,
+    extended: bool,
+}
+
+impl Command {
+    fn parse(cmd: &[u8]) -> Result<Self, u16> {
+        if cmd.len() < 4 {
+            return Err(sw::WRONG_LENGTH);
+        }
+        let (cla, ins, p1, p2) = (cmd[0], cmd[1], cmd[2], cmd[3]);
+        let body = &cmd[4..];
+        let (data, extended) = match body.len() {
+            // Case 1, or case 2 short (Le only)
+            0 | 1 => (Vec::new(), false),
+            // Case 2 extended: 00 Le1 Le2
+            3 if body[0] == 0 => (Vec::new(), true),
+            _ if body[0] == 0 && body.len() >= 3 => {
+                let lc = (body[1] as usize) << 8 | body[2] as usize;
+                if body.len() != 3 + lc && body.len() != 3 + lc + 2 {
+                    return Err(sw::WRONG_LENGTH);
+                }
+                (body[3..3 + lc].to_vec(), true)
+            }
+            _ => {
+                let lc = body[0] as usize;
+                if body.len() != 1 + lc && body.len() != 1 + lc + 1 {
+                    return Err(sw::WRONG_LENGTH);
+                }
+                (body[1..1 + lc].to_vec(), false)
+            }
+        };
+        Ok(Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data,
+            extended,
+        })
+    }
+}
+
+/// An emulated YubiKey-style PIV applet.
+///
+/// Holds keys, data objects, PIN/PUK retry counters and the 9B management
+/// key in memory and answers raw command APDUs through [`VirtualCard::process`].
+pub struct VirtualCard {
+    guid: [u8; 16],
+    version: [u8; 3],
+    serial: u32,
+    /// Data objects, stored as the full TLV returned by GET DATA
+    objects: HashMap<u32, Vec<u8>>,
+    keys: HashMap<u8, SlotKey>,
+    pin: PinCounter,
+    puk: PinCounter,
+    /// Card-wide Global PIN (reference 00), if the card has one
+    global_pin: Option<PinCounter>,
+    /// The Discovery object names the Global PIN as the primary PIN
+    global_pin_primary: bool,
+    admin_alg: u8,
+    admin_key: Zeroizing<Vec<u8>>,
+    admin_touch: bool,
+    /// Stand-in for the Yubico root CA and this card's F9 attestation key
+    attest_root: X509,
+    attest_key: PKey<Private>,
+    attest_cert: X509,
+
+    // Security status, cleared on reset
+    pin_verified: bool,
+    /// PIN verified by the immediately preceding command (PIN-always keys)
+    pin_fresh: bool,
+    admin_authed: bool,
+    admin_challenge: Option<Vec<u8>>,
+    admin_witness: Option<Vec<u8>>,
+
+    // Link state
+    applet: Applet,
+    chain: Option<(u8, u8, u8, Vec<u8>)>,
+    pending: Vec<u8>,
+}
+
+/// Applet currently selected on the card. Nothing is selected after power
+/// up or a reset, so hosts have to SELECT again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Applet {
+    None,
+    Piv,
+    YkPiv,
+}
+
+impl Default for VirtualCard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualCard {
+    /// A card in the state `pivy-tool init` leaves it in: default PIN,
+    /// PUK and management key, a CHUID with a random GUID and no keys.
+    pub fn new() -> Self {
+        let guid: [u8; 16] = crypto::random(16).try_into().unwrap();
+        let serial_bytes = crypto::random(4);
+        let serial = u32::from_be_bytes(serial_bytes.try_into().unwrap()) & 0x00FF_FFFF;
+        let root_key = crypto::generate(alg::ECCP256).expect("attestation root key");
+        let attest_root = crypto::ca_cert(&root_key, "Emulated PIV Root CA", None)
+            .expect("attestation root cert");
+        let attest_key = crypto::generate(alg::ECCP256).expect("attestation key");
+        let attest_cert = crypto::ca_cert(
+            &attest_key,
+            "Emulated PIV Attestation",
+            Some((&attest_root, &root_key)),
+        )
+        .expect("attestation cert");
+        let mut card = Self {
+            guid,
+            version: [5, 4, 3],
+            serial,
+            objects: HashMap::new(),
+            keys: HashMap::new(),
+            pin: PinCounter::new(DEFAULT_PIN, DEFAULT_PIN_RETRIES),
+            puk: PinCounter::new(DEFAULT_PUK, DEFAULT_PUK_RETRIES),
+            global_pin: None,
+            global_pin_primary: false,
+            admin_alg: alg::TDEA_3KEY,
+            admin_key: Zeroizing::new(DEFAULT_ADMIN_KEY.to_vec()),
+            admin_touch: false,
+            attest_root,
+            attest_key,
+            attest_cert,
+            pin_verified: false,
+            pin_fresh: false,
+            admin_authed: false,
+            admin_challenge: None,
+            admin_witness: None,
+            applet: Applet::None,
+            chain: None,
+            pending: Vec::new(),
+        };
+        card.write_default_objects();
+        let f9_der = card.attest_cert.to_der().expect("encoding attestation cert");
+        card.set_cert(ATTESTATION_SLOT, &f9_der);
+        card
+    }
+
+    fn write_default_objects(&mut self) {
+        let mut chuid = TlvWriter::new();
+        chuid.write_tag_value(0x30, &DEFAULT_FASCN);
+        chuid.write_tag_value(0x34, &self.guid);
+        chuid.write_tag_value(0x35, b"20991231");
+        chuid.write_tag_value(0x3E, &[]);
+        chuid.write_tag_value(0xFE, &[]);
+        self.set_object(TAG_CHUID, chuid.as_bytes());
+        self.write_discovery();
+    }
+
+    /// PIN Usage Policy: the PIV PIN, plus the Global PIN if the card has
+    /// one, with the primary PIN named when there are two.
+    fn write_discovery(&mut self) {
+        let policy = match (&self.global_pin, self.global_pin_primary) {
+            (None, _) => [0x40, 0x00],
+            (Some(_), false) => [0x60, 0x10],
+            (Some(_), true) => [0x60, 0x20],
+        };
+        let mut discovery = TlvWriter::new();
+        discovery.write_tag_value(0x4F, PIV_AID);
+        discovery.write_tag_value(0x5F2F, &policy);
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(TAG_DISCOVERY, discovery.as_bytes());
+        self.objects.insert(TAG_DISCOVERY, outer.into_vec());
+    }
+
+    pub fn with_guid(mut self, guid: [u8; 16]) -> Self {
+        self.guid = guid;
+        self.write_default_objects();
+        self
+    }
+
+    pub fn with_version(mut self, major: u8, minor: u8, patch: u8) -> Self {
+        self.version = [major, minor, patch];
+        self
+    }
+
+    pub fn with_serial(mut self, serial: u32) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    pub fn with_pin(mut self, pin: &str) -> Self {
+        self.pin = PinCounter::new(pin, self.pin.max_retries);
+        self
+    }
+
+    /// Give the card a Global PIN; verifying either it or the PIV PIN
+    /// satisfies key PIN policies.
+    pub fn with_global_pin(mut self, pin: &str) -> Self {
+        self.global_pin = Some(PinCounter::new(pin, DEFAULT_PIN_RETRIES));
+        self.write_discovery();
+        self
+    }
+
+    /// Name the Global PIN as the primary PIN in the Discovery object.
+    pub fn with_global_pin_primary(mut self) -> Self {
+        self.global_pin_primary = true;
+        self.write_discovery();
+        self
+    }
+
+    pub fn with_admin_key(mut self, alg_id: u8, key: &[u8]) -> Self {
+        self.admin_alg = alg_id;
+        self.admin_key = Zeroizing::new(key.to_vec());
+        self
+    }
+
+    pub fn guid(&self) -> &[u8; 16] {
+        &self.guid
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    pub fn pin_retries(&self) -> u8 {
+        self.pin.retries
+    }
+
+    pub fn puk_retries(&self) -> u8 {
+        self.puk.retries
+    }
+
+    /// DER certificate of the CA that issued this card's F9 attestation
+    /// certificate; plays the part of the Yubico PIV root.
+    pub fn attestation_root(&self) -> Vec<u8> {
+        self.attest_root.to_der().expect("encoding attestation root")
+    }
+
+    pub fn key(&self, slot: u8) -> Option<&SlotKey> {
+        self.keys.get(&slot)
+    }
+
+    /// Raw contents of a data object as GET DATA would return them.
+    pub fn object(&self, tag: u32) -> Option<&[u8]> {
+        self.objects.get(&tag).map(|v| v.as_slice())
+    }
+
+    /// Store `value` as the contents of a 0x53-wrapped data object.
+    pub fn set_object(&mut self, tag: u32, value: &[u8]) {
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x53, value);
+        self.objects.insert(tag, outer.into_vec());
+    }
+
+    pub fn remove_object(&mut self, tag: u32) {
+        self.objects.remove(&tag);
+    }
+
+    /// Generate a key in `slot` and write a self-signed certificate for it,
+    /// as a provisioning tool would. Returns the certificate DER.
+    pub fn generate_with_cert(&mut self, slot: u8, alg_id: u8) -> Vec<u8> {
+        let pkey = crypto::generate(alg_id).expect("unsupported algorithm");
+        let cert = crypto::self_signed_cert(alg_id, &pkey, &format!("Slot {:02X}", slot))
+            .expect("self-signed cert");
+        self.install_key(slot, alg_id, pkey, true);
+        self.set_cert(slot, &cert);
+        cert
+    }
+
+    /// Write `cert_der` into the certificate object for `slot`.
+    pub fn set_cert(&mut self, slot: u8, cert_der: &[u8]) {
+        let tag = pivy_piv::slot::slot_to_cert_tag(slot).expect("slot has no cert object");
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(0x70, cert_der);
+        inner.write_tag_value(0x71, &[0x00]);
+        inner.write_tag_value(0xFE, &[]);
+        self.set_object(tag, inner.as_bytes());
+    }
+
+    fn install_key(&mut self, slot: u8, alg_id: u8, pkey: PKey<Private>, generated: bool) {
+        self.keys.insert(
+            slot,
+            SlotKey {
+                algorithm: alg_id,
+                pin_policy: pin_policy::DEFAULT,
+                touch_policy: touch_policy::DEFAULT,
+                generated,
+                pkey,
+            },
+        );
+    }
+
+    /// Clear all security status, as a card reset does.
+    pub fn reset(&mut self) {
+        self.pin_verified = false;
+        self.pin_fresh = false;
+        self.admin_authed = false;
+        self.admin_challenge = None;
+        self.admin_witness = None;
+        self.applet = Applet::None;
+        self.chain = None;
+        self.pending.clear();
+    }
+
+    pub fn is_pin_verified(&self) -> bool {
+        self.pin_verified
+    }
+
+    pub fn is_admin_authenticated(&self) -> bool {
+        self.admin_authed
+    }
+
+    /// Process one command APDU and return the response (data + SW).
+    pub fn process(&mut self, cmd: &[u8]) -> Vec<u8> {
+        let command = match Command::parse(cmd) {
+            Ok(c) => c,
+            Err(status) => return status.to_be_bytes().to_vec(),
+        };
+
+        if command.ins == ins::CONTINUE {
+            return self.next_pending_chunk();
+        }
+        self.pending.clear();
+
+        if command.cla & !cla::CHAIN != cla::ISO {
+            return sw::CLA_NOT_SUPPORTED.to_be_bytes().to_vec();
+        }
+
+        // Accumulate ISO 7816-4 command chains
+        let mut command = command;
+        if let Some((c_ins, c_p1, c_p2, mut buf)) = self.chain.take() {
+            if (c_ins, c_p1, c_p2) != (command.ins, command.p1, command.p2) {
+                return sw::CONDITIONS_NOT_SATISFIED.to_be_bytes().to_vec();
+            }
+            buf.extend_from_slice(&command.data);
+            command.data = buf;
+        }
+        if command.cla & cla::CHAIN != 0 {
+            self.chain = Some((command.ins, command.p1, command.p2, command.data));
+            return sw::OK.to_be_bytes().to_vec();
+        }
+
+        let pin_fresh = std::mem::take(&mut self.pin_fresh);
+        let result = self.dispatch(&command, pin_fresh);
+        match result {
+            Ok(data) if command.extended || data.len() <= 256 => {
+                let mut resp = data;
+                resp.extend_from_slice(&sw::OK.to_be_bytes());
+                resp
+            }
+            Ok(data) => {
+                self.pending = data;
+                self.next_pending_chunk()
+            }
+            Err(status) => status.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn next_pending_chunk(&mut self) -> Vec<u8> {
+        let take = self.pending.len().min(256);
+        let mut resp: Vec<u8> = self.pending.drain(..take).collect();
+        let remaining = self.pending.len();
+        if remaining == 0 {
+            resp.extend_from_slice(&sw::OK.to_be_bytes());
+        } else {
+            resp.push(0x61);
+            resp.push(remaining.min(256) as u8);
+        }
+        resp
+    }
+
+    fn dispatch(&mut self, cmd: &Command, pin_fresh: bool) -> Result<Vec<u8>, u16> {
+        // Only SELECT works until an applet is selected; the YubiKey
+        // management applet answers just the version and serial queries.
+        let allowed = match self.applet {
+            Applet::Piv => true,
+            Applet::YkPiv => matches!(cmd.ins, yk_ins::GET_VER | yk_ins::GET_SERIAL),
+            Applet::None => false,
+        };
+        if !allowed && cmd.ins != ins::SELECT {
+            return Err(sw::INS_NOT_SUPPORTED);
+        }
+        match cmd.ins {
+            ins::SELECT => self.cmd_select(cmd),
+            ins::GET_DATA => self.cmd_get_data(cmd),
+            ins::PUT_DATA => self.cmd_put_data(cmd),
+            ins::VERIFY => self.cmd_verify(cmd),
+            ins::CHANGE_PIN => self.cmd_change_reference(cmd),
+            ins::RESET_PIN => self.cmd_reset_retry(cmd),
+            ins::GEN_AUTH => self.cmd_general_authenticate(cmd, pin_fresh),
+            ins::GEN_ASYM => self.cmd_generate(cmd),
+            yk_ins::GET_VER => Ok(self.version.to_vec()),
+            yk_ins::GET_SERIAL => self.cmd_get_serial(),
+            yk_ins::SET_MGMT => self.cmd_set_mgmt(cmd),
+            yk_ins::IMPORT_ASYM => self.cmd_import(cmd),
+            yk_ins::SET_PIN_RETRIES => self.cmd_set_pin_retries(cmd),
+            yk_ins::RESET => self.cmd_reset(),
+            yk_ins::ATTEST => self.cmd_attest(cmd),
+            yk_ins::GET_METADATA => self.cmd_get_metadata(cmd),
+            _ => Err(sw::INS_NOT_SUPPORTED),
+        }
+    }
+
+    fn cmd_select(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p1 != 0x04 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if cmd.data.as_slice() == YKPIV_AID {
+            self.applet = Applet::YkPiv;
+            return Ok(Vec::new());
+        }
+        if cmd.data.is_empty() || !PIV_AID.starts_with(&cmd.data) {
+            return Err(sw::FILE_NOT_FOUND);
+        }
+        self.applet = Applet::Piv;
+
+        // Application Property Template (SP 800-73-4 part 2, table 3)
+        let mut authority = TlvWriter::new();
+        authority.write_tag_value(0x4F, &PIV_AID[..5]);
+        let mut algs = TlvWriter::new();
+        for a in [
+            alg::RSA1024,
+            alg::RSA2048,
+            alg::ECCP256,
+            alg::ECCP384,
+            alg::ED25519,
+            alg::X25519,
+        ] {
+            algs.write_tag_value(0x80, &[a]);
+        }
+        algs.write_tag_value(0x06, &[]);
+        let mut apt = TlvWriter::new();
+        apt.write_tag_value(0x4F, &PIV_AID[5..]);
+        apt.write_tag_value(0x79, authority.as_bytes());
+        apt.write_tag_value(0xAC, algs.as_bytes());
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x61, apt.as_bytes());
+        Ok(outer.into_vec())
+    }
+
+    fn cmd_get_data(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if (cmd.p1, cmd.p2) != (0x3F, 0xFF) {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        let (tag, _) = parse_object_tag(&cmd.data)?;
+        if PIN_PROTECTED_OBJECTS.contains(&tag) && !self.pin_verified {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        self.objects.get(&tag).cloned().ok_or(sw::FILE_NOT_FOUND)
+    }
+
+    fn cmd_put_data(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if (cmd.p1, cmd.p2) != (0x3F, 0xFF) {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !self.admin_authed {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        // The Discovery object and BIT Group Template are sent bare
+        match TlvReader::new(&cmd.data).read_tag() {
+            Ok(TAG_DISCOVERY) => return Err(sw::CONDITIONS_NOT_SATISFIED),
+            Ok(TAG_BITGT) => {
+                self.objects.insert(TAG_BITGT, cmd.data.clone());
+                return Ok(Vec::new());
+            }
+            _ => {}
+        }
+        let (tag, rest) = parse_object_tag(&cmd.data)?;
+        if tag == TAG_DISCOVERY {
+            return Err(sw::CONDITIONS_NOT_SATISFIED);
+        }
+        let mut reader = TlvReader::new(rest);
+        let outer = reader.read_tag().map_err(|_| sw::WRONG_DATA)?;
+        let value = reader.read_value().map_err(|_| sw::WRONG_DATA)?;
+        if outer != 0x53 || reader.has_remaining() {
+            return Err(sw::WRONG_DATA);
+        }
+        if value.is_empty() {
+            self.objects.remove(&tag);
+        } else {
+            self.objects.insert(tag, rest.to_vec());
+        }
+        Ok(Vec::new())
+    }
+
+    /// The PIN a VERIFY or CHANGE REFERENCE with this P2 talks to.
+    fn pin_counter(&mut self, reference: u8) -> Result<&mut PinCounter, u16> {
+        match reference {
+            PIV_PIN_REF => Ok(&mut self.pin),
+            GLOBAL_PIN_REF => self.global_pin.as_mut().ok_or(sw::REFERENCE_NOT_FOUND),
+            _ => Err(sw::REFERENCE_NOT_FOUND),
+        }
+    }
+
+    fn cmd_verify(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        let counter = self.pin_counter(cmd.p2)?;
+        match cmd.p1 {
+            // Clear verification status
+            0xFF if cmd.data.is_empty() => {
+                self.pin_verified = false;
+                Ok(Vec::new())
+            }
+            0x00 if cmd.data.is_empty() => {
+                let status = counter.retries_sw();
+                if self.pin_verified {
+                    Ok(Vec::new())
+                } else {
+                    Err(status)
+                }
+            }
+            0x00 if cmd.data.len() == 8 => {
+                let result = counter.check(&cmd.data);
+                self.pin_verified = result.is_ok();
+                result?;
+                self.pin_fresh = true;
+                Ok(Vec::new())
+            }
+            0x00 => Err(sw::WRONG_DATA),
+            _ => Err(sw::INCORRECT_P1P2),
+        }
+    }
+
+    fn cmd_change_reference(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p1 != 0x00 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if cmd.data.len() != 16 {
+            return Err(sw::WRONG_DATA);
+        }
+        let (old, new) = cmd.data.split_at(8);
+        let counter = match cmd.p2 {
+            PIV_PUK_REF => &mut self.puk,
+            reference => self.pin_counter(reference)?,
+        };
+        counter.check(old)?;
+        if unpadded_len(new) < 6 {
+            return Err(sw::WRONG_DATA);
+        }
+        counter.value.copy_from_slice(new);
+        Ok(Vec::new())
+    }
+
+    fn cmd_reset_retry(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p1 != 0x00 || cmd.p2 != PIV_PIN_REF {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if cmd.data.len() != 16 {
+            return Err(sw::WRONG_DATA);
+        }
+        let (puk, new_pin) = cmd.data.split_at(8);
+        self.puk.check(puk)?;
+        if unpadded_len(new_pin) < 6 {
+            return Err(sw::WRONG_DATA);
+        }
+        self.pin.value.copy_from_slice(new_pin);
+        self.pin.retries = self.pin.max_retries;
+        Ok(Vec::new())
+    }
+
+    fn cmd_general_authenticate(&mut self, cmd: &Command, pin_fresh: bool) -> Result<Vec<u8>, u16> {
+        let fields = parse_dynamic_auth(&cmd.data)?;
+        if cmd.p2 == ADMIN_SLOT {
+            return self.admin_authenticate(cmd.p1, &fields);
+        }
+
+        let key = self.keys.get(&cmd.p2).ok_or(sw::WRONG_DATA)?;
+        if key.algorithm != cmd.p1 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        let policy = match key.pin_policy {
+            pin_policy::DEFAULT => default_pin_policy(cmd.p2),
+            p => p,
+        };
+        let allowed = match policy {
+            pin_policy::NEVER => true,
+            pin_policy::ALWAYS => self.pin_verified && pin_fresh,
+            _ => self.pin_verified,
+        };
+        if !allowed {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+
+        let result = match (field(&fields, 0x81), field(&fields, 0x85)) {
+            (Some(challenge), None) => {
+                crypto::private_op(key.algorithm, &key.pkey, challenge).map_err(|_| sw::WRONG_DATA)?
+            }
+            (None, Some(point)) => crypto::ecdh(key.algorithm, &key.pkey, point)
+                .map_err(|_| sw::WRONG_DATA)?
+                .to_vec(),
+            _ => return Err(sw::WRONG_DATA),
+        };
+        Ok(dynamic_auth_response(0x82, &result))
+    }
+
+    fn admin_authenticate(&mut self, alg_id: u8, fields: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, u16> {
+        if alg_id != self.admin_alg {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        let (cipher, _) = crypto::admin_cipher(alg_id).ok_or(sw::INCORRECT_P1P2)?;
+        let block = cipher.block_size();
+        let encrypt = |data: &[u8], key: &[u8]| {
+            crypto::admin_block(alg_id, key, data, Mode::Encrypt).map_err(|_| sw::WRONG_DATA)
+        };
+
+        let witness = field(fields, 0x80);
+        let challenge = field(fields, 0x81);
+        let response = field(fields, 0x82);
+
+        match (witness, challenge, response) {
+            // Single-step: host asks for a challenge
+            (None, Some([]), None) => {
+                let chal = crypto::random(block);
+                self.admin_challenge = Some(chal.clone());
+                Ok(dynamic_auth_response(0x81, &chal))
+            }
+            // Single-step: host answers our challenge
+            (None, None, Some(resp)) => {
+                let chal = self.admin_challenge.take().ok_or(sw::CONDITIONS_NOT_SATISFIED)?;
+                if encrypt(&chal, &self.admin_key)? != resp {
+                    return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+                }
+                self.admin_authed = true;
+                Ok(Vec::new())
+            }
+            // Mutual: host asks for a witness
+            (Some([]), None, None) => {
+                let plain = crypto::random(block);
+                let enc = encrypt(&plain, &self.admin_key)?;
+                self.admin_witness = Some(plain);
+                Ok(dynamic_auth_response(0x80, &enc))
+            }
+            // Mutual: host returns decrypted witness plus its own challenge
+            (Some(decrypted), Some(host_chal), None) if !host_chal.is_empty() => {
+                let plain = self.admin_witness.take().ok_or(sw::CONDITIONS_NOT_SATISFIED)?;
+                if plain != decrypted {
+                    return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+                }
+                let resp = encrypt(host_chal, &self.admin_key)?;
+                self.admin_authed = true;
+                Ok(dynamic_auth_response(0x82, &resp))
+            }
+            _ => Err(sw::WRONG_DATA),
+        }
+    }
+
+    fn cmd_generate(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p1 != 0x00 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !is_key_slot(cmd.p2) {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !self.admin_authed {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        let mut reader = TlvReader::new(&cmd.data);
+        if reader.read_tag().map_err(|_| sw::WRONG_DATA)? != 0xAC {
+            return Err(sw::WRONG_DATA);
+        }
+        let template = reader.read_value().map_err(|_| sw::WRONG_DATA)?;
+        let fields = parse_tlvs(template)?;
+        let alg_id = match field(&fields, 0x80) {
+            Some([a]) => *a,
+            _ => return Err(sw::WRONG_DATA),
+        };
+        let pin = single_byte(&fields, 0xAA)?.unwrap_or(pin_policy::DEFAULT);
+        let touch = single_byte(&fields, 0xAB)?.unwrap_or(touch_policy::DEFAULT);
+
+        let pkey = crypto::generate(alg_id).map_err(|_| sw::WRONG_DATA)?;
+        let template = crypto::public_key_template(alg_id, &pkey).map_err(|_| sw::WRONG_DATA)?;
+        self.keys.insert(
+            cmd.p2,
+            SlotKey {
+                algorithm: alg_id,
+                pin_policy: pin,
+                touch_policy: touch,
+                generated: true,
+                pkey,
+            },
+        );
+        Ok(template)
+    }
+
+    fn cmd_import(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if !is_key_slot(cmd.p2) {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !self.admin_authed {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        let fields = parse_tlvs(&cmd.data)?;
+        let pin = single_byte(&fields, 0xAA)?.unwrap_or(pin_policy::DEFAULT);
+        let touch = single_byte(&fields, 0xAB)?.unwrap_or(touch_policy::DEFAULT);
+        let parts: Vec<(u32, &[u8])> = fields.iter().map(|(t, v)| (*t, v.as_slice())).collect();
+        let pkey = crypto::import(cmd.p1, &parts).map_err(|_| sw::WRONG_DATA)?;
+        self.keys.insert(
+            cmd.p2,
+            SlotKey {
+                algorithm: cmd.p1,
+                pin_policy: pin,
+                touch_policy: touch,
+                generated: false,
+                pkey,
+            },
+        );
+        Ok(Vec::new())
+    }
+
+    fn cmd_set_mgmt(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p1 != 0xFF || (cmd.p2 != 0xFF && cmd.p2 != 0xFE) {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !self.admin_authed {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        if cmd.data.len() < 3 || cmd.data[1] != ADMIN_SLOT {
+            return Err(sw::WRONG_DATA);
+        }
+        let alg_id = cmd.data[0];
+        let key_len = cmd.data[2] as usize;
+        let (_, expected) = crypto::admin_cipher(alg_id).ok_or(sw::WRONG_DATA)?;
+        if key_len != expected || cmd.data.len() != 3 + key_len {
+            return Err(sw::WRONG_DATA);
+        }
+        self.admin_alg = alg_id;
+        self.admin_key = Zeroizing::new(cmd.data[3..].to_vec());
+        self.admin_touch = cmd.p2 == 0xFE;
+        Ok(Vec::new())
+    }
+
+    fn cmd_set_pin_retries(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if !self.admin_authed || !self.pin_verified {
+            return Err(sw::SECURITY_STATUS_NOT_SATISFIED);
+        }
+        if cmd.p1 == 0 || cmd.p2 == 0 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        // YubiKeys reset PIN and PUK to their defaults when retries change
+        self.pin = PinCounter::new(DEFAULT_PIN, cmd.p1);
+        self.puk = PinCounter::new(DEFAULT_PUK, cmd.p2);
+        self.pin_verified = false;
+        Ok(Vec::new())
+    }
+
+    /// The PIV applet only reports the serial from firmware 5.0; older
+    /// keys answer it in the management applet alone.
+    fn cmd_get_serial(&mut self) -> Result<Vec<u8>, u16> {
+        if self.applet == Applet::Piv && self.version[0] < 5 {
+            return Err(sw::INS_NOT_SUPPORTED);
+        }
+        Ok(self.serial.to_be_bytes().to_vec())
+    }
+
+    /// Report algorithm, policy, origin and public key of a slot's key
+    /// (firmware 5.3 and later).
+    fn cmd_get_metadata(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if self.version < [5, 3, 0] {
+            return Err(sw::INS_NOT_SUPPORTED);
+        }
+        if cmd.p1 != 0x00 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        if !is_key_slot(cmd.p2) {
+            return Err(sw::REFERENCE_NOT_FOUND);
+        }
+        let key = self.keys.get(&cmd.p2).ok_or(sw::FILE_NOT_FOUND)?;
+        let (pin, touch) = effective_policy(cmd.p2, key);
+        let public_key =
+            crypto::public_key_fields(key.algorithm, &key.pkey).map_err(|_| sw::WRONG_DATA)?;
+        let mut tlv = TlvWriter::new();
+        tlv.write_tag_value(0x01, &[key.algorithm]);
+        tlv.write_tag_value(0x02, &[pin, touch]);
+        tlv.write_tag_value(0x03, &[if key.generated { 0x01 } else { 0x02 }]);
+        tlv.write_tag_value(0x04, &public_key);
+        Ok(tlv.into_vec())
+    }
+
+    /// Sign an attestation certificate for a key generated on the card.
+    fn cmd_attest(&mut self, cmd: &Command) -> Result<Vec<u8>, u16> {
+        if cmd.p2 != 0x00 {
+            return Err(sw::INCORRECT_P1P2);
+        }
+        let key = self.keys.get(&cmd.p1).ok_or(sw::WRONG_DATA)?;
+        // Imported keys can't be vouched for
+        if !key.generated {
+            return Err(sw::WRONG_DATA);
+        }
+        let (pin, touch) = effective_policy(cmd.p1, key);
+        let extensions = [
+            (3, self.version.to_vec()),
+            (7, der_integer(self.serial)),
+            (8, vec![pin, touch]),
+            (9, vec![FORM_FACTOR]),
+        ];
+        crypto::attestation_cert(
+            &key.pkey,
+            cmd.p1,
+            &self.attest_cert,
+            &self.attest_key,
+            &extensions,
+        )
+        .map_err(|_| sw::WRONG_DATA)
+    }
+
+    fn cmd_reset(&mut self) -> Result<Vec<u8>, u16> {
+        if !self.pin.is_blocked() || !self.puk.is_blocked() {
+            return Err(sw::CONDITIONS_NOT_SATISFIED);
+        }
+        let mut fresh = VirtualCard::new()
+            .with_version(self.version[0], self.version[1], self.version[2])
+            .with_serial(self.serial);
+        // The factory attestation key and its certificate survive a reset
+        std::mem::swap(&mut fresh.attest_root, &mut self.attest_root);
+        std::mem::swap(&mut fresh.attest_key, &mut self.attest_key);
+        std::mem::swap(&mut fresh.attest_cert, &mut self.attest_cert);
+        if let Some(f9) = self.objects.remove(&TAG_ATTESTATION_CERT) {
+            fresh.objects.insert(TAG_ATTESTATION_CERT, f9);
+        }
+        *self = fresh;
+        Ok(Vec::new())
+    }
+
+    /// Whether the 9B key requires touch (recorded for provisioning tests).
+    pub fn admin_requires_touch(&self) -> bool {
+        self.admin_touch
+    }
+
+    /// The current management key algorithm and value.
+    pub fn admin_key(&self) -> (u8, &[u8]) {
+        (self.admin_alg, &self.admin_key)
+    }
+}
+
+/// DER INTEGER encoding of a non-negative value
+fn der_integer(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(3);
+    let mut body = bytes[start..].to_vec();
+    if body[0] & 0x80 != 0 {
+        body.insert(0, 0x00);
+    }
+    let mut der = vec![0x02, body.len() as u8];
+    der.extend_from_slice(&body);
+    der
+}
+
+/// PIN and touch policy in force for a key, with defaults resolved.
+fn effective_policy(slot: u8, key: &SlotKey) -> (u8, u8) {
+    let pin = match key.pin_policy {
+        pin_policy::DEFAULT => default_pin_policy(slot),
+        p => p,
+    };
+    let touch = match key.touch_policy {
+        touch_policy::DEFAULT => touch_policy::NEVER,
+        t => t,
+    };
+    (pin, touch)
+}
+
+fn default_pin_policy(slot: u8) -> u8 {
+    match slot {
+        0x9C => pin_policy::ALWAYS,
+        0x9E => pin_policy::NEVER,
+        _ => pin_policy::ONCE,
+    }
+}
+
+fn is_key_slot(slot: u8) -> bool {
+    matches!(slot, 0x9A | 0x9C | 0x9D | 0x9E | 0x82..=0x95)
+}
+
+/// Parse the `5C {tag}` prefix of GET/PUT DATA, returning the tag and any
+/// bytes following it.
+fn parse_object_tag(data: &[u8]) -> Result<(u32, &[u8]), u16> {
+    let mut reader = TlvReader::new(data);
+    if reader.read_tag().map_err(|_| sw::WRONG_DATA)? != 0x5C {
+        return Err(sw::WRONG_DATA);
+    }
+    let tag_bytes = reader.read_value().map_err(|_| sw::WRONG_DATA)?;
+    if tag_bytes.is_empty() || tag_bytes.len() > 3 {
+        return Err(sw::WRONG_DATA);
+    }
+    let tag = tag_bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+    let consumed = data.len() - reader.remaining();
+    Ok((tag, &data[consumed..]))
+}
+
+fn parse_tlvs(data: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, u16> {
+    let mut reader = TlvReader::new(data);
+    let mut fields = Vec::new();
+    while reader.has_remaining() {
+        let tag = reader.read_tag().map_err(|_| sw::WRONG_DATA)?;
+        let value = reader.read_value().map_err(|_| sw::WRONG_DATA)?;
+        fields.push((tag, value.to_vec()));
+    }
+    Ok(fields)
+}
+
+/// Parse a GENERAL AUTHENTICATE 0x7C dynamic authentication template.
+fn parse_dynamic_auth(data: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, u16> {
+    let mut reader = TlvReader::new(data);
+    if reader.read_tag().map_err(|_| sw::WRONG_DATA)? != 0x7C {
+        return Err(sw::WRONG_DATA);
+    }
+    let template = reader.read_value().map_err(|_| sw::WRONG_DATA)?;
+    parse_tlvs(template)
+}
+
+fn field(fields: &[(u32, Vec<u8>)], tag: u32) -> Option<&[u8]> {
+    fields
+        .iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, v)| v.as_slice())
+}
+
+fn single_byte(fields: &[(u32, Vec<u8>)], tag: u32) -> Result<Option<u8>, u16> {
+    match field(fields, tag) {
+        None => Ok(None),
+        Some([b]) => Ok(Some(*b)),
+        Some(_) => Err(sw::WRONG_DATA),
+    }
+}
+
+fn dynamic_auth_response(tag: u32, value: &[u8]) -> Vec<u8> {
+    let mut inner = TlvWriter::new();
+    inner.write_tag_value(tag, value);
+    let mut outer = TlvWriter::new();
+    outer.write_tag_value(0x7C, inner.as_bytes());
+    outer.into_vec()
+}
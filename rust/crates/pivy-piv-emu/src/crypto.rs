@@ -0,0 +1,321 @@
+use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::rand::rand_bytes;
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::Signer;
+use openssl::symm::{Cipher, Crypter, Mode};
+use openssl::x509::extension::BasicConstraints;
+use openssl::x509::{X509Builder, X509Extension, X509NameBuilder, X509Ref, X509};
+use zeroize::Zeroizing;
+
+use pivy_piv::apdu::alg;
+use pivy_piv::tlv::TlvWriter;
+
+/// Failure inside the emulated key store; always reported to the host as
+/// SW 6A80 (incorrect data) or similar by the caller.
+#[derive(Debug)]
+pub struct CryptoError;
+
+impl From<openssl::error::ErrorStack> for CryptoError {
+    fn from(_: openssl::error::ErrorStack) -> Self {
+        CryptoError
+    }
+}
+
+pub fn random(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    rand_bytes(&mut buf).expect("RNG failure");
+    buf
+}
+
+fn curve(alg_id: u8) -> Option<Nid> {
+    match alg_id {
+        alg::ECCP256 => Some(Nid::X9_62_PRIME256V1),
+        alg::ECCP384 => Some(Nid::SECP384R1),
+        _ => None,
+    }
+}
+
+/// Generate a fresh private key for a PIV asymmetric algorithm.
+pub fn generate(alg_id: u8) -> Result<PKey<Private>, CryptoError> {
+    let pkey = match alg_id {
+        alg::RSA1024 => PKey::from_rsa(Rsa::generate(1024)?)?,
+        alg::RSA2048 => PKey::from_rsa(Rsa::generate(2048)?)?,
+        alg::ECCP256 | alg::ECCP384 => {
+            let group = EcGroup::from_curve_name(curve(alg_id).ok_or(CryptoError)?)?;
+            PKey::from_ec_key(EcKey::generate(&group)?)?
+        }
+        alg::ED25519 => PKey::generate_ed25519()?,
+        alg::X25519 => PKey::generate_x25519()?,
+        _ => return Err(CryptoError),
+    };
+    Ok(pkey)
+}
+
+/// Encode the public half of `pkey` as the 0x7F49 template returned by
+/// GENERATE ASYMMETRIC KEY PAIR.
+pub fn public_key_template(alg_id: u8, pkey: &PKey<Private>) -> Result<Vec<u8>, CryptoError> {
+    let mut outer = TlvWriter::new();
+    outer.write_tag_value(0x7F49, &public_key_fields(alg_id, pkey)?);
+    Ok(outer.into_vec())
+}
+
+/// The fields of a public key template without the 0x7F49 wrapper.
+pub fn public_key_fields(alg_id: u8, pkey: &PKey<Private>) -> Result<Vec<u8>, CryptoError> {
+    let mut inner = TlvWriter::new();
+    match alg_id {
+        alg::RSA1024 | alg::RSA2048 => {
+            let rsa = pkey.rsa()?;
+            inner.write_tag_value(0x81, &rsa.n().to_vec());
+            inner.write_tag_value(0x82, &rsa.e().to_vec());
+        }
+        alg::ECCP256 | alg::ECCP384 => {
+            let ec = pkey.ec_key()?;
+            let mut ctx = BigNumContext::new()?;
+            let point =
+                ec.public_key()
+                    .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)?;
+            inner.write_tag_value(0x86, &point);
+        }
+        alg::ED25519 | alg::X25519 => {
+            inner.write_tag_value(0x86, &pkey.raw_public_key()?);
+        }
+        _ => return Err(CryptoError),
+    }
+    Ok(inner.into_vec())
+}
+
+/// The private-key operation behind GENERAL AUTHENTICATE with a challenge:
+/// raw RSA, ECDSA over a pre-computed hash, or Ed25519 over the message.
+pub fn private_op(alg_id: u8, pkey: &PKey<Private>, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    match alg_id {
+        alg::RSA1024 | alg::RSA2048 => {
+            let rsa = pkey.rsa()?;
+            if data.len() != rsa.size() as usize {
+                return Err(CryptoError);
+            }
+            let mut out = vec![0u8; rsa.size() as usize];
+            let len = rsa.private_decrypt(data, &mut out, Padding::NONE)?;
+            out.truncate(len);
+            Ok(out)
+        }
+        alg::ECCP256 | alg::ECCP384 => {
+            let ec = pkey.ec_key()?;
+            Ok(EcdsaSig::sign(data, &ec)?.to_der()?)
+        }
+        alg::ED25519 => {
+            let mut signer = Signer::new_without_digest(pkey)?;
+            Ok(signer.sign_oneshot_to_vec(data)?)
+        }
+        _ => Err(CryptoError),
+    }
+}
+
+/// ECDH with the peer public key given as a SEC1 point (EC) or raw
+/// 32 bytes (X25519); returns the shared secret.
+pub fn ecdh(
+    alg_id: u8,
+    pkey: &PKey<Private>,
+    peer: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let peer_key: PKey<Public> = match alg_id {
+        alg::ECCP256 | alg::ECCP384 => {
+            let group = EcGroup::from_curve_name(curve(alg_id).ok_or(CryptoError)?)?;
+            let mut ctx = BigNumContext::new()?;
+            let point = EcPoint::from_bytes(&group, peer, &mut ctx)?;
+            PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)?
+        }
+        alg::X25519 => PKey::public_key_from_raw_bytes(peer, Id::X25519)?,
+        _ => return Err(CryptoError),
+    };
+    let mut deriver = Deriver::new(pkey)?;
+    deriver.set_peer(&peer_key)?;
+    Ok(Zeroizing::new(deriver.derive_to_vec()?))
+}
+
+/// Rebuild a private key from the YubiKey IMPORT ASYMMETRIC KEY fields.
+/// RSA keys are given as CRT components (public exponent is 65537).
+pub fn import(alg_id: u8, fields: &[(u32, &[u8])]) -> Result<PKey<Private>, CryptoError> {
+    let field = |tag: u32| -> Result<&[u8], CryptoError> {
+        fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| *v)
+            .ok_or(CryptoError)
+    };
+    match alg_id {
+        alg::RSA1024 | alg::RSA2048 => {
+            let p = BigNum::from_slice(field(0x01)?)?;
+            let q = BigNum::from_slice(field(0x02)?)?;
+            let dmp1 = BigNum::from_slice(field(0x03)?)?;
+            let dmq1 = BigNum::from_slice(field(0x04)?)?;
+            let iqmp = BigNum::from_slice(field(0x05)?)?;
+            let e = BigNum::from_u32(65537)?;
+
+            let mut ctx = BigNumContext::new()?;
+            let mut n = BigNum::new()?;
+            n.checked_mul(&p, &q, &mut ctx)?;
+            let one = BigNum::from_u32(1)?;
+            let mut p1 = BigNum::new()?;
+            p1.checked_sub(&p, &one)?;
+            let mut q1 = BigNum::new()?;
+            q1.checked_sub(&q, &one)?;
+            let mut phi = BigNum::new()?;
+            phi.checked_mul(&p1, &q1, &mut ctx)?;
+            let mut d = BigNum::new()?;
+            d.mod_inverse(&e, &phi, &mut ctx)?;
+
+            let rsa = Rsa::from_private_components(n, e, d, p, q, dmp1, dmq1, iqmp)?;
+            let bits = rsa.size() * 8;
+            let expected = if alg_id == alg::RSA1024 { 1024 } else { 2048 };
+            if bits != expected {
+                return Err(CryptoError);
+            }
+            Ok(PKey::from_rsa(rsa)?)
+        }
+        alg::ECCP256 | alg::ECCP384 => {
+            let group = EcGroup::from_curve_name(curve(alg_id).ok_or(CryptoError)?)?;
+            let priv_bn = BigNum::from_slice(field(0x06)?)?;
+            let mut ctx = BigNumContext::new()?;
+            let mut point = EcPoint::new(&group)?;
+            point.mul_generator2(&group, &priv_bn, &mut ctx)?;
+            let ec = EcKey::from_private_components(&group, &priv_bn, &point)?;
+            ec.check_key()?;
+            Ok(PKey::from_ec_key(ec)?)
+        }
+        alg::ED25519 => Ok(PKey::private_key_from_raw_bytes(field(0x07)?, Id::ED25519)?),
+        alg::X25519 => Ok(PKey::private_key_from_raw_bytes(field(0x08)?, Id::X25519)?),
+        _ => Err(CryptoError),
+    }
+}
+
+/// Self-signed certificate for a slot key so hosts can discover it via the
+/// cert object. X25519 keys can't sign and get no certificate.
+pub fn self_signed_cert(
+    alg_id: u8,
+    pkey: &PKey<Private>,
+    common_name: &str,
+) -> Result<Vec<u8>, CryptoError> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", common_name)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_slice(&random(8))?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(pkey)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(3650)?.as_ref())?;
+    let digest = match alg_id {
+        alg::ED25519 => MessageDigest::null(),
+        alg::X25519 => return Err(CryptoError),
+        _ => MessageDigest::sha256(),
+    };
+    builder.sign(pkey, digest)?;
+    Ok(builder.build().to_der()?)
+}
+
+/// Certificate authority used by the emulated attestation chain: a
+/// self-signed root when `issuer` is `None`, else issued by `issuer`.
+pub fn ca_cert(
+    pkey: &PKey<Private>,
+    common_name: &str,
+    issuer: Option<(&X509Ref, &PKey<Private>)>,
+) -> Result<X509, CryptoError> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", common_name)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_slice(&random(8))?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_pubkey(pkey)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(3650)?.as_ref())?;
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    match issuer {
+        Some((cert, key)) => {
+            builder.set_issuer_name(cert.subject_name())?;
+            builder.sign(key, MessageDigest::sha256())?;
+        }
+        None => {
+            builder.set_issuer_name(&name)?;
+            builder.sign(pkey, MessageDigest::sha256())?;
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Attestation certificate for a slot key, signed by the F9 key and
+/// carrying the Yubico extensions (1.3.6.1.4.1.41482.3.x) given as
+/// (last OID arc, extnValue contents).
+pub fn attestation_cert(
+    subject_key: &PKey<Private>,
+    slot: u8,
+    f9_cert: &X509Ref,
+    f9_key: &PKey<Private>,
+    extensions: &[(u8, Vec<u8>)],
+) -> Result<Vec<u8>, CryptoError> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", &format!("YubiKey PIV Attestation {:02x}", slot))?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_slice(&random(8))?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(f9_cert.subject_name())?;
+    builder.set_pubkey(subject_key)?;
+    builder.set_not_before(Asn1Time::from_str("20160314120000Z")?.as_ref())?;
+    builder.set_not_after(Asn1Time::from_str("99991231235959Z")?.as_ref())?;
+    for (arc, value) in extensions {
+        let oid = Asn1Object::from_str(&format!("1.3.6.1.4.1.41482.3.{}", arc))?;
+        let contents = Asn1OctetString::new_from_bytes(value)?;
+        builder.append_extension(X509Extension::new_from_der(&oid, false, &contents)?)?;
+    }
+    builder.sign(f9_key, MessageDigest::sha256())?;
+    Ok(builder.build().to_der()?)
+}
+
+/// Symmetric algorithm used for the 9B card management key.
+pub fn admin_cipher(alg_id: u8) -> Option<(Cipher, usize)> {
+    match alg_id {
+        alg::TDEA_3KEY => Some((Cipher::des_ede3(), 24)),
+        alg::AES128 => Some((Cipher::aes_128_ecb(), 16)),
+        alg::AES192 => Some((Cipher::aes_192_ecb(), 24)),
+        alg::AES256 => Some((Cipher::aes_256_ecb(), 32)),
+        _ => None,
+    }
+}
+
+/// Single-block ECB encrypt or decrypt with the management key.
+pub fn admin_block(
+    alg_id: u8,
+    key: &[u8],
+    block: &[u8],
+    mode: Mode,
+) -> Result<Vec<u8>, CryptoError> {
+    let (cipher, _) = admin_cipher(alg_id).ok_or(CryptoError)?;
+    if block.len() != cipher.block_size() {
+        return Err(CryptoError);
+    }
+    let mut crypter = Crypter::new(cipher, mode, key, None)?;
+    crypter.pad(false);
+    let mut out = vec![0u8; block.len() + cipher.block_size()];
+    let mut len = crypter.update(block, &mut out)?;
+    len += crypter.finalize(&mut out[len..])?;
+    out.truncate(len);
+    Ok(out)
+}
@@ -0,0 +1,12 @@
+//! Software emulation of a YubiKey-style PIV applet for hermetic tests.
+//!
+//! A [`VirtualCard`] holds keys, data objects and PIN state in memory and
+//! answers APDUs; a [`VirtualReader`] hands out [`EmulatedTransport`]
+//! connections to it that plug into `PivToken::open`.
+
+pub mod card;
+mod crypto;
+pub mod reader;
+
+pub use card::VirtualCard;
+pub use reader::{EmulatedTransport, VirtualReader};
@@ -0,0 +1,194 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use pivy_piv::{CardTransport, Disposition, PivError};
+
+use crate::card::VirtualCard;
+
+struct ReaderState {
+    card: Option<VirtualCard>,
+    /// Bumped whenever a card is inserted or removed
+    insertions: u64,
+    /// Bumped whenever the card is reset
+    resets: u64,
+    /// Connection currently holding an exclusive transaction
+    txn_owner: Option<u64>,
+    next_handle: u64,
+}
+
+/// An emulated card reader. Clones share the same slot, so a test can keep
+/// one handle to insert/remove cards while transports talk to it.
+#[derive(Clone)]
+pub struct VirtualReader {
+    name: String,
+    state: Arc<Mutex<ReaderState>>,
+}
+
+impl VirtualReader {
+    /// An empty reader.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state: Arc::new(Mutex::new(ReaderState {
+                card: None,
+                insertions: 0,
+                resets: 0,
+                txn_owner: None,
+                next_handle: 1,
+            })),
+        }
+    }
+
+    /// A reader with `card` already inserted.
+    pub fn with_card(name: &str, card: VirtualCard) -> Self {
+        let reader = Self::new(name);
+        reader.insert(card);
+        reader
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ReaderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Insert a card, replacing any card already present.
+    pub fn insert(&self, card: VirtualCard) {
+        let mut state = self.lock();
+        state.card = Some(card);
+        state.insertions += 1;
+        state.txn_owner = None;
+    }
+
+    /// Pull the card out of the reader.
+    pub fn remove(&self) -> Option<VirtualCard> {
+        let mut state = self.lock();
+        state.insertions += 1;
+        state.txn_owner = None;
+        state.card.take()
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.lock().card.is_some()
+    }
+
+    /// Reset the card as another PC/SC client would, clearing its security
+    /// status. Existing connections see a reset error on their next use.
+    pub fn reset_card(&self) {
+        let mut state = self.lock();
+        if let Some(card) = state.card.as_mut() {
+            card.reset();
+        }
+        state.resets += 1;
+    }
+
+    /// Run `f` against the inserted card, e.g. to inspect retry counters.
+    pub fn with_card_mut<R>(&self, f: impl FnOnce(&mut VirtualCard) -> R) -> Option<R> {
+        self.lock().card.as_mut().map(f)
+    }
+
+    /// Open a connection to the inserted card.
+    pub fn connect(&self) -> Result<EmulatedTransport, PivError> {
+        let mut state = self.lock();
+        if state.card.is_none() {
+            return Err(PivError::Pcsc(pcsc::Error::NoSmartcard));
+        }
+        let handle = state.next_handle;
+        state.next_handle += 1;
+        Ok(EmulatedTransport {
+            reader: self.clone(),
+            handle,
+            insertions: state.insertions,
+            resets: state.resets,
+        })
+    }
+}
+
+/// `CardTransport` connected to a `VirtualReader`. Mirrors the PC/SC error
+/// behaviour a real connection would show: removed cards, resets by other
+/// connections and sharing violations during another's transaction.
+pub struct EmulatedTransport {
+    reader: VirtualReader,
+    handle: u64,
+    insertions: u64,
+    resets: u64,
+}
+
+impl EmulatedTransport {
+    /// Check this connection is still valid against the reader state.
+    fn check(&self, state: &ReaderState) -> Result<(), PivError> {
+        if state.card.is_none() || state.insertions != self.insertions {
+            return Err(PivError::Pcsc(pcsc::Error::RemovedCard));
+        }
+        if state.resets != self.resets {
+            return Err(PivError::Pcsc(pcsc::Error::ResetCard));
+        }
+        match state.txn_owner {
+            Some(owner) if owner != self.handle => {
+                Err(PivError::Pcsc(pcsc::Error::SharingViolation))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CardTransport for EmulatedTransport {
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError> {
+        let mut state = self.reader.lock();
+        self.check(&state)?;
+        let card = state.card.as_mut().expect("checked above");
+        Ok(card.process(cmd))
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), PivError> {
+        let mut state = self.reader.lock();
+        self.check(&state)?;
+        state.txn_owner = Some(self.handle);
+        Ok(())
+    }
+
+    fn end_transaction(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        let mut state = self.reader.lock();
+        if state.txn_owner != Some(self.handle) {
+            return Err(PivError::Other("no transaction in progress".into()));
+        }
+        state.txn_owner = None;
+        if disposition == Disposition::Reset {
+            if let Some(card) = state.card.as_mut() {
+                card.reset();
+            }
+            state.resets += 1;
+            self.resets = state.resets;
+        }
+        Ok(())
+    }
+
+    fn reconnect(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        let mut state = self.reader.lock();
+        if state.card.is_none() || state.insertions != self.insertions {
+            return Err(PivError::Pcsc(pcsc::Error::RemovedCard));
+        }
+        if disposition == Disposition::Reset {
+            if let Some(card) = state.card.as_mut() {
+                card.reset();
+            }
+            state.resets += 1;
+        }
+        self.resets = state.resets;
+        Ok(())
+    }
+
+    fn reader_name(&self) -> &str {
+        self.reader.name()
+    }
+}
+
+impl Drop for EmulatedTransport {
+    fn drop(&mut self) {
+        let mut state = self.reader.lock();
+        if state.txn_owner == Some(self.handle) {
+            state.txn_owner = None;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use pivy_piv::apdu::{alg, Apdu, PIV_AID};
+use pivy_piv::{CardTransport, Disposition, PivError, PivToken};
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+
+fn sw(resp: &[u8]) -> u16 {
+    let len = resp.len();
+    (resp[len - 2] as u16) << 8 | resp[len - 1] as u16
+}
+
+/// A fresh card with the PIV applet selected.
+fn selected_card() -> VirtualCard {
+    let mut card = VirtualCard::new();
+    assert_eq!(sw(&card.process(&Apdu::select(PIV_AID).to_bytes())), 0x9000);
+    card
+}
+
+#[test]
+fn select_returns_application_property_template() {
+    let mut card = VirtualCard::new();
+    let resp = card.process(&Apdu::select(PIV_AID).to_bytes());
+    assert_eq!(sw(&resp), 0x9000);
+    assert_eq!(resp[0], 0x61);
+}
+
+#[test]
+fn unknown_instruction_rejected() {
+    let mut card = VirtualCard::new();
+    let resp = card.process(&[0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(resp, &[0x6D, 0x00]);
+}
+
+#[test]
+fn commands_need_applet_selected() {
+    let mut card = VirtualCard::new();
+    assert_eq!(card.process(&Apdu::get_data(0x5FC102).to_bytes()), &[0x6D, 0x00]);
+    card.process(&Apdu::select(PIV_AID).to_bytes());
+    assert_eq!(sw(&card.process(&Apdu::get_data(0x5FC102).to_bytes())), 0x9000);
+    card.reset();
+    assert_eq!(card.process(&Apdu::get_data(0x5FC102).to_bytes()), &[0x6D, 0x00]);
+}
+
+#[test]
+fn missing_object_not_found() {
+    let mut card = selected_card();
+    let resp = card.process(&Apdu::get_data(0x5FC105).to_bytes());
+    assert_eq!(resp, &[0x6A, 0x82]);
+}
+
+#[test]
+fn short_response_is_chained() {
+    let mut card = selected_card();
+    let cert = card.generate_with_cert(0x9C, alg::RSA2048);
+    assert!(cert.len() > 256);
+
+    let mut resp = card.process(&Apdu::get_data(0x5FC10A).to_bytes());
+    let mut data = Vec::new();
+    while sw(&resp) & 0xFF00 == 0x6100 {
+        data.extend_from_slice(&resp[..resp.len() - 2]);
+        resp = card.process(&[0x00, 0xC0, 0x00, 0x00, resp[resp.len() - 1]]);
+    }
+    assert_eq!(sw(&resp), 0x9000);
+    data.extend_from_slice(&resp[..resp.len() - 2]);
+    assert_eq!(data.as_slice(), card.object(0x5FC10A).unwrap());
+}
+
+#[test]
+fn extended_response_is_not_chained() {
+    let mut card = selected_card();
+    card.generate_with_cert(0x9C, alg::RSA2048);
+    let mut apdu = Apdu::get_data(0x5FC10A);
+    apdu.le = Some(0);
+    let resp = card.process(&apdu.to_extended_bytes());
+    assert_eq!(sw(&resp), 0x9000);
+    assert_eq!(&resp[..resp.len() - 2], card.object(0x5FC10A).unwrap());
+}
+
+#[test]
+fn put_data_requires_admin() {
+    let mut card = selected_card();
+    let mut apdu = Apdu::new(0x00, 0xDB, 0x3F, 0xFF);
+    apdu.data = vec![0x5C, 0x03, 0x5F, 0xC1, 0x09, 0x53, 0x01, 0x00];
+    assert_eq!(card.process(&apdu.to_bytes()), &[0x69, 0x82]);
+}
+
+#[test]
+fn chained_command_is_reassembled() {
+    let mut card = selected_card();
+    // An unknown GET DATA tag split over two chained APDUs
+    let first = [0x10, 0xCB, 0x3F, 0xFF, 0x02, 0x5C, 0x03];
+    let second = [0x00, 0xCB, 0x3F, 0xFF, 0x03, 0x5F, 0xC1, 0x02];
+    assert_eq!(card.process(&first), &[0x90, 0x00]);
+    let resp = card.process(&second);
+    assert_eq!(sw(&resp), 0x9000);
+    assert_eq!(&resp[..resp.len() - 2], card.object(0x5FC102).unwrap());
+}
+
+#[test]
+fn removed_card_fails_transmit() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new());
+    let transport = reader.connect().unwrap();
+    reader.remove();
+    assert!(!reader.is_present());
+    assert!(matches!(
+        transport.transmit(&Apdu::select(PIV_AID).to_bytes()),
+        Err(PivError::Pcsc(pcsc::Error::RemovedCard))
+    ));
+}
+
+#[test]
+fn reinserted_card_needs_new_connection() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new());
+    let old = reader.connect().unwrap();
+    let card = reader.remove().unwrap();
+    reader.insert(card);
+    assert!(old.transmit(&Apdu::select(PIV_AID).to_bytes()).is_err());
+    let token = PivToken::open(reader.connect().unwrap()).unwrap();
+    assert_eq!(token.reader_name(), "Virtual Reader 00");
+}
+
+#[test]
+fn reset_by_other_client_is_reported() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new());
+    let mut transport = reader.connect().unwrap();
+    reader.reset_card();
+    assert!(matches!(
+        transport.transmit(&Apdu::select(PIV_AID).to_bytes()),
+        Err(PivError::Pcsc(pcsc::Error::ResetCard))
+    ));
+    transport.reconnect(Disposition::Leave).unwrap();
+    assert!(transport.transmit(&Apdu::select(PIV_AID).to_bytes()).is_ok());
+}
+
+#[test]
+fn transaction_excludes_other_connections() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new());
+    let mut first = reader.connect().unwrap();
+    let second = reader.connect().unwrap();
+    first.begin_transaction().unwrap();
+    assert!(matches!(
+        second.transmit(&Apdu::select(PIV_AID).to_bytes()),
+        Err(PivError::Pcsc(pcsc::Error::SharingViolation))
+    ));
+    first.end_transaction(Disposition::Leave).unwrap();
+    assert!(second.transmit(&Apdu::select(PIV_AID).to_bytes()).is_ok());
+}
+
+#[test]
+fn reset_disposition_clears_pin_status() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new());
+    let mut transport = reader.connect().unwrap();
+    transport.begin_transaction().unwrap();
+    transport.transmit(&Apdu::select(PIV_AID).to_bytes()).unwrap();
+    let resp = transport
+        .transmit(&Apdu::verify_pin(b"123456").to_bytes())
+        .unwrap();
+    assert_eq!(sw(&resp), 0x9000);
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(true));
+    transport.end_transaction(Disposition::Reset).unwrap();
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+}
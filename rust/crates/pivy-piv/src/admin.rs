@@ -0,0 +1,93 @@
+//! Card management ("admin") key support for slot 9B.
+//!
+//! The admin key is a symmetric key that authorizes writes to the card:
+//! generating keys, writing certificates and other data objects, changing
+//! the management key itself. The card proves we hold it with a
+//! challenge-response exchange (see `PivTransaction::auth_admin`).
+
+use openssl::symm::{Cipher, Crypter, Mode};
+use zeroize::Zeroizing;
+
+use crate::apdu::alg;
+use crate::error::PivError;
+
+/// Management key YubiKeys ship with (and `pivy-tool init` leaves unless
+/// told otherwise). Firmware before 5.7 uses it as a 3DES key, later
+/// firmware as AES-192.
+pub const DEFAULT_ADMIN_KEY: [u8; 24] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+];
+
+/// Algorithms the default key is tried with, in order.
+pub const DEFAULT_ADMIN_KEY_ALGS: &[u8] = &[alg::TDEA_3KEY, alg::AES192];
+
+/// Block cipher used for an admin key algorithm. Challenges are a single
+/// block, so ECB is the same as the C library's CBC with a zero IV.
+fn cipher(alg_id: u8) -> Option<Cipher> {
+    match alg_id {
+        alg::TDEA_3KEY => Some(Cipher::des_ede3()),
+        alg::AES128 => Some(Cipher::aes_128_ecb()),
+        alg::AES192 => Some(Cipher::aes_192_ecb()),
+        alg::AES256 => Some(Cipher::aes_256_ecb()),
+        _ => None,
+    }
+}
+
+/// Key length in bytes for an admin key algorithm, or `None` if it isn't one.
+pub fn key_len(alg_id: u8) -> Option<usize> {
+    cipher(alg_id).map(|c| c.key_len())
+}
+
+/// Human-readable name of an admin key algorithm.
+pub fn alg_name(alg_id: u8) -> &'static str {
+    match alg_id {
+        alg::TDEA_3KEY => "3DES",
+        alg::AES128 => "AES-128",
+        alg::AES192 => "AES-192",
+        alg::AES256 => "AES-256",
+        _ => "unknown",
+    }
+}
+
+/// Check `key` is usable with `alg_id` before sending anything to the card.
+pub(crate) fn check_key(alg_id: u8, key: &[u8]) -> Result<(), PivError> {
+    let expected = key_len(alg_id).ok_or_else(|| {
+        PivError::UnsupportedAlgorithm(format!("{:#04x} is not an admin key algorithm", alg_id))
+    })?;
+    if key.len() != expected {
+        return Err(PivError::InvalidKeyLength {
+            expected,
+            actual: key.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Encrypt the card's challenge with the admin key.
+pub(crate) fn respond(
+    alg_id: u8,
+    key: &[u8],
+    challenge: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, PivError> {
+    check_key(alg_id, key)?;
+    let cipher = cipher(alg_id).expect("checked above");
+    if challenge.len() != cipher.block_size() {
+        return Err(PivError::Tlv {
+            message: format!(
+                "admin challenge is {} bytes but {} blocks are {} bytes",
+                challenge.len(),
+                alg_name(alg_id),
+                cipher.block_size()
+            ),
+        });
+    }
+
+    let mut crypter = Crypter::new(cipher, Mode::Encrypt, key, None)?;
+    crypter.pad(false);
+    let mut out = Zeroizing::new(vec![0u8; challenge.len() + cipher.block_size()]);
+    let mut len = crypter.update(challenge, &mut out)?;
+    len += crypter.finalize(&mut out[len..])?;
+    out.truncate(len);
+    Ok(out)
+}
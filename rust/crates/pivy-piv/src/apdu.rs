@@ -6,6 +6,13 @@ pub const PIV_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x0
 /// YubiKey PIV management AID
 pub const YKPIV_AID: &[u8] = &[0xA0, 0x00, 0x00, 0x05, 0x27, 0x47, 0x11, 0x17];
 
+/// ISO 7816-4 class byte values
+pub mod cla {
+    pub const ISO: u8 = 0x00;
+    /// Command chaining: more command APDUs follow in this chain
+    pub const CHAIN: u8 = 0x10;
+}
+
 /// ISO 7816-4 instruction codes
 pub mod ins {
     pub const SELECT: u8 = 0xA4;
@@ -19,15 +26,31 @@ pub mod ins {
     pub const CONTINUE: u8 = 0xC0;
 }
 
+/// YubiKey PIV extension instruction codes
+pub mod yk_ins {
+    pub const GET_METADATA: u8 = 0xF7;
+    pub const GET_SERIAL: u8 = 0xF8;
+    pub const ATTEST: u8 = 0xF9;
+    pub const SET_PIN_RETRIES: u8 = 0xFA;
+    pub const RESET: u8 = 0xFB;
+    pub const GET_VER: u8 = 0xFD;
+    pub const IMPORT_ASYM: u8 = 0xFE;
+    pub const SET_MGMT: u8 = 0xFF;
+}
+
 /// PIV slot IDs
 pub mod slot_id {
     pub const PIV_AUTH: u8 = 0x9A;
     pub const SIGNATURE: u8 = 0x9C;
     pub const KEY_MGMT: u8 = 0x9D;
     pub const CARD_AUTH: u8 = 0x9E;
+    /// Card management (admin) key
+    pub const ADMIN: u8 = 0x9B;
     // Retired key management slots
     pub const RETIRED_1: u8 = 0x82;
     pub const RETIRED_20: u8 = 0x95;
+    /// YubiKey attestation key
+    pub const ATTESTATION: u8 = 0xF9;
 }
 
 /// PIV algorithm identifiers
@@ -52,7 +75,31 @@ pub mod ga_tag {
     pub const EXPONENT: u8 = 0x85;
 }
 
+/// ISO 7816-4 status words the PIV commands report
+pub mod sw {
+    pub const OK: u16 = 0x9000;
+    pub const WRONG_LENGTH: u16 = 0x6700;
+    pub const SECURITY_STATUS_NOT_SATISFIED: u16 = 0x6982;
+    pub const AUTH_METHOD_BLOCKED: u16 = 0x6983;
+    pub const CONDITIONS_NOT_SATISFIED: u16 = 0x6985;
+    pub const WRONG_DATA: u16 = 0x6A80;
+    pub const FILE_NOT_FOUND: u16 = 0x6A82;
+    pub const FUNC_NOT_SUPPORTED: u16 = 0x6A81;
+    pub const OUT_OF_MEMORY: u16 = 0x6A84;
+    pub const REFERENCE_NOT_FOUND: u16 = 0x6A88;
+    pub const INCORRECT_P1P2: u16 = 0x6A86;
+    pub const INS_NOT_SUPPORTED: u16 = 0x6D00;
+    pub const CLA_NOT_SUPPORTED: u16 = 0x6E00;
+}
+
+/// Largest command data field that fits in a short APDU
+pub const SHORT_MAX_DATA: usize = 255;
+
 /// ISO 7816-4 APDU (Application Protocol Data Unit)
+///
+/// `le` of `Some(0)` requests the maximum response length (256 bytes for a
+/// short APDU, 65536 for an extended-length one).
+#[derive(Debug, Clone)]
 pub struct Apdu {
     pub cla: u8,
     pub ins: u8,
@@ -103,6 +150,23 @@ impl Apdu {
         }
     }
 
+    /// PUT DATA command to write (or, with empty `value`, delete) a PIV
+    /// data object
+    pub fn put_data(tag: u32, value: &[u8]) -> Self {
+        let mut tlv = TlvWriter::new();
+        tlv.write_tag_value(0x5C, &tag_to_bytes(tag));
+        tlv.write_tag_value(0x53, value);
+
+        Self {
+            cla: 0x00,
+            ins: ins::PUT_DATA,
+            p1: 0x3F,
+            p2: 0xFF,
+            data: tlv.into_vec(),
+            le: None,
+        }
+    }
+
     /// GENERAL AUTHENTICATE command for signing/key agreement
     pub fn general_authenticate(alg: u8, slot: u8, data: &[u8]) -> Self {
         Self {
@@ -131,9 +195,23 @@ impl Apdu {
         }
     }
 
-    /// Encode APDU to ISO 7816-4 byte format (short APDU)
+    /// Encode APDU to ISO 7816-4 byte format.
+    ///
+    /// Uses the short form when the command data and Le fit, and switches to
+    /// extended-length encoding otherwise so that Lc is never truncated.
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(5 + self.data.len() + 1);
+        let extended = self.data.len() > SHORT_MAX_DATA || self.le.is_some_and(|le| le > 256);
+        self.encode(extended)
+    }
+
+    /// Encode APDU using extended-length fields (3-byte Lc, 2-byte Le).
+    /// Only valid for cards that advertise extended-length support.
+    pub fn to_extended_bytes(&self) -> Vec<u8> {
+        self.encode(true)
+    }
+
+    fn encode(&self, extended: bool) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + 3 + self.data.len() + 2);
         buf.push(self.cla);
         buf.push(self.ins);
         buf.push(self.p1);
@@ -141,13 +219,28 @@ impl Apdu {
 
         if !self.data.is_empty() {
             // Case 3/4: command data present
-            buf.push(self.data.len() as u8); // Lc
+            let lc = self.data.len();
+            if extended {
+                assert!(lc <= 0xFFFF, "APDU data too large: {}", lc);
+                buf.push(0x00);
+                buf.push((lc >> 8) as u8);
+                buf.push(lc as u8);
+            } else {
+                buf.push(lc as u8);
+            }
             buf.extend_from_slice(&self.data);
         }
 
         if let Some(le) = self.le {
-            // Le field
-            if le >= 256 {
+            if extended {
+                // Le is prefixed by 0x00 only when there is no Lc field
+                if self.data.is_empty() {
+                    buf.push(0x00);
+                }
+                // 65536 encoded as 0x0000
+                buf.push((le >> 8) as u8);
+                buf.push(le as u8);
+            } else if le >= 256 {
                 buf.push(0x00); // 256 encoded as 0x00
             } else {
                 buf.push(le as u8);
@@ -156,6 +249,35 @@ impl Apdu {
 
         buf
     }
+
+    /// Split this command into an ISO 7816-4 command chain where each
+    /// APDU carries at most `max_data` bytes. Every APDU but the last has
+    /// the chaining bit set in CLA and no Le. Commands that already fit are
+    /// returned unchanged as a single-element chain.
+    pub fn chain(&self, max_data: usize) -> Vec<Apdu> {
+        assert!(max_data > 0, "chain segment size must be non-zero");
+        if self.data.len() <= max_data {
+            return vec![self.clone()];
+        }
+
+        let mut segments: Vec<Apdu> = self
+            .data
+            .chunks(max_data)
+            .map(|chunk| Apdu {
+                cla: self.cla | cla::CHAIN,
+                ins: self.ins,
+                p1: self.p1,
+                p2: self.p2,
+                data: chunk.to_vec(),
+                le: None,
+            })
+            .collect();
+        if let Some(last) = segments.last_mut() {
+            last.cla = self.cla;
+            last.le = self.le;
+        }
+        segments
+    }
 }
 
 /// Status word from a smartcard response (SW1-SW2)
@@ -190,6 +312,11 @@ impl StatusWord {
         self.0 == 0x63 && (self.1 & 0xF0) == 0xC0
     }
 
+    /// SW 6Cxx: wrong Le, x = exact number of bytes available
+    pub fn is_wrong_le(&self) -> bool {
+        self.0 == 0x6C
+    }
+
     /// Retries remaining if is_pin_incorrect(), else None
     pub fn pin_retries_remaining(&self) -> Option<u8> {
         if self.is_pin_incorrect() {
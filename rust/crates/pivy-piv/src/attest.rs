@@ -0,0 +1,209 @@
+//! YubiKey PIV attestation.
+//!
+//! A YubiKey can sign a certificate for a key it generated using the
+//! attestation key in slot F9. The F9 certificate is in turn issued by
+//! Yubico's PIV root CA, so a chain attestation -> F9 -> root proves the
+//! key was generated on a genuine device and never left it. See
+//! <https://developers.yubico.com/PIV/Introduction/PIV_attestation.html>.
+
+use openssl::pkey::{PKey, Public};
+use openssl::x509::{X509Ref, X509VerifyResult, X509};
+
+use crate::error::PivError;
+use crate::slot::{PinPolicy, TouchPolicy};
+use crate::tlv::TlvReader;
+use crate::ykpiv::Version;
+
+/// Yubico attestation extension OIDs (1.3.6.1.4.1.41482.3.x), DER encoded
+const OID_FIRMWARE: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x03];
+const OID_SERIAL: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x07];
+const OID_POLICY: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x08];
+const OID_FORM_FACTOR: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xC4, 0x0A, 0x03, 0x09];
+
+/// DER universal tags walked when looking for extensions
+const DER_INTEGER: u32 = 0x02;
+const DER_OCTET_STRING: u32 = 0x04;
+const DER_OID: u32 = 0x06;
+const DER_SEQUENCE: u32 = 0x30;
+/// tbsCertificate `extensions [3] EXPLICIT`
+const TBS_EXTENSIONS: u32 = 0xA3;
+
+/// What an attestation certificate says about the key it covers.
+#[derive(Debug, Clone)]
+pub struct Attestation {
+    cert_der: Vec<u8>,
+    firmware: Option<Version>,
+    serial: Option<u32>,
+    pin_policy: Option<PinPolicy>,
+    touch_policy: Option<TouchPolicy>,
+    form_factor: Option<u8>,
+}
+
+impl Attestation {
+    /// Read the Yubico extensions from an attestation certificate without
+    /// checking who signed it. Use [`verify`] unless the chain has already
+    /// been checked.
+    pub fn parse(cert_der: &[u8]) -> Result<Self, PivError> {
+        let mut attestation = Self {
+            cert_der: cert_der.to_vec(),
+            firmware: None,
+            serial: None,
+            pin_policy: None,
+            touch_policy: None,
+            form_factor: None,
+        };
+        for (oid, value) in extensions(cert_der)? {
+            match oid {
+                OID_FIRMWARE if value.len() == 3 => {
+                    attestation.firmware = Some(Version::from_bytes(value)?);
+                }
+                OID_SERIAL => attestation.serial = Some(parse_serial(value)?),
+                OID_POLICY if value.len() == 2 => {
+                    attestation.pin_policy = PinPolicy::from_byte(value[0]);
+                    attestation.touch_policy = TouchPolicy::from_byte(value[1]);
+                }
+                OID_FORM_FACTOR if value.len() == 1 => attestation.form_factor = Some(value[0]),
+                OID_FIRMWARE | OID_POLICY | OID_FORM_FACTOR => {
+                    return Err(PivError::Tlv {
+                        message: "malformed Yubico attestation extension".into(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        Ok(attestation)
+    }
+
+    pub fn cert_der(&self) -> &[u8] {
+        &self.cert_der
+    }
+
+    /// Public key of the attested slot.
+    pub fn public_key(&self) -> Result<PKey<Public>, PivError> {
+        Ok(X509::from_der(&self.cert_der)?.public_key()?)
+    }
+
+    /// Firmware version of the device.
+    pub fn firmware(&self) -> Option<Version> {
+        self.firmware
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        self.serial
+    }
+
+    pub fn pin_policy(&self) -> Option<PinPolicy> {
+        self.pin_policy
+    }
+
+    pub fn touch_policy(&self) -> Option<TouchPolicy> {
+        self.touch_policy
+    }
+
+    /// YubiKey form factor byte (1 = USB-A keychain, 2 = nano, ...)
+    pub fn form_factor(&self) -> Option<u8> {
+        self.form_factor
+    }
+}
+
+/// Check that `attestation` was signed by the F9 certificate
+/// `intermediate`, and that it in turn was issued by `root` (normally the
+/// Yubico PIV root CA), then return the attested properties.
+///
+/// Only signatures and issuer names are checked: F9 certificates on many
+/// devices lack CA basic constraints, and attestation certificates are
+/// expected to be checked regardless of their validity period.
+pub fn verify(
+    attestation: &[u8],
+    intermediate: &[u8],
+    root: &X509Ref,
+) -> Result<Attestation, PivError> {
+    let intermediate = X509::from_der(intermediate)?;
+    let leaf = X509::from_der(attestation)?;
+    check_issued(root, &intermediate, "F9 certificate")?;
+    check_issued(&intermediate, &leaf, "attestation certificate")?;
+    Attestation::parse(attestation)
+}
+
+fn check_issued(issuer: &X509Ref, subject: &X509Ref, what: &str) -> Result<(), PivError> {
+    if issuer.issued(subject) != X509VerifyResult::OK {
+        return Err(PivError::Crypto(format!("{} is not issued by its parent", what)));
+    }
+    let issuer_key = issuer.public_key()?;
+    if !subject.verify(&issuer_key)? {
+        return Err(PivError::Crypto(format!("{} has a bad signature", what)));
+    }
+    Ok(())
+}
+
+/// A certificate extension as (DER OID, extnValue contents)
+type Extension<'a> = (&'a [u8], &'a [u8]);
+
+/// Every extension in a DER certificate.
+fn extensions(cert_der: &[u8]) -> Result<Vec<Extension<'_>>, PivError> {
+    let cert = der_sequence(cert_der)?;
+    let tbs = der_sequence(cert)?;
+    let mut reader = TlvReader::new(tbs);
+    let mut found = Vec::new();
+    while reader.has_remaining() {
+        let tag = reader.read_tag()?;
+        let value = reader.read_value()?;
+        if tag != TBS_EXTENSIONS {
+            continue;
+        }
+        let mut list = TlvReader::new(der_sequence(value)?);
+        while list.has_remaining() {
+            expect_tag(&mut list, DER_SEQUENCE)?;
+            let mut ext = TlvReader::new(list.read_value()?);
+            expect_tag(&mut ext, DER_OID)?;
+            let oid = ext.read_value()?;
+            // Skip the optional critical flag
+            let mut tag = ext.read_tag()?;
+            if tag != DER_OCTET_STRING {
+                ext.read_value()?;
+                tag = ext.read_tag()?;
+            }
+            if tag != DER_OCTET_STRING {
+                return Err(PivError::Tlv {
+                    message: format!("expected extnValue, got tag {:#X}", tag),
+                });
+            }
+            found.push((oid, ext.read_value()?));
+        }
+    }
+    Ok(found)
+}
+
+/// Contents of the DER SEQUENCE at the start of `data`.
+fn der_sequence(data: &[u8]) -> Result<&[u8], PivError> {
+    let mut reader = TlvReader::new(data);
+    expect_tag(&mut reader, DER_SEQUENCE)?;
+    reader.read_value()
+}
+
+fn expect_tag(reader: &mut TlvReader<'_>, expected: u32) -> Result<(), PivError> {
+    let tag = reader.read_tag()?;
+    if tag != expected {
+        return Err(PivError::Tlv {
+            message: format!("expected DER tag {:#X}, got {:#X}", expected, tag),
+        });
+    }
+    Ok(())
+}
+
+/// The serial extension holds a DER INTEGER.
+fn parse_serial(value: &[u8]) -> Result<u32, PivError> {
+    let mut reader = TlvReader::new(value);
+    expect_tag(&mut reader, DER_INTEGER)?;
+    let bytes = reader.read_value()?;
+    let bytes = match bytes {
+        [0x00, rest @ ..] => rest,
+        _ => bytes,
+    };
+    if bytes.is_empty() || bytes.len() > 4 {
+        return Err(PivError::Tlv {
+            message: "malformed serial number in attestation".into(),
+        });
+    }
+    Ok(bytes.iter().fold(0u32, |acc, &b| acc << 8 | b as u32))
+}
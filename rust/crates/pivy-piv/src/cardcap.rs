@@ -0,0 +1,276 @@
+//! Card Capability Container object (0x5FC107).
+//!
+//! A legacy GSC-IS object that PIV still requires to be present. Nothing
+//! in it matters to PIV clients, but some middleware refuses cards
+//! without a well-formed one, so provisioning writes a fresh CCC naming
+//! the PIV data model. See NIST SP 800-73-4 part 1, section 3.1.1.
+
+use crate::error::PivError;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Card Capability Container data object tag (NIST SP 800-73-4)
+pub const PIV_TAG_CARDCAP: u32 = 0x5FC107;
+
+const TAG_CARD_ID: u32 = 0xF0;
+const TAG_CONTAINER_VERSION: u32 = 0xF1;
+const TAG_GRAMMAR_VERSION: u32 = 0xF2;
+const TAG_APP_CARD_URL: u32 = 0xF3;
+const TAG_PKCS15: u32 = 0xF4;
+const TAG_DATA_MODEL: u32 = 0xF5;
+const TAG_ACL_RULES: u32 = 0xF6;
+const TAG_CARD_APDUS: u32 = 0xF7;
+const TAG_REDIRECTION: u32 = 0xFA;
+const TAG_CAPABILITY_TUPLES: u32 = 0xFB;
+const TAG_STATUS_TUPLES: u32 = 0xFC;
+const TAG_NEXT_CCC: u32 = 0xFD;
+const TAG_LRC: u32 = 0xFE;
+
+/// GSC-IS registered application provider ID, the start of every card ID
+const GSC_RID: [u8; 5] = [0xA0, 0x00, 0x00, 0x01, 0x16];
+/// Card ID prefix: RID, manufacturer and card type
+const CARD_ID_PREFIX_LEN: usize = 7;
+const CARD_ID_MAX_LEN: usize = 21;
+/// Manufacturer byte written by pivy (unregistered)
+const DEFAULT_MANUFACTURER: u8 = 0xFF;
+
+/// PIV Data Model number (Registered Data Model, tag 0xF5)
+pub const DATA_MODEL_PIV: u8 = 0x10;
+
+/// Card type byte of the card ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardType {
+    FileSystem,
+    JavaCard,
+    Multos,
+    JavaCardFileSystem,
+    Other(u8),
+}
+
+impl CardType {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            CardType::FileSystem => 0x01,
+            CardType::JavaCard => 0x02,
+            CardType::Multos => 0x03,
+            CardType::JavaCardFileSystem => 0x04,
+            CardType::Other(b) => *b,
+        }
+    }
+
+    pub fn from_byte(b: u8) -> Self {
+        match b {
+            0x01 => CardType::FileSystem,
+            0x02 => CardType::JavaCard,
+            0x03 => CardType::Multos,
+            0x04 => CardType::JavaCardFileSystem,
+            other => CardType::Other(other),
+        }
+    }
+}
+
+/// Contents of a Card Capability Container.
+///
+/// Empty byte fields are encoded as empty tags, which the spec requires
+/// for every field PIV cards don't use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardCapability {
+    /// GSC RID, manufacturer, card type, then up to 14 bytes of ID
+    card_id: Vec<u8>,
+    pub container_version: u8,
+    pub grammar_version: u8,
+    pub app_card_url: Vec<u8>,
+    pub pkcs15: bool,
+    pub data_model: u8,
+    pub acl_rules: Vec<u8>,
+    pub card_apdus: Option<[u8; 6]>,
+    pub redirection: Vec<u8>,
+    pub capability_tuples: Vec<u8>,
+    pub status_tuples: Vec<u8>,
+    pub next_ccc: Vec<u8>,
+    pub lrc: Option<u8>,
+}
+
+impl Default for CardCapability {
+    /// A CCC for a Java Card naming the PIV data model, with no card ID
+    /// beyond the prefix.
+    fn default() -> Self {
+        let mut card_id = GSC_RID.to_vec();
+        card_id.push(DEFAULT_MANUFACTURER);
+        card_id.push(CardType::JavaCard.to_byte());
+        Self {
+            card_id,
+            container_version: 0x21,
+            grammar_version: 0x21,
+            app_card_url: Vec::new(),
+            pkcs15: false,
+            data_model: DATA_MODEL_PIV,
+            acl_rules: Vec::new(),
+            card_apdus: None,
+            redirection: Vec::new(),
+            capability_tuples: Vec::new(),
+            status_tuples: Vec::new(),
+            next_ccc: Vec::new(),
+            lrc: None,
+        }
+    }
+}
+
+impl CardCapability {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A default CCC with a random 14-byte card ID, as written when a
+    /// card is initialised.
+    pub fn with_random_id() -> Result<Self, PivError> {
+        let mut cc = Self::new();
+        let mut id = [0u8; CARD_ID_MAX_LEN - CARD_ID_PREFIX_LEN];
+        openssl::rand::rand_bytes(&mut id)?;
+        cc.set_id(&id)?;
+        Ok(cc)
+    }
+
+    /// Parse the content of the object's 0x53 wrapper.
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut cc = Self::new();
+        cc.card_id.clear();
+        let mut container_version = None;
+        let mut grammar_version = None;
+        let mut reader = TlvReader::new(data);
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match tag {
+                // Fixed-size fields may be sent empty
+                TAG_CARD_ID | TAG_CONTAINER_VERSION | TAG_GRAMMAR_VERSION | TAG_PKCS15
+                | TAG_CARD_APDUS | TAG_LRC
+                    if value.is_empty() => {}
+                TAG_CARD_ID if value.len() <= CARD_ID_MAX_LEN => cc.card_id = value.to_vec(),
+                TAG_CONTAINER_VERSION => container_version = Some(byte(tag, value)?),
+                TAG_GRAMMAR_VERSION => grammar_version = Some(byte(tag, value)?),
+                TAG_APP_CARD_URL => cc.app_card_url = value.to_vec(),
+                TAG_PKCS15 => cc.pkcs15 = byte(tag, value)? == 1,
+                TAG_DATA_MODEL => cc.data_model = byte(tag, value)?,
+                TAG_ACL_RULES => cc.acl_rules = value.to_vec(),
+                TAG_CARD_APDUS => {
+                    let apdus = value.try_into().map_err(|_| bad_length(tag, value))?;
+                    cc.card_apdus = Some(apdus);
+                }
+                TAG_REDIRECTION => cc.redirection = value.to_vec(),
+                TAG_CAPABILITY_TUPLES => cc.capability_tuples = value.to_vec(),
+                TAG_STATUS_TUPLES => cc.status_tuples = value.to_vec(),
+                TAG_NEXT_CCC => cc.next_ccc = value.to_vec(),
+                TAG_LRC => cc.lrc = Some(byte(tag, value)?),
+                TAG_CARD_ID => return Err(bad_length(tag, value)),
+                _ => {
+                    return Err(PivError::Tlv {
+                        message: format!("unexpected tag {:#04X} in CCC", tag),
+                    })
+                }
+            }
+        }
+
+        let (Some(container_version), Some(grammar_version)) = (container_version, grammar_version)
+        else {
+            return Err(PivError::Tlv {
+                message: "CCC is missing its container and grammar versions".into(),
+            });
+        };
+        for (what, version) in [("container", container_version), ("grammar", grammar_version)] {
+            if version >> 4 > 2 {
+                return Err(PivError::Tlv {
+                    message: format!("CCC has unsupported {} version {:#04X}", what, version),
+                });
+            }
+        }
+        cc.container_version = container_version;
+        cc.grammar_version = grammar_version;
+        Ok(cc)
+    }
+
+    /// Encode as the content of the object's 0x53 wrapper.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut tlv = TlvWriter::new();
+        tlv.write_tag_value(TAG_CARD_ID, &self.card_id);
+        tlv.write_tag_value(TAG_CONTAINER_VERSION, &[self.container_version]);
+        tlv.write_tag_value(TAG_GRAMMAR_VERSION, &[self.grammar_version]);
+        tlv.write_tag_value(TAG_APP_CARD_URL, &self.app_card_url);
+        tlv.write_tag_value(TAG_PKCS15, &[self.pkcs15 as u8]);
+        tlv.write_tag_value(TAG_DATA_MODEL, &[self.data_model]);
+        tlv.write_tag_value(TAG_ACL_RULES, &self.acl_rules);
+        tlv.write_tag_value(TAG_CARD_APDUS, self.card_apdus.as_ref().map_or(&[], |a| &a[..]));
+        tlv.write_tag_value(TAG_REDIRECTION, &self.redirection);
+        tlv.write_tag_value(TAG_CAPABILITY_TUPLES, &self.capability_tuples);
+        tlv.write_tag_value(TAG_STATUS_TUPLES, &self.status_tuples);
+        tlv.write_tag_value(TAG_NEXT_CCC, &self.next_ccc);
+        tlv.write_tag_value(TAG_LRC, self.lrc.as_slice());
+        tlv.into_vec()
+    }
+
+    /// The whole card ID: GSC RID, manufacturer, card type and ID.
+    pub fn card_id(&self) -> &[u8] {
+        &self.card_id
+    }
+
+    /// Manufacturer byte of the card ID, if it has one.
+    pub fn manufacturer(&self) -> Option<u8> {
+        self.card_id.get(5).copied()
+    }
+
+    pub fn set_manufacturer(&mut self, id: u8) {
+        self.pad_prefix();
+        self.card_id[5] = id;
+    }
+
+    pub fn card_type(&self) -> Option<CardType> {
+        self.card_id.get(6).map(|&b| CardType::from_byte(b))
+    }
+
+    pub fn set_card_type(&mut self, card_type: CardType) {
+        self.pad_prefix();
+        self.card_id[6] = card_type.to_byte();
+    }
+
+    /// The card-specific part of the card ID, after the prefix.
+    pub fn id(&self) -> Option<&[u8]> {
+        self.card_id.get(CARD_ID_PREFIX_LEN..)
+    }
+
+    /// Set the card-specific ID, at most 14 bytes.
+    pub fn set_id(&mut self, id: &[u8]) -> Result<(), PivError> {
+        if id.len() > CARD_ID_MAX_LEN - CARD_ID_PREFIX_LEN {
+            return Err(PivError::Other(format!(
+                "CCC card ID must be at most {} bytes, got {}",
+                CARD_ID_MAX_LEN - CARD_ID_PREFIX_LEN,
+                id.len()
+            )));
+        }
+        self.pad_prefix();
+        self.card_id.truncate(CARD_ID_PREFIX_LEN);
+        self.card_id.extend_from_slice(id);
+        Ok(())
+    }
+
+    /// Make sure the card ID is long enough to hold its prefix, filling
+    /// in the defaults for a short one read from a card.
+    fn pad_prefix(&mut self) {
+        if self.card_id.len() < CARD_ID_PREFIX_LEN {
+            let default = Self::default().card_id;
+            let len = self.card_id.len();
+            self.card_id.extend_from_slice(&default[len..]);
+        }
+    }
+}
+
+fn byte(tag: u32, value: &[u8]) -> Result<u8, PivError> {
+    match value {
+        [b] => Ok(*b),
+        _ => Err(bad_length(tag, value)),
+    }
+}
+
+fn bad_length(tag: u32, value: &[u8]) -> PivError {
+    PivError::Tlv {
+        message: format!("CCC tag {:#04X} has bad length {}", tag, value.len()),
+    }
+}
@@ -1,10 +1,109 @@
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey, EcPoint};
 use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
 use openssl::x509::X509;
 use ssh_key::public::{EcdsaPublicKey, KeyData};
 use ssh_key::PublicKey;
 
 use crate::error::PivError;
 use crate::slot::PivAlgorithm;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Tags inside a certificate data object (SP 800-73-4 part 1, appendix A)
+const TAG_CERT: u32 = 0x70;
+const TAG_CERTINFO: u32 = 0x71;
+const TAG_ERROR_DETECTION: u32 = 0xFE;
+
+/// CertInfo byte: compression type in the low two bits
+pub const CERTINFO_GZIP: u8 = 0x01;
+const CERTINFO_COMPTYPE: u8 = 0x03;
+/// CertInfo byte: "not X.509" flag, which the spec requires to be zero
+const CERTINFO_X509: u8 = 0x04;
+
+/// Largest certificate we'll inflate a compressed object to
+const MAX_CERT_LEN: usize = 16384;
+
+/// Build the contents of a certificate data object (what goes inside the
+/// 0x53 wrapper): 0x70 cert, 0x71 certinfo, 0xFE error detection code.
+/// With `compress`, the certificate is stored gzipped.
+pub fn encode_cert_object(cert_der: &[u8], compress: bool) -> Result<Vec<u8>, PivError> {
+    let mut tlv = TlvWriter::new();
+    if compress {
+        tlv.write_tag_value(TAG_CERT, &gzip(cert_der)?);
+        tlv.write_tag_value(TAG_CERTINFO, &[CERTINFO_GZIP]);
+    } else {
+        tlv.write_tag_value(TAG_CERT, cert_der);
+        tlv.write_tag_value(TAG_CERTINFO, &[0x00]);
+    }
+    tlv.write_tag_value(TAG_ERROR_DETECTION, &[]);
+    Ok(tlv.into_vec())
+}
+
+/// Pull the DER certificate out of a certificate data object, inflating it
+/// if the certinfo byte says it's compressed. Returns `None` if the object
+/// has no certificate in it.
+pub fn decode_cert_object(data: &[u8]) -> Result<Option<Vec<u8>>, PivError> {
+    let mut reader = TlvReader::new(data);
+    let mut cert = None;
+    let mut certinfo = 0u8;
+    while reader.has_remaining() {
+        let tag = reader.read_tag()?;
+        let value = reader.read_value()?;
+        match tag {
+            TAG_CERT => cert = Some(value),
+            TAG_CERTINFO => certinfo = value.first().copied().unwrap_or(0),
+            // 0xFE error detection code and anything else: skip
+            _ => {}
+        }
+    }
+    let cert = match cert {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    if certinfo & CERTINFO_X509 != 0 {
+        return Err(PivError::Tlv {
+            message: "certificate has the non-X.509 certinfo flag set".into(),
+        });
+    }
+    match certinfo & CERTINFO_COMPTYPE {
+        0 => Ok(Some(cert.to_vec())),
+        CERTINFO_GZIP => Ok(Some(gunzip(cert)?)),
+        other => Err(PivError::Tlv {
+            message: format!("unknown certificate compression type {}", other),
+        }),
+    }
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, PivError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| PivError::Other(format!("compressing certificate: {}", e)))
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, PivError> {
+    let mut out = Vec::new();
+    // Read one byte past the limit so oversized certs are detected
+    GzDecoder::new(data)
+        .take(MAX_CERT_LEN as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| PivError::Other(format!("decompressing certificate: {}", e)))?;
+    if out.len() > MAX_CERT_LEN {
+        return Err(PivError::Other(format!(
+            "compressed certificate inflates to more than {} bytes",
+            MAX_CERT_LEN
+        )));
+    }
+    Ok(out)
+}
 
 /// Extract the public key algorithm and ssh_key::PublicKey from a DER-encoded X.509 cert.
 pub fn extract_public_key(cert_der: &[u8]) -> Result<(PivAlgorithm, PublicKey), PivError> {
@@ -70,3 +169,29 @@ pub fn extract_public_key(cert_der: &[u8]) -> Result<(PivAlgorithm, PublicKey),
         ))
     }
 }
+
+/// Convert an SSH EC public key to OpenSSL form, e.g. as the peer key for
+/// [`PivToken::ecdh`].
+///
+/// [`PivToken::ecdh`]: crate::PivToken::ecdh
+pub fn pkey_from_ssh_public(key: &KeyData) -> Result<PKey<Public>, PivError> {
+    let KeyData::Ecdsa(ec) = key else {
+        return Err(PivError::UnsupportedAlgorithm(format!(
+            "SSH {} key",
+            key.algorithm()
+        )));
+    };
+    let nid = match ec {
+        EcdsaPublicKey::NistP256(_) => Nid::X9_62_PRIME256V1,
+        EcdsaPublicKey::NistP384(_) => Nid::SECP384R1,
+        EcdsaPublicKey::NistP521(_) => {
+            return Err(PivError::UnsupportedAlgorithm("ECDSA P-521".into()))
+        }
+    };
+    let group = EcGroup::from_curve_name(nid)?;
+    let mut ctx = BigNumContext::new()?;
+    let point = EcPoint::from_bytes(&group, ec.as_sec1_bytes(), &mut ctx)?;
+    let ec = EcKey::from_public_key(&group, &point)?;
+    ec.check_key()?;
+    Ok(PKey::from_ec_key(ec)?)
+}
@@ -0,0 +1,215 @@
+//! PIV Card Holder Unique Identifier object (0x5FC102).
+//!
+//! The CHUID names the card (GUID, FASC-N, cardholder UUID) and can carry
+//! an issuer signature: a detached CMS SignedData over the other fields,
+//! made with a CA key, so relying parties can tell a card was issued by
+//! someone they trust. See NIST SP 800-73-4 part 1, section 3.1.2.
+
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{PKeyRef, Private};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreRef;
+use openssl::x509::{X509Ref, X509};
+
+use crate::error::PivError;
+use crate::fascn::Fascn;
+use crate::guid::Guid;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// CHUID data object tag (NIST SP 800-73-4)
+pub const PIV_TAG_CHUID: u32 = 0x5FC102;
+
+const TAG_BUFFER_LENGTH: u32 = 0xEE;
+const TAG_FASCN: u32 = 0x30;
+const TAG_ORG_ID: u32 = 0x32;
+const TAG_DUNS: u32 = 0x33;
+const TAG_GUID: u32 = 0x34;
+const TAG_EXPIRY: u32 = 0x35;
+const TAG_CARDHOLDER_UUID: u32 = 0x36;
+/// Authentication Key Map (deprecated, skipped)
+const TAG_AUTH_KEY_MAP: u32 = 0x3D;
+const TAG_SIGNATURE: u32 = 0x3E;
+/// Error Detection Code, always empty
+const TAG_LRC: u32 = 0xFE;
+
+/// Contents of a CHUID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chuid {
+    /// Buffer Length (only on legacy cards)
+    pub buffer_length: Option<u16>,
+    pub fascn: Option<Fascn>,
+    pub org_id: Option<Vec<u8>>,
+    pub duns: Option<Vec<u8>>,
+    /// Mandatory in the spec, but missing or all zero on some cards
+    pub guid: Option<Guid>,
+    /// Expiration date, `YYYYMMDD`
+    pub expiry: Option<String>,
+    pub cardholder_uuid: Option<[u8; 16]>,
+    /// DER CMS SignedData over [`Chuid::tbs`]
+    signature: Option<Vec<u8>>,
+}
+
+impl Chuid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A CHUID with a fresh random GUID, as written by `pivy-tool init`.
+    pub fn with_random_guid() -> Result<Self, PivError> {
+        let mut guid = [0u8; 16];
+        openssl::rand::rand_bytes(&mut guid)?;
+        Ok(Self {
+            guid: Some(Guid::from_bytes(&guid)?),
+            ..Self::default()
+        })
+    }
+
+    /// Parse the content of the object's 0x53 wrapper.
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut chuid = Self::new();
+        let mut reader = TlvReader::new(data);
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match tag {
+                TAG_BUFFER_LENGTH => {
+                    let len: [u8; 2] = value.try_into().map_err(|_| bad_length(tag, value))?;
+                    chuid.buffer_length = Some(u16::from_be_bytes(len));
+                }
+                TAG_FASCN => chuid.fascn = Some(Fascn::decode(value)?),
+                TAG_ORG_ID => chuid.org_id = Some(value.to_vec()),
+                TAG_DUNS => chuid.duns = Some(value.to_vec()),
+                TAG_GUID => {
+                    let guid = Guid::from_bytes(value).map_err(|_| bad_length(tag, value))?;
+                    chuid.guid = Some(guid);
+                }
+                TAG_EXPIRY => {
+                    let expiry = String::from_utf8(value.to_vec()).map_err(|_| PivError::Tlv {
+                        message: "CHUID expiry date is not text".into(),
+                    })?;
+                    chuid.expiry = Some(expiry);
+                }
+                TAG_CARDHOLDER_UUID => {
+                    let uuid = value.try_into().map_err(|_| bad_length(tag, value))?;
+                    chuid.cardholder_uuid = Some(uuid);
+                }
+                TAG_SIGNATURE if value.is_empty() => {}
+                TAG_SIGNATURE => chuid.signature = Some(value.to_vec()),
+                TAG_AUTH_KEY_MAP | TAG_LRC => {}
+                _ => {
+                    return Err(PivError::Tlv {
+                        message: format!("unexpected tag {:#04X} in CHUID", tag),
+                    })
+                }
+            }
+        }
+        Ok(chuid)
+    }
+
+    /// The fields covered by the issuer signature, in encoding order.
+    pub fn tbs(&self) -> Vec<u8> {
+        let mut tlv = TlvWriter::new();
+        if let Some(len) = self.buffer_length {
+            tlv.write_tag_value(TAG_BUFFER_LENGTH, &len.to_be_bytes());
+        }
+        if let Some(fascn) = &self.fascn {
+            tlv.write_tag_value(TAG_FASCN, &fascn.encode());
+        }
+        if let Some(org_id) = &self.org_id {
+            tlv.write_tag_value(TAG_ORG_ID, org_id);
+        }
+        if let Some(duns) = &self.duns {
+            tlv.write_tag_value(TAG_DUNS, duns);
+        }
+        let guid = self.guid.as_ref().map_or([0; 16], |g| *g.as_bytes());
+        tlv.write_tag_value(TAG_GUID, &guid);
+        if let Some(expiry) = &self.expiry {
+            tlv.write_tag_value(TAG_EXPIRY, expiry.as_bytes());
+        }
+        if let Some(uuid) = &self.cardholder_uuid {
+            tlv.write_tag_value(TAG_CARDHOLDER_UUID, uuid);
+        }
+        tlv.into_vec()
+    }
+
+    /// Encode as the content of the object's 0x53 wrapper. The signature
+    /// tag is mandatory, so an unsigned CHUID gets an empty one.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut tlv = TlvWriter::new();
+        let tbs = self.tbs();
+        let signature = self.signature.as_deref().unwrap_or_default();
+        tlv.write_tag_value(TAG_SIGNATURE, signature);
+        tlv.write_tag_value(TAG_LRC, &[]);
+        [tbs, tlv.into_vec()].concat()
+    }
+
+    /// The identity to use for the card: the GUID, or if the GUID is
+    /// missing or all zero, the cardholder UUID, or failing that a hash of
+    /// the FASC-N credential fields.
+    pub fn identity(&self) -> Option<Guid> {
+        if let Some(guid) = self.guid.as_ref().filter(|g| g.as_bytes() != &[0; 16]) {
+            return Some(guid.clone());
+        }
+        if let Some(uuid) = &self.cardholder_uuid {
+            return Some(Guid::from_bytes(uuid).expect("16 bytes"));
+        }
+        let fascn = self.fascn.as_ref()?;
+        let ident = [
+            fascn.agency_code(),
+            fascn.system_code(),
+            fascn.credential_number(),
+            fascn.credential_series(),
+            fascn.individual_credential_issue(),
+        ]
+        .concat();
+        let digest = hash(MessageDigest::sha256(), ident.as_bytes()).ok()?;
+        Guid::from_bytes(&digest[..16]).ok()
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// DER CMS SignedData of the issuer signature, if any.
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
+
+    /// Sign the CHUID as issuer with `key`, whose certificate `cert` is
+    /// embedded in the signature. Changing any field afterwards
+    /// invalidates the signature.
+    pub fn sign(&mut self, cert: &X509Ref, key: &PKeyRef<Private>) -> Result<(), PivError> {
+        let flags = CMSOptions::DETACHED | CMSOptions::BINARY | CMSOptions::NOSMIMECAP;
+        let cms = CmsContentInfo::sign(Some(cert), Some(key), None, Some(&self.tbs()), flags)?;
+        self.signature = Some(cms.to_der()?);
+        Ok(())
+    }
+
+    pub fn clear_signature(&mut self) {
+        self.signature = None;
+    }
+
+    /// Check the issuer signature and that the signing certificate chains
+    /// to a trust anchor in `store`. `certs` may hold the signing and
+    /// intermediate certificates if they aren't embedded in the signature.
+    pub fn verify(&self, certs: &[X509], store: &X509StoreRef) -> Result<(), PivError> {
+        let signature = self
+            .signature
+            .as_deref()
+            .ok_or_else(|| PivError::Crypto("CHUID is not signed".into()))?;
+        let mut cms = CmsContentInfo::from_der(signature)?;
+        let mut stack = Stack::new()?;
+        for cert in certs {
+            stack.push(cert.clone())?;
+        }
+        cms.verify(Some(&stack), Some(store), Some(&self.tbs()), None, CMSOptions::BINARY)
+            .map_err(|e| PivError::Crypto(format!("CHUID signature is not valid: {}", e)))
+    }
+}
+
+fn bad_length(tag: u32, value: &[u8]) -> PivError {
+    PivError::Tlv {
+        message: format!("CHUID tag {:#04X} has bad length {}", tag, value.len()),
+    }
+}
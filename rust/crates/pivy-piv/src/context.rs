@@ -1,4 +1,6 @@
-use pcsc::{Context, Scope};
+use std::time::Duration;
+
+use pcsc::{Context, ReaderState, Scope, State};
 
 use crate::error::PivError;
 
@@ -24,3 +26,103 @@ impl PivContext {
         &self.ctx
     }
 }
+
+/// A card arriving in or leaving a reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReaderEvent {
+    Inserted(String),
+    Removed(String),
+}
+
+/// Watches the readers for card insertion and removal with
+/// SCardGetStatusChange, including readers plugged in later.
+///
+/// Holds its own PC/SC context, since [`ReaderMonitor::wait`] blocks.
+pub struct ReaderMonitor {
+    ctx: PivContext,
+    /// The PnP pseudo-reader first, then one entry per reader
+    states: Vec<ReaderState>,
+}
+
+impl ReaderMonitor {
+    pub fn new() -> Result<Self, PivError> {
+        let pnp = ReaderState::new(pcsc::PNP_NOTIFICATION(), State::UNAWARE);
+        Ok(Self {
+            ctx: PivContext::new()?,
+            states: vec![pnp],
+        })
+    }
+
+    /// The monitor's context, for connecting to the readers it reports.
+    pub fn context(&self) -> &PivContext {
+        &self.ctx
+    }
+
+    /// Wait up to `timeout` for cards to come or go. Cards already present
+    /// are reported as inserted by the first call; a card swapped between
+    /// two calls is reported as removed and inserted again.
+    pub fn wait(&mut self, timeout: Duration) -> Result<Vec<ReaderEvent>, PivError> {
+        let mut events = Vec::new();
+        self.sync_readers(&mut events)?;
+
+        // The event counts from the last call, to spot a card swapped out
+        // between calls
+        let counts: Vec<u32> = self.states.iter().map(ReaderState::event_count).collect();
+        match self.ctx.pcsc_context().get_status_change(timeout, &mut self.states) {
+            Ok(()) => {}
+            Err(pcsc::Error::Timeout) => return Ok(events),
+            Err(e) => return Err(e.into()),
+        }
+
+        for (state, count) in self.states.iter_mut().zip(counts).skip(1) {
+            let name = state.name().to_string_lossy().into_owned();
+            let current = state.current_state();
+            let event = state.event_state();
+            let was_present = current.contains(State::PRESENT);
+            let present = event.contains(State::PRESENT) && !event.contains(State::MUTE);
+            let swapped = count != state.event_count();
+            if was_present && (!present || swapped) {
+                events.push(ReaderEvent::Removed(name.clone()));
+            }
+            if present && (!was_present || swapped) {
+                events.push(ReaderEvent::Inserted(name));
+            }
+            state.sync_current_state();
+        }
+        self.states[0].sync_current_state();
+        Ok(events)
+    }
+
+    /// Start watching new readers and drop the ones that went away,
+    /// reporting their cards as removed.
+    fn sync_readers(&mut self, events: &mut Vec<ReaderEvent>) -> Result<(), PivError> {
+        let readers = match self.ctx.list_readers() {
+            Ok(readers) => readers,
+            Err(PivError::Pcsc(pcsc::Error::NoReadersAvailable)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut i = 1;
+        while i < self.states.len() {
+            let name = self.states[i].name().to_string_lossy().into_owned();
+            if readers.contains(&name) {
+                i += 1;
+                continue;
+            }
+            let state = self.states.remove(i);
+            if state.current_state().contains(State::PRESENT) {
+                events.push(ReaderEvent::Removed(name));
+            }
+        }
+        for reader in readers {
+            let known = self.states[1..]
+                .iter()
+                .any(|s| s.name().to_string_lossy() == reader.as_str());
+            if !known {
+                let name = std::ffi::CString::new(reader)
+                    .map_err(|e| PivError::Other(e.to_string()))?;
+                self.states.push(ReaderState::new(name, State::UNAWARE));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Discovery object (0x7E).
+//!
+//! Tells clients which PINs the card supports and which one to use: the
+//! PIV application PIN, the card-wide Global PIN, or both with one of them
+//! primary. See NIST SP 800-73-4 part 1, section 3.3.2.
+
+use crate::apdu::PIV_AID;
+use crate::error::PivError;
+use crate::pin::PinType;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Discovery object tag; unlike other objects it isn't wrapped in 0x53
+pub const PIV_TAG_DISCOVERY: u32 = 0x7E;
+
+const TAG_AID: u32 = 0x4F;
+const TAG_PIN_POLICY: u32 = 0x5F2F;
+
+/// First PIN Usage Policy byte
+const POLICY_APP_PIN: u8 = 0x40;
+const POLICY_GLOBAL_PIN: u8 = 0x20;
+const POLICY_OCC: u8 = 0x10;
+const POLICY_VCI: u8 = 0x08;
+
+/// Second PIN Usage Policy byte: which PIN is primary when both are
+/// supported
+const PRIMARY_APP_PIN: u8 = 0x10;
+const PRIMARY_GLOBAL_PIN: u8 = 0x20;
+
+/// PIN Usage Policy from the Discovery object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Discovery {
+    /// The PIV application PIN satisfies PIN access conditions
+    pub app_pin: bool,
+    /// The Global PIN satisfies PIN access conditions
+    pub global_pin: bool,
+    /// On-card biometric comparison satisfies PIN access conditions
+    pub occ: bool,
+    /// Virtual contact interface supported
+    pub vci: bool,
+    /// With both PINs supported, the Global PIN is the primary one
+    pub global_pin_primary: bool,
+}
+
+impl Discovery {
+    /// Parse the whole object, including the outer 0x7E tag.
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut outer = TlvReader::new(data);
+        let tag = outer.read_tag()?;
+        if tag != PIV_TAG_DISCOVERY {
+            return Err(unexpected(tag));
+        }
+        let mut disc = Self::default();
+        let mut reader = TlvReader::new(outer.read_value()?);
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match tag {
+                // Some cards give a truncated AID
+                TAG_AID if PIV_AID.starts_with(value) => {}
+                TAG_AID => {
+                    return Err(PivError::Tlv {
+                        message: "Discovery object names another application".into(),
+                    })
+                }
+                TAG_PIN_POLICY => {
+                    let [policy, primary]: [u8; 2] =
+                        value.try_into().map_err(|_| PivError::Tlv {
+                            message: format!("PIN Usage Policy has bad length {}", value.len()),
+                        })?;
+                    disc.app_pin = policy & POLICY_APP_PIN != 0;
+                    disc.global_pin = policy & POLICY_GLOBAL_PIN != 0;
+                    disc.occ = policy & POLICY_OCC != 0;
+                    disc.vci = policy & POLICY_VCI != 0;
+                    disc.global_pin_primary = primary == PRIMARY_GLOBAL_PIN;
+                }
+                _ => return Err(unexpected(tag)),
+            }
+        }
+        Ok(disc)
+    }
+
+    /// Encode the whole object, including the outer 0x7E tag.
+    pub fn encode(&self) -> Vec<u8> {
+        let flags = [
+            (self.app_pin, POLICY_APP_PIN),
+            (self.global_pin, POLICY_GLOBAL_PIN),
+            (self.occ, POLICY_OCC),
+            (self.vci, POLICY_VCI),
+        ];
+        let policy = flags.iter().filter(|(set, _)| *set).fold(0, |acc, (_, bit)| acc | bit);
+        let primary = match (self.app_pin && self.global_pin, self.global_pin_primary) {
+            (false, _) => 0x00,
+            (true, false) => PRIMARY_APP_PIN,
+            (true, true) => PRIMARY_GLOBAL_PIN,
+        };
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(TAG_AID, PIV_AID);
+        inner.write_tag_value(TAG_PIN_POLICY, &[policy, primary]);
+        let mut tlv = TlvWriter::new();
+        tlv.write_tag_value(PIV_TAG_DISCOVERY, inner.as_bytes());
+        tlv.into_vec()
+    }
+
+    /// The PIN to verify by default: the Global PIN if it's primary or
+    /// the only one supported, otherwise the PIV application PIN.
+    pub fn preferred_pin(&self) -> PinType {
+        if self.global_pin && (self.global_pin_primary || !self.app_pin) {
+            PinType::Global
+        } else {
+            PinType::Piv
+        }
+    }
+}
+
+fn unexpected(tag: u32) -> PivError {
+    PivError::Tlv {
+        message: format!("unexpected tag {:#04X} in Discovery object", tag),
+    }
+}
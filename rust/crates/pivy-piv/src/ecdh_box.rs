@@ -0,0 +1,468 @@
+//! ECDH boxes: a payload encrypted to an EC public key, usually a card's
+//! 9D key, so that only the holder of the private key can open it.
+//!
+//! Sealing generates an ephemeral key on the recipient's curve, hashes the
+//! ECDH secret together with a random nonce into a symmetric key, and
+//! encrypts the PKCS#7-padded payload with an authenticated cipher. The
+//! box carries both public keys and, optionally, the GUID and slot of the
+//! card holding the recipient key. The binary format is the C pivy
+//! `piv_ecdh_box` one, down to writing EC points compressed, so boxes move
+//! freely between the two.
+
+use openssl::bn::BigNumContext;
+use openssl::derive::Deriver;
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::hash::{Hasher, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{HasPrivate, Id, PKey, PKeyRef};
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use ssh_key::public::{EcdsaPublicKey, KeyData};
+use zeroize::Zeroizing;
+
+use crate::cert::pkey_from_ssh_public;
+use crate::error::PivError;
+use crate::guid::Guid;
+
+const MAGIC: [u8; 2] = [0xB0, 0xC5];
+pub const BOX_V1: u8 = 0x01;
+/// Adds the nonce hashed into the key
+pub const BOX_V2: u8 = 0x02;
+
+const DEFAULT_CIPHER: &str = "chacha20-poly1305";
+const DEFAULT_KDF: &str = "sha512";
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+/// A sealed ECDH box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcdhBox {
+    version: u8,
+    /// Card and slot holding the recipient key, if recorded
+    guid_slot: Option<(Guid, u8)>,
+    cipher: String,
+    kdf: String,
+    nonce: Vec<u8>,
+    recipient: EcdsaPublicKey,
+    ephemeral: EcdsaPublicKey,
+    iv: Vec<u8>,
+    /// Ciphertext followed by the authentication tag
+    ciphertext: Vec<u8>,
+}
+
+impl EcdhBox {
+    /// Seal `data` to an EC public key, with no card recorded.
+    pub fn seal(recipient: &KeyData, data: &[u8]) -> Result<Self, PivError> {
+        if data.is_empty() {
+            return Err(PivError::Other("can't seal an empty box".into()));
+        }
+        let recipient_pkey = pkey_from_ssh_public(recipient)?;
+        let KeyData::Ecdsa(recipient) = recipient else {
+            unreachable!("pkey_from_ssh_public only accepts EC keys");
+        };
+        let curve = recipient.curve();
+        let recipient_ec = recipient_pkey.ec_key()?;
+        let group = recipient_ec.group();
+        let ephemeral = PKey::from_ec_key(EcKey::generate(group)?)?;
+        let secret = derive(&ephemeral, &recipient_pkey)?;
+
+        let cipher = BoxCipher::from_name(DEFAULT_CIPHER)?;
+        let mut nonce = vec![0u8; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let mut iv = vec![0u8; cipher.iv_len()];
+        openssl::rand::rand_bytes(&mut iv)?;
+        let key = kdf(DEFAULT_KDF, cipher, &secret, &nonce)?;
+
+        // PKCS#7 padding: 1 to block-size bytes, each holding the count
+        let block = cipher.block_size();
+        let padding = block - data.len() % block;
+        let mut plain = Zeroizing::new(Vec::with_capacity(data.len() + padding));
+        plain.extend_from_slice(data);
+        plain.resize(data.len() + padding, padding as u8);
+        let ciphertext = cipher.encrypt(&key, &iv, &plain)?;
+
+        let ec = ephemeral.ec_key()?;
+        let mut ctx = BigNumContext::new()?;
+        let point = ec
+            .public_key()
+            .to_bytes(group, PointConversionForm::UNCOMPRESSED, &mut ctx)?;
+        Ok(Self {
+            version: BOX_V2,
+            guid_slot: None,
+            cipher: DEFAULT_CIPHER.into(),
+            kdf: DEFAULT_KDF.into(),
+            nonce,
+            recipient: ec_public(curve.as_str(), recipient.as_sec1_bytes())?,
+            ephemeral: ec_public(curve.as_str(), &point)?,
+            iv,
+            ciphertext,
+        })
+    }
+
+    /// Record the card and slot holding the recipient key, to help find
+    /// it when opening.
+    pub fn set_guid_slot(&mut self, guid: Guid, slot_id: u8) {
+        self.guid_slot = Some((guid, slot_id));
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn guid(&self) -> Option<&Guid> {
+        self.guid_slot.as_ref().map(|(guid, _)| guid)
+    }
+
+    pub fn slot(&self) -> Option<u8> {
+        self.guid_slot.as_ref().map(|(_, slot)| *slot)
+    }
+
+    pub fn cipher(&self) -> &str {
+        &self.cipher
+    }
+
+    pub fn kdf(&self) -> &str {
+        &self.kdf
+    }
+
+    /// The key the box was sealed to.
+    pub fn recipient(&self) -> KeyData {
+        KeyData::Ecdsa(self.recipient)
+    }
+
+    /// The ephemeral public key, for the ECDH that opens the box.
+    pub fn ephemeral(&self) -> KeyData {
+        KeyData::Ecdsa(self.ephemeral)
+    }
+
+    /// Size of the ciphertext, including padding and tag.
+    pub fn encrypted_len(&self) -> usize {
+        self.ciphertext.len()
+    }
+
+    /// Open the box with the recipient private key held locally.
+    pub fn open_offline<T: HasPrivate>(
+        &self,
+        key: &PKeyRef<T>,
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let ephemeral = pkey_from_ssh_public(&self.ephemeral())?;
+        let secret = derive(key, &ephemeral)?;
+        self.open_with_secret(&secret)
+    }
+
+    /// Open the box given the ECDH secret between the recipient key and
+    /// the ephemeral key.
+    pub(crate) fn open_with_secret(&self, secret: &[u8]) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let cipher = BoxCipher::from_name(&self.cipher)?;
+        if self.iv.len() != cipher.iv_len() {
+            return Err(invalid(format!(
+                "IV is {} bytes, {} needs {}",
+                self.iv.len(),
+                self.cipher,
+                cipher.iv_len()
+            )));
+        }
+        if self.ciphertext.len() < TAG_LEN + cipher.block_size() {
+            return Err(invalid(format!(
+                "ciphertext is only {} bytes",
+                self.ciphertext.len()
+            )));
+        }
+        let key = kdf(&self.kdf, cipher, secret, &self.nonce)?;
+        let mut plain = cipher.decrypt(&key, &self.iv, &self.ciphertext)?;
+
+        let padding = plain.last().copied().unwrap_or(0) as usize;
+        if padding == 0
+            || padding > cipher.block_size()
+            || padding > plain.len()
+            || plain[plain.len() - padding..].iter().any(|&b| b as usize != padding)
+        {
+            return Err(invalid("padding failed validation"));
+        }
+        let len = plain.len() - padding;
+        plain.truncate(len);
+        Ok(plain)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut r = BoxReader(data);
+        if r.bytes(2)? != MAGIC {
+            return Err(invalid("bad magic number"));
+        }
+        let version = r.u8()?;
+        if !(BOX_V1..=BOX_V2).contains(&version) {
+            return Err(invalid(format!("unsupported version {:#04x}", version)));
+        }
+        let guid_valid = r.u8()? != 0;
+        let guid = r.string8()?;
+        let slot = r.u8()?;
+        let guid_slot = if guid_valid {
+            let guid = Guid::from_bytes(guid)
+                .map_err(|_| invalid(format!("GUID is {} bytes", guid.len())))?;
+            Some((guid, slot))
+        } else {
+            None
+        };
+        let cipher = r.cstring8()?;
+        let kdf = r.cstring8()?;
+        let nonce = if version >= BOX_V2 {
+            r.string8()?.to_vec()
+        } else {
+            Vec::new()
+        };
+        let curve = r.cstring8()?;
+        let recipient = ec_public(&curve, r.string8()?)?;
+        let ephemeral = ec_public(&curve, r.string8()?)?;
+        let iv = r.string8()?.to_vec();
+        let ciphertext = r.string32()?.to_vec();
+        Ok(Self {
+            version,
+            guid_slot,
+            cipher,
+            kdf,
+            nonce,
+            recipient,
+            ephemeral,
+            iv,
+            ciphertext,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(self.version);
+        match &self.guid_slot {
+            Some((guid, slot)) => {
+                out.push(0x01);
+                put_string8(&mut out, guid.as_bytes());
+                out.push(*slot);
+            }
+            None => out.extend_from_slice(&[0x00, 0x00, 0x00]),
+        }
+        put_string8(&mut out, self.cipher.as_bytes());
+        put_string8(&mut out, self.kdf.as_bytes());
+        if self.version >= BOX_V2 {
+            put_string8(&mut out, &self.nonce);
+        }
+        put_string8(&mut out, self.recipient.curve().as_str().as_bytes());
+        put_string8(&mut out, &compressed(&self.recipient));
+        put_string8(&mut out, &compressed(&self.ephemeral));
+        put_string8(&mut out, &self.iv);
+        out.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+}
+
+/// The authenticated ciphers boxes may use, under their OpenSSH names.
+#[derive(Debug, Clone, Copy)]
+enum BoxCipher {
+    /// OpenSSH's ChaCha20-Poly1305 (not the RFC 8439 AEAD): the Poly1305
+    /// key is the first ChaCha20 block, the payload is encrypted from
+    /// block 1, and the tag covers only the ciphertext.
+    ChaChaPoly,
+    Aes128Gcm,
+    Aes256Gcm,
+}
+
+impl BoxCipher {
+    fn from_name(name: &str) -> Result<Self, PivError> {
+        match name {
+            "chacha20-poly1305" => Ok(Self::ChaChaPoly),
+            "aes128-gcm" => Ok(Self::Aes128Gcm),
+            "aes256-gcm" => Ok(Self::Aes256Gcm),
+            other => Err(PivError::UnsupportedAlgorithm(format!("box cipher {}", other))),
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            // Only the first half is used; the rest keys packet lengths
+            // in SSH
+            Self::ChaChaPoly => 64,
+            Self::Aes128Gcm => 16,
+            Self::Aes256Gcm => 32,
+        }
+    }
+
+    fn iv_len(self) -> usize {
+        match self {
+            // None: every key is fresh, so the nonce is always zero. C
+            // pivy rejects boxes that carry one.
+            Self::ChaChaPoly => 0,
+            Self::Aes128Gcm | Self::Aes256Gcm => 12,
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            Self::ChaChaPoly => 8,
+            Self::Aes128Gcm | Self::Aes256Gcm => 16,
+        }
+    }
+
+    fn gcm(self) -> Cipher {
+        match self {
+            Self::Aes128Gcm => Cipher::aes_128_gcm(),
+            _ => Cipher::aes_256_gcm(),
+        }
+    }
+
+    /// Encrypt, returning the ciphertext with the tag appended.
+    fn encrypt(self, key: &[u8], iv: &[u8], plain: &[u8]) -> Result<Vec<u8>, PivError> {
+        match self {
+            Self::ChaChaPoly => {
+                let poly_key = chacha_poly_key(key)?;
+                let mut out =
+                    symm::encrypt(Cipher::chacha20(), &key[..32], Some(&CHACHA_IV_1), plain)?;
+                let tag = poly1305(&poly_key, &out)?;
+                out.extend_from_slice(&tag);
+                Ok(out)
+            }
+            Self::Aes128Gcm | Self::Aes256Gcm => {
+                let mut tag = [0u8; TAG_LEN];
+                let mut out = symm::encrypt_aead(self.gcm(), key, Some(iv), &[], plain, &mut tag)?;
+                out.extend_from_slice(&tag);
+                Ok(out)
+            }
+        }
+    }
+
+    /// Check the tag and decrypt.
+    fn decrypt(self, key: &[u8], iv: &[u8], enc: &[u8]) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let (enc, tag) = enc.split_at(enc.len() - TAG_LEN);
+        let plain = match self {
+            Self::ChaChaPoly => {
+                let poly_key = chacha_poly_key(key)?;
+                if !openssl::memcmp::eq(&poly1305(&poly_key, enc)?, tag) {
+                    return Err(invalid("authentication failed"));
+                }
+                symm::decrypt(Cipher::chacha20(), &key[..32], Some(&CHACHA_IV_1), enc)?
+            }
+            Self::Aes128Gcm | Self::Aes256Gcm => {
+                symm::decrypt_aead(self.gcm(), key, Some(iv), &[], enc, tag)
+                    .map_err(|_| invalid("authentication failed"))?
+            }
+        };
+        Ok(Zeroizing::new(plain))
+    }
+}
+
+/// OpenSSL ChaCha20 IVs are a 32-bit little-endian block counter then the
+/// nonce; OpenSSH's nonce is the (zero) sequence number.
+const CHACHA_IV_0: [u8; 16] = [0; 16];
+const CHACHA_IV_1: [u8; 16] = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+fn chacha_poly_key(key: &[u8]) -> Result<Zeroizing<Vec<u8>>, PivError> {
+    let block = symm::encrypt(Cipher::chacha20(), &key[..32], Some(&CHACHA_IV_0), &[0u8; 32])?;
+    Ok(Zeroizing::new(block))
+}
+
+fn poly1305(key: &[u8], data: &[u8]) -> Result<Vec<u8>, PivError> {
+    let pkey = PKey::private_key_from_raw_bytes(key, Id::POLY1305)?;
+    let mut signer = Signer::new_without_digest(&pkey)?;
+    Ok(signer.sign_oneshot_to_vec(data)?)
+}
+
+/// Hash the ECDH secret and nonce into the cipher key.
+fn kdf(
+    name: &str,
+    cipher: BoxCipher,
+    secret: &[u8],
+    nonce: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, PivError> {
+    let digest = match name {
+        "sha256" => MessageDigest::sha256(),
+        "sha384" => MessageDigest::sha384(),
+        "sha512" => MessageDigest::sha512(),
+        other => return Err(PivError::UnsupportedAlgorithm(format!("box KDF {}", other))),
+    };
+    if digest.size() < cipher.key_len() {
+        return Err(PivError::UnsupportedAlgorithm(format!(
+            "box KDF {} is too short for cipher {:?}",
+            name, cipher
+        )));
+    }
+    let mut hasher = Hasher::new(digest)?;
+    hasher.update(secret)?;
+    hasher.update(nonce)?;
+    let hash = hasher.finish()?;
+    Ok(Zeroizing::new(hash[..cipher.key_len()].to_vec()))
+}
+
+fn derive<T: HasPrivate, U: openssl::pkey::HasPublic>(
+    key: &PKeyRef<T>,
+    peer: &PKeyRef<U>,
+) -> Result<Zeroizing<Vec<u8>>, PivError> {
+    let mut deriver = Deriver::new(key)?;
+    deriver.set_peer(peer)?;
+    Ok(Zeroizing::new(deriver.derive_to_vec()?))
+}
+
+/// Parse a point on the named curve, in either SEC1 form, and keep it
+/// uncompressed so it compares equal to the same key from elsewhere.
+fn ec_public(curve: &str, point: &[u8]) -> Result<EcdsaPublicKey, PivError> {
+    let nid = match curve {
+        "nistp256" => Nid::X9_62_PRIME256V1,
+        "nistp384" => Nid::SECP384R1,
+        other => return Err(invalid(format!("unsupported curve {}", other))),
+    };
+    let group = EcGroup::from_curve_name(nid)?;
+    let mut ctx = BigNumContext::new()?;
+    // Rejects points not on the curve
+    let point = EcPoint::from_bytes(&group, point, &mut ctx)
+        .map_err(|e| invalid(format!("bad EC point: {e}")))?;
+    let point = point.to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)?;
+    EcdsaPublicKey::from_sec1_bytes(&point).map_err(|e| invalid(format!("bad EC point: {e}")))
+}
+
+/// The SEC1 compressed form of an uncompressed point.
+fn compressed(key: &EcdsaPublicKey) -> Vec<u8> {
+    let point = key.as_sec1_bytes();
+    let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+    let mut out = vec![0x02 | (y[y.len() - 1] & 1)];
+    out.extend_from_slice(x);
+    out
+}
+
+fn invalid(message: impl Into<String>) -> PivError {
+    PivError::InvalidBox(message.into())
+}
+
+fn put_string8(out: &mut Vec<u8>, data: &[u8]) {
+    out.push(data.len() as u8);
+    out.extend_from_slice(data);
+}
+
+/// Reads the box's length-prefixed fields.
+struct BoxReader<'a>(&'a [u8]);
+
+impl<'a> BoxReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PivError> {
+        if self.0.len() < n {
+            return Err(invalid("truncated"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, PivError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn string8(&mut self) -> Result<&'a [u8], PivError> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    fn cstring8(&mut self) -> Result<String, PivError> {
+        let s = self.string8()?;
+        String::from_utf8(s.to_vec()).map_err(|_| invalid("name is not text"))
+    }
+
+    fn string32(&mut self) -> Result<&'a [u8], PivError> {
+        let len = u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()) as usize;
+        self.bytes(len)
+    }
+}
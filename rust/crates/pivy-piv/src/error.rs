@@ -29,18 +29,53 @@ pub enum PivError {
     #[error("PIN is blocked")]
     PinBlocked,
 
+    #[error("PUK is blocked")]
+    PukBlocked,
+
     #[error("slot {0:#04x} not found or empty")]
     SlotEmpty(u8),
 
     #[error("unsupported algorithm: {0}")]
     UnsupportedAlgorithm(String),
 
+    #[error("card administrator authentication required")]
+    AdminRequired,
+
+    #[error("admin key authentication failed (wrong management key)")]
+    AdminAuthFailed,
+
+    #[error("key is {actual} bytes, expected {expected}")]
+    InvalidKeyLength { expected: usize, actual: usize },
+
     #[error("OpenSSL error: {0}")]
     Openssl(#[from] openssl::error::ErrorStack),
 
+    #[error("invalid ECDH box: {0}")]
+    InvalidBox(String),
+
     #[error("crypto error: {0}")]
     Crypto(String),
 
     #[error("{0}")]
     Other(String),
 }
+
+impl PivError {
+    /// Whether the card was removed or reset under us, so the connection
+    /// is dead but a new one may work.
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            PivError::Pcsc(
+                pcsc::Error::ResetCard
+                    | pcsc::Error::RemovedCard
+                    | pcsc::Error::NoSmartcard
+                    | pcsc::Error::UnpoweredCard
+                    | pcsc::Error::UnresponsiveCard
+                    | pcsc::Error::CommError
+                    | pcsc::Error::ReaderUnavailable
+                    | pcsc::Error::InvalidHandle
+            )
+        )
+    }
+}
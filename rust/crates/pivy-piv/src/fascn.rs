@@ -0,0 +1,472 @@
+//! Federal Agency Smart Credential Number (FASC-N), as found in the CHUID.
+//!
+//! A FASC-N is 40 five-bit characters (4-bit BCD digit plus odd parity,
+//! least significant bit first) packed into 25 bytes: a start sentinel,
+//! the agency/system/credential fields separated by field separators, the
+//! person and organization fields, an end sentinel and a longitudinal
+//! redundancy check. See the "Technical Implementation Guidance: Smart
+//! Card Enabled Physical Access Control Systems" (TIG SCEPACS).
+
+use std::fmt;
+
+use crate::error::PivError;
+
+/// Encoded length of a FASC-N
+pub const FASCN_LEN: usize = 25;
+
+/// Control characters, as 4-bit values
+const SS: u8 = 0xB;
+const FS: u8 = 0xD;
+const ES: u8 = 0xF;
+
+/// Organizational Category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrgCategory {
+    Federal,
+    State,
+    Commercial,
+    Foreign,
+}
+
+impl OrgCategory {
+    fn to_digit(self) -> u8 {
+        match self {
+            Self::Federal => 1,
+            Self::State => 2,
+            Self::Commercial => 3,
+            Self::Foreign => 4,
+        }
+    }
+
+    fn from_digit(d: u8) -> Option<Self> {
+        match d {
+            1 => Some(Self::Federal),
+            2 => Some(Self::State),
+            3 => Some(Self::Commercial),
+            4 => Some(Self::Foreign),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OrgCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Federal => "federal",
+            Self::State => "state",
+            Self::Commercial => "commercial",
+            Self::Foreign => "foreign",
+        })
+    }
+}
+
+/// Person/Organization Association Category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Association {
+    Employee,
+    Civil,
+    ExecutiveStaff,
+    UniformedService,
+    Contractor,
+    Affiliate,
+    Beneficiary,
+}
+
+impl Association {
+    fn to_digit(self) -> u8 {
+        match self {
+            Self::Employee => 1,
+            Self::Civil => 2,
+            Self::ExecutiveStaff => 3,
+            Self::UniformedService => 4,
+            Self::Contractor => 5,
+            Self::Affiliate => 6,
+            Self::Beneficiary => 7,
+        }
+    }
+
+    fn from_digit(d: u8) -> Option<Self> {
+        match d {
+            1 => Some(Self::Employee),
+            2 => Some(Self::Civil),
+            3 => Some(Self::ExecutiveStaff),
+            4 => Some(Self::UniformedService),
+            5 => Some(Self::Contractor),
+            6 => Some(Self::Affiliate),
+            7 => Some(Self::Beneficiary),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Association {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Employee => "employee",
+            Self::Civil => "civil",
+            Self::ExecutiveStaff => "executive-staff",
+            Self::UniformedService => "uniformed-service",
+            Self::Contractor => "contractor",
+            Self::Affiliate => "affiliate",
+            Self::Beneficiary => "beneficiary",
+        })
+    }
+}
+
+/// A decoded FASC-N. Digit fields are kept as strings of their fixed
+/// width; setters left-pad shorter values with zeros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fascn {
+    agency_code: String,
+    system_code: String,
+    credential_number: String,
+    credential_series: String,
+    individual_credential_issue: String,
+    person_identifier: String,
+    org_category: OrgCategory,
+    org_identifier: String,
+    association: Association,
+    /// Decoded from 25 zero bytes (unprovisioned cards), and re-encoded
+    /// the same way until a field is set
+    all_zero: bool,
+}
+
+impl Default for Fascn {
+    /// The all-zero FASC-N of an unprovisioned card.
+    fn default() -> Self {
+        Self {
+            agency_code: "0000".into(),
+            system_code: "0000".into(),
+            credential_number: "000000".into(),
+            credential_series: "0".into(),
+            individual_credential_issue: "1".into(),
+            person_identifier: "0000000000".into(),
+            org_category: OrgCategory::Commercial,
+            org_identifier: "0000".into(),
+            association: Association::Employee,
+            all_zero: true,
+        }
+    }
+}
+
+impl Fascn {
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        if (7..=FASCN_LEN).contains(&data.len()) && data.iter().all(|&b| b == 0) {
+            return Ok(Self::default());
+        }
+
+        let mut r = BcdReader::new(data);
+        if r.read()? != SS {
+            return Err(format_error("missing start sentinel"));
+        }
+        let agency_code = r.read_field("agency code", 4, Some(FS))?;
+        let system_code = r.read_field("system code", 4, Some(FS))?;
+        let credential_number = r.read_field("credential number", 6, Some(FS))?;
+        let credential_series = r.read_field("credential series", 1, Some(FS))?;
+        let individual_credential_issue = r.read_field("credential issue", 1, Some(FS))?;
+        let person_identifier = r.read_field("person identifier", 10, None)?;
+        let oc = r.read_field("org category", 1, None)?;
+        let org_identifier = r.read_field("org identifier", 4, None)?;
+        let poa = r.read_field("association category", 1, Some(ES))?;
+        r.check_lrc()?;
+        if !r.at_end() {
+            return Err(format_error("trailing data after end sentinel"));
+        }
+
+        let org_category = OrgCategory::from_digit(oc.as_bytes()[0] - b'0')
+            .ok_or_else(|| format_error(&format!("unknown org category {}", oc)))?;
+        let association = Association::from_digit(poa.as_bytes()[0] - b'0')
+            .ok_or_else(|| format_error(&format!("unknown association category {}", poa)))?;
+        Ok(Self {
+            agency_code,
+            system_code,
+            credential_number,
+            credential_series,
+            individual_credential_issue,
+            person_identifier,
+            org_category,
+            org_identifier,
+            association,
+            all_zero: false,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        if self.all_zero {
+            return vec![0; FASCN_LEN];
+        }
+        let mut w = BcdWriter::new();
+        w.write(SS);
+        for field in [
+            &self.agency_code,
+            &self.system_code,
+            &self.credential_number,
+            &self.credential_series,
+            &self.individual_credential_issue,
+        ] {
+            w.write_digits(field);
+            w.write(FS);
+        }
+        w.write_digits(&self.person_identifier);
+        w.write(self.org_category.to_digit());
+        w.write_digits(&self.org_identifier);
+        w.write(self.association.to_digit());
+        w.write(ES);
+        w.finish()
+    }
+
+    /// Whether this is the all-zero FASC-N of an unprovisioned card.
+    pub fn is_zero(&self) -> bool {
+        self.all_zero
+    }
+
+    pub fn agency_code(&self) -> &str {
+        &self.agency_code
+    }
+
+    pub fn system_code(&self) -> &str {
+        &self.system_code
+    }
+
+    pub fn credential_number(&self) -> &str {
+        &self.credential_number
+    }
+
+    pub fn credential_series(&self) -> &str {
+        &self.credential_series
+    }
+
+    pub fn individual_credential_issue(&self) -> &str {
+        &self.individual_credential_issue
+    }
+
+    pub fn person_identifier(&self) -> &str {
+        &self.person_identifier
+    }
+
+    pub fn org_category(&self) -> OrgCategory {
+        self.org_category
+    }
+
+    pub fn org_identifier(&self) -> &str {
+        &self.org_identifier
+    }
+
+    pub fn association(&self) -> Association {
+        self.association
+    }
+
+    pub fn set_agency_code(&mut self, v: &str) -> Result<(), PivError> {
+        self.agency_code = pad("agency code", v, 4)?;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_system_code(&mut self, v: &str) -> Result<(), PivError> {
+        self.system_code = pad("system code", v, 4)?;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_credential_number(&mut self, v: &str) -> Result<(), PivError> {
+        self.credential_number = pad("credential number", v, 6)?;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_credential_series(&mut self, v: &str) -> Result<(), PivError> {
+        self.credential_series = pad("credential series", v, 1)?;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_individual_credential_issue(&mut self, v: &str) -> Result<(), PivError> {
+        self.individual_credential_issue = pad("credential issue", v, 1)?;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_person(&mut self, association: Association, id: &str) -> Result<(), PivError> {
+        self.person_identifier = pad("person identifier", id, 10)?;
+        self.association = association;
+        self.all_zero = false;
+        Ok(())
+    }
+
+    pub fn set_org(&mut self, category: OrgCategory, id: &str) -> Result<(), PivError> {
+        self.org_identifier = pad("org identifier", id, 4)?;
+        self.org_category = category;
+        self.all_zero = false;
+        Ok(())
+    }
+}
+
+/// `agency-system-credential-series-issue/orgcat:orgid/assoc:person`, the
+/// same format pivy-tool prints.
+impl fmt::Display for Fascn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}-{}-{}/{}:{}/{}:{}",
+            self.agency_code,
+            self.system_code,
+            self.credential_number,
+            self.credential_series,
+            self.individual_credential_issue,
+            self.org_category,
+            self.org_identifier,
+            self.association,
+            self.person_identifier
+        )
+    }
+}
+
+/// Left-pad a digit string to `width`.
+fn pad(name: &str, v: &str, width: usize) -> Result<String, PivError> {
+    if v.len() > width || !v.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(PivError::Other(format!(
+            "FASC-N {} must be at most {} digits, got {:?}",
+            name, width, v
+        )));
+    }
+    Ok(format!("{:0>width$}", v, width = width))
+}
+
+fn format_error(message: &str) -> PivError {
+    PivError::Tlv {
+        message: format!("invalid FASC-N: {}", message),
+    }
+}
+
+/// Five-bit character for a 4-bit value: the value's bits least
+/// significant first, then an odd parity bit.
+fn to_symbol(v: u8) -> u8 {
+    let reversed = (v & 1) << 3 | (v & 2) << 1 | (v & 4) >> 1 | (v & 8) >> 3;
+    let parity = (v.count_ones() as u8 + 1) & 1;
+    reversed << 1 | parity
+}
+
+/// Inverse of [`to_symbol`], or `None` if the parity is wrong.
+fn from_symbol(sym: u8) -> Option<u8> {
+    if sym.count_ones().is_multiple_of(2) {
+        return None;
+    }
+    let bits = sym >> 1;
+    Some((bits & 8) >> 3 | (bits & 4) >> 1 | (bits & 2) << 1 | (bits & 1) << 3)
+}
+
+struct BcdWriter {
+    buf: Vec<u8>,
+    bits: usize,
+    lrc: u8,
+}
+
+impl BcdWriter {
+    fn new() -> Self {
+        Self {
+            buf: Vec::with_capacity(FASCN_LEN),
+            bits: 0,
+            lrc: 0,
+        }
+    }
+
+    fn write(&mut self, v: u8) {
+        self.lrc ^= v;
+        self.write_symbol(to_symbol(v));
+    }
+
+    fn write_digits(&mut self, digits: &str) {
+        for b in digits.bytes() {
+            self.write(b - b'0');
+        }
+    }
+
+    fn write_symbol(&mut self, sym: u8) {
+        for i in (0..5).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.buf.push(0);
+            }
+            let bit = (sym >> i) & 1;
+            *self.buf.last_mut().expect("pushed above") |= bit << (7 - self.bits % 8);
+            self.bits += 1;
+        }
+    }
+
+    /// Append the LRC (XOR of every character's value) and return the
+    /// encoded bytes.
+    fn finish(mut self) -> Vec<u8> {
+        let lrc = self.lrc;
+        self.write_symbol(to_symbol(lrc));
+        self.buf
+    }
+}
+
+struct BcdReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+    lrc: u8,
+}
+
+impl<'a> BcdReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, bit: 0, lrc: 0 }
+    }
+
+    fn read_symbol(&mut self) -> Result<u8, PivError> {
+        if self.bit + 5 > self.data.len() * 8 {
+            return Err(format_error("truncated"));
+        }
+        let mut sym = 0;
+        for _ in 0..5 {
+            let bit = (self.data[self.bit / 8] >> (7 - self.bit % 8)) & 1;
+            sym = sym << 1 | bit;
+            self.bit += 1;
+        }
+        Ok(sym)
+    }
+
+    fn read(&mut self) -> Result<u8, PivError> {
+        let offset = self.bit / 5;
+        let v = from_symbol(self.read_symbol()?)
+            .ok_or_else(|| format_error(&format!("bad parity at character {}", offset)))?;
+        self.lrc ^= v;
+        Ok(v)
+    }
+
+    /// Read up to `width` digits, then the `terminator` if there is one
+    /// (fields without one are exactly `width` digits).
+    fn read_field(
+        &mut self,
+        name: &str,
+        width: usize,
+        terminator: Option<u8>,
+    ) -> Result<String, PivError> {
+        let mut digits = String::with_capacity(width);
+        loop {
+            let v = self.read()?;
+            match v {
+                0..=9 if digits.len() < width => digits.push((b'0' + v) as char),
+                _ if Some(v) == terminator && !digits.is_empty() => break,
+                _ => return Err(format_error(&format!("malformed {}", name))),
+            }
+            if terminator.is_none() && digits.len() == width {
+                break;
+            }
+        }
+        Ok(format!("{:0>width$}", digits, width = width))
+    }
+
+    fn check_lrc(&mut self) -> Result<(), PivError> {
+        let expected = self.lrc;
+        let lrc = from_symbol(self.read_symbol()?).ok_or_else(|| format_error("bad LRC parity"))?;
+        if lrc != expected {
+            return Err(format_error("LRC mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Only zero padding bits may follow the LRC.
+    fn at_end(&self) -> bool {
+        self.data.len() * 8 - self.bit < 8
+    }
+}
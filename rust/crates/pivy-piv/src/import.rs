@@ -0,0 +1,180 @@
+//! Private key import (YubiKey IMPORT ASYMMETRIC KEY, INS 0xFE).
+//!
+//! The key travels to the card as TLVs of its private components: RSA
+//! CRT values in tags 0x01-0x05, the EC private scalar in 0x06, and the
+//! raw Ed25519/X25519 private key in 0x07/0x08. Every buffer holding
+//! key material is zeroized once the command has been built.
+
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, PKeyRef, Private};
+use openssl::rsa::Rsa;
+use ssh_key::private::{EcdsaKeypair, KeypairData};
+use ssh_key::PrivateKey;
+use zeroize::Zeroizing;
+
+use crate::error::PivError;
+use crate::slot::{KeyPolicy, PinPolicy, PivAlgorithm, TouchPolicy};
+use crate::tlv::TlvWriter;
+
+/// IMPORT ASYMMETRIC KEY data tags
+const TAG_RSA_P: u32 = 0x01;
+const TAG_RSA_Q: u32 = 0x02;
+const TAG_RSA_DP: u32 = 0x03;
+const TAG_RSA_DQ: u32 = 0x04;
+const TAG_RSA_QINV: u32 = 0x05;
+const TAG_EC_PRIVATE: u32 = 0x06;
+const TAG_ED25519_PRIVATE: u32 = 0x07;
+const TAG_X25519_PRIVATE: u32 = 0x08;
+const TAG_PIN_POLICY: u32 = 0xAA;
+const TAG_TOUCH_POLICY: u32 = 0xAB;
+
+/// The only RSA public exponent the card accepts
+const RSA_EXPONENT: u32 = 65537;
+
+/// Build the IMPORT ASYMMETRIC KEY command data for `key`, returning the
+/// algorithm to send as P1.
+pub fn encode_import(
+    key: &PKeyRef<Private>,
+    policy: KeyPolicy,
+) -> Result<(PivAlgorithm, Zeroizing<Vec<u8>>), PivError> {
+    let (algorithm, fields) = private_fields(key)?;
+    let capacity = fields.iter().map(|(_, v)| v.len() + 4).sum::<usize>() + 6;
+    let mut tlv = TlvWriter::with_capacity(capacity);
+    for (tag, value) in &fields {
+        tlv.write_tag_value(*tag, value);
+    }
+    if policy.pin != PinPolicy::Default {
+        tlv.write_tag_value(TAG_PIN_POLICY, &[policy.pin.to_byte()]);
+    }
+    if policy.touch != TouchPolicy::Default {
+        tlv.write_tag_value(TAG_TOUCH_POLICY, &[policy.touch.to_byte()]);
+    }
+    Ok((algorithm, Zeroizing::new(tlv.into_vec())))
+}
+
+type Field = (u32, Zeroizing<Vec<u8>>);
+
+fn private_fields(key: &PKeyRef<Private>) -> Result<(PivAlgorithm, Vec<Field>), PivError> {
+    match key.id() {
+        Id::RSA => {
+            let rsa = key.rsa()?;
+            let algorithm = match rsa.size() * 8 {
+                1024 => PivAlgorithm::Rsa1024,
+                2048 => PivAlgorithm::Rsa2048,
+                bits => {
+                    return Err(PivError::UnsupportedAlgorithm(format!("{}-bit RSA", bits)))
+                }
+            };
+            if rsa.e() != BigNum::from_u32(RSA_EXPONENT)?.as_ref() {
+                return Err(PivError::UnsupportedAlgorithm(
+                    "RSA public exponent other than 65537".into(),
+                ));
+            }
+            let missing = || PivError::Crypto("RSA key has no CRT parameters".into());
+            // Each CRT value is padded to half the modulus length
+            let half = rsa.size() as i32 / 2;
+            let padded = |bn: Option<&BigNumRef>| -> Result<Zeroizing<Vec<u8>>, PivError> {
+                Ok(Zeroizing::new(bn.ok_or_else(missing)?.to_vec_padded(half)?))
+            };
+            let fields = vec![
+                (TAG_RSA_P, padded(rsa.p())?),
+                (TAG_RSA_Q, padded(rsa.q())?),
+                (TAG_RSA_DP, padded(rsa.dmp1())?),
+                (TAG_RSA_DQ, padded(rsa.dmq1())?),
+                (TAG_RSA_QINV, padded(rsa.iqmp())?),
+            ];
+            Ok((algorithm, fields))
+        }
+        Id::EC => {
+            let ec = key.ec_key()?;
+            let (algorithm, len) = match ec.group().curve_name() {
+                Some(Nid::X9_62_PRIME256V1) => (PivAlgorithm::EcP256, 32),
+                Some(Nid::SECP384R1) => (PivAlgorithm::EcP384, 48),
+                other => {
+                    return Err(PivError::UnsupportedAlgorithm(format!(
+                        "EC key on curve {:?}",
+                        other
+                    )))
+                }
+            };
+            let scalar = Zeroizing::new(ec.private_key().to_vec_padded(len)?);
+            Ok((algorithm, vec![(TAG_EC_PRIVATE, scalar)]))
+        }
+        Id::ED25519 => Ok((
+            PivAlgorithm::Ed25519,
+            vec![(TAG_ED25519_PRIVATE, Zeroizing::new(key.raw_private_key()?))],
+        )),
+        Id::X25519 => Ok((
+            PivAlgorithm::X25519,
+            vec![(TAG_X25519_PRIVATE, Zeroizing::new(key.raw_private_key()?))],
+        )),
+        other => Err(PivError::UnsupportedAlgorithm(format!("{:?} key", other))),
+    }
+}
+
+/// Convert an (unencrypted) OpenSSH private key for [`import_key`]. Keys
+/// in PEM or PKCS#8 form can be loaded with
+/// [`PKey::private_key_from_pem`] or [`PKey::private_key_from_pkcs8`].
+///
+/// [`import_key`]: crate::PivToken::import_key
+pub fn pkey_from_ssh(key: &PrivateKey) -> Result<PKey<Private>, PivError> {
+    let bad = |what: &str| PivError::Crypto(format!("malformed SSH {} key", what));
+    match key.key_data() {
+        KeypairData::Rsa(rsa) => {
+            let bn = |m: &ssh_key::Mpint| -> Result<BigNum, PivError> {
+                let bytes = m.as_positive_bytes().ok_or_else(|| bad("RSA"))?;
+                Ok(BigNum::from_slice(bytes)?)
+            };
+            let n = bn(&rsa.public.n)?;
+            let e = bn(&rsa.public.e)?;
+            let d = bn(&rsa.private.d)?;
+            let p = bn(&rsa.private.p)?;
+            let q = bn(&rsa.private.q)?;
+            let iqmp = bn(&rsa.private.iqmp)?;
+
+            // OpenSSH keys don't carry d mod (p-1) and d mod (q-1)
+            let mut ctx = BigNumContext::new_secure()?;
+            let one = BigNum::from_u32(1)?;
+            let mut p1 = BigNum::new_secure()?;
+            p1.checked_sub(&p, &one)?;
+            let mut q1 = BigNum::new_secure()?;
+            q1.checked_sub(&q, &one)?;
+            let mut dmp1 = BigNum::new_secure()?;
+            dmp1.nnmod(&d, &p1, &mut ctx)?;
+            let mut dmq1 = BigNum::new_secure()?;
+            dmq1.nnmod(&d, &q1, &mut ctx)?;
+
+            let rsa = Rsa::from_private_components(n, e, d, p, q, dmp1, dmq1, iqmp)?;
+            Ok(PKey::from_rsa(rsa)?)
+        }
+        KeypairData::Ecdsa(ec) => {
+            let nid = match ec {
+                EcdsaKeypair::NistP256 { .. } => Nid::X9_62_PRIME256V1,
+                EcdsaKeypair::NistP384 { .. } => Nid::SECP384R1,
+                EcdsaKeypair::NistP521 { .. } => {
+                    return Err(PivError::UnsupportedAlgorithm("ECDSA P-521".into()))
+                }
+            };
+            let group = EcGroup::from_curve_name(nid)?;
+            let mut ctx = BigNumContext::new()?;
+            let private = BigNum::from_slice(ec.private_key_bytes())?;
+            let public = EcPoint::from_bytes(&group, ec.public_key_bytes(), &mut ctx)?;
+            let ec = EcKey::from_private_components(&group, &private, &public)?;
+            ec.check_key()?;
+            Ok(PKey::from_ec_key(ec)?)
+        }
+        KeypairData::Ed25519(ed) => {
+            let seed = Zeroizing::new(ed.private.to_bytes());
+            Ok(PKey::private_key_from_raw_bytes(&*seed, Id::ED25519)?)
+        }
+        KeypairData::Encrypted(_) => {
+            Err(PivError::Other("SSH private key is encrypted; decrypt it first".into()))
+        }
+        other => Err(PivError::UnsupportedAlgorithm(format!(
+            "SSH {} key",
+            other.algorithm().map(|a| a.to_string()).unwrap_or_default()
+        ))),
+    }
+}
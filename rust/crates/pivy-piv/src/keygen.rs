@@ -0,0 +1,153 @@
+//! Public keys returned by GENERATE ASYMMETRIC KEY PAIR (INS 0x47).
+
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::rsa::Rsa;
+use ssh_key::public::{EcdsaPublicKey, Ed25519PublicKey, KeyData, RsaPublicKey};
+use ssh_key::PublicKey;
+
+use crate::error::PivError;
+use crate::slot::PivAlgorithm;
+use crate::tlv::TlvReader;
+
+/// Public key template tag (SP 800-73-4 part 2, table 10)
+pub const TAG_PUBLIC_KEY: u32 = 0x7F49;
+
+/// The public half of a key generated on the card.
+pub struct GeneratedKey {
+    algorithm: PivAlgorithm,
+    public_key: Option<PublicKey>,
+    spki: Vec<u8>,
+}
+
+impl GeneratedKey {
+    pub fn algorithm(&self) -> PivAlgorithm {
+        self.algorithm
+    }
+
+    /// SSH form of the key. X25519 keys have none, as they can't sign.
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    /// DER-encoded SubjectPublicKeyInfo, e.g. for building a certificate.
+    pub fn spki(&self) -> &[u8] {
+        &self.spki
+    }
+
+    /// The key as an OpenSSL public key.
+    pub fn pkey(&self) -> Result<PKey<Public>, PivError> {
+        Ok(PKey::public_key_from_der(&self.spki)?)
+    }
+}
+
+/// Parse the 0x7F49 template the card returns for a new key:
+/// RSA keys have modulus (0x81) and exponent (0x82), EC keys an
+/// uncompressed point (0x86), Ed25519/X25519 the raw 32-byte key (0x86).
+pub fn parse_public_key_template(
+    algorithm: PivAlgorithm,
+    data: &[u8],
+) -> Result<GeneratedKey, PivError> {
+    let mut reader = TlvReader::new(data);
+    let tag = reader.read_tag()?;
+    if tag != TAG_PUBLIC_KEY {
+        return Err(PivError::Tlv {
+            message: format!("expected public key tag 0x7F49, got {:#X}", tag),
+        });
+    }
+    parse_public_key_fields(algorithm, reader.read_value()?)
+}
+
+/// Parse the contents of a public key template without the 0x7F49
+/// wrapper, as YubiKey GET METADATA returns them.
+pub fn parse_public_key_fields(
+    algorithm: PivAlgorithm,
+    data: &[u8],
+) -> Result<GeneratedKey, PivError> {
+    let mut inner = TlvReader::new(data);
+    let mut modulus = None;
+    let mut exponent = None;
+    let mut point = None;
+    while inner.has_remaining() {
+        let tag = inner.read_tag()?;
+        let value = inner.read_value()?;
+        match tag {
+            0x81 => modulus = Some(value),
+            0x82 => exponent = Some(value),
+            0x86 => point = Some(value),
+            _ => {
+                return Err(PivError::Tlv {
+                    message: format!("unexpected tag {:#X} in public key template", tag),
+                })
+            }
+        }
+    }
+    let missing = |tag: u8| PivError::Tlv {
+        message: format!("public key template has no tag {:#04X}", tag),
+    };
+
+    let (pkey, key_data): (PKey<Public>, Option<KeyData>) = match algorithm {
+        PivAlgorithm::Rsa1024 | PivAlgorithm::Rsa2048 => {
+            let n = modulus.ok_or_else(|| missing(0x81))?;
+            let e = exponent.ok_or_else(|| missing(0x82))?;
+            let rsa = Rsa::from_public_components(BigNum::from_slice(n)?, BigNum::from_slice(e)?)?;
+            let expected = if algorithm == PivAlgorithm::Rsa1024 { 1024 } else { 2048 };
+            if rsa.size() * 8 != expected {
+                return Err(PivError::Crypto(format!(
+                    "card returned a {}-bit RSA key, expected {}",
+                    rsa.size() * 8,
+                    expected
+                )));
+            }
+            let key_data = KeyData::Rsa(RsaPublicKey {
+                e: ssh_key::Mpint::from_positive_bytes(e)
+                    .map_err(|e| PivError::Crypto(e.to_string()))?,
+                n: ssh_key::Mpint::from_positive_bytes(n)
+                    .map_err(|e| PivError::Crypto(e.to_string()))?,
+            });
+            (PKey::from_rsa(rsa)?, Some(key_data))
+        }
+        PivAlgorithm::EcP256 | PivAlgorithm::EcP384 => {
+            let point_bytes = point.ok_or_else(|| missing(0x86))?;
+            let nid = if algorithm == PivAlgorithm::EcP256 {
+                Nid::X9_62_PRIME256V1
+            } else {
+                Nid::SECP384R1
+            };
+            let group = EcGroup::from_curve_name(nid)?;
+            let mut ctx = BigNumContext::new()?;
+            let ec_point = EcPoint::from_bytes(&group, point_bytes, &mut ctx)?;
+            let ec = EcKey::from_public_key(&group, &ec_point)?;
+            ec.check_key()?;
+            let ec_key = EcdsaPublicKey::from_sec1_bytes(point_bytes)
+                .map_err(|e| PivError::Crypto(e.to_string()))?;
+            (PKey::from_ec_key(ec)?, Some(KeyData::Ecdsa(ec_key)))
+        }
+        PivAlgorithm::Ed25519 | PivAlgorithm::X25519 => {
+            let raw = point.ok_or_else(|| missing(0x86))?;
+            if raw.len() != 32 {
+                return Err(PivError::Crypto(format!(
+                    "{:?} public key is {} bytes, expected 32",
+                    algorithm,
+                    raw.len()
+                )));
+            }
+            if algorithm == PivAlgorithm::Ed25519 {
+                let pkey = PKey::public_key_from_raw_bytes(raw, Id::ED25519)?;
+                let key = Ed25519PublicKey::try_from(raw)
+                    .map_err(|e| PivError::Crypto(e.to_string()))?;
+                (pkey, Some(KeyData::Ed25519(key)))
+            } else {
+                (PKey::public_key_from_raw_bytes(raw, Id::X25519)?, None)
+            }
+        }
+    };
+
+    Ok(GeneratedKey {
+        algorithm,
+        public_key: key_data.map(|k| PublicKey::new(k, "")),
+        spki: pkey.public_key_to_der()?,
+    })
+}
@@ -0,0 +1,115 @@
+//! Key History object (0x5FC10C).
+//!
+//! Counts the retired key management keys the card holds: those whose
+//! certificates are on the card occupy the retired slots from 82 upwards,
+//! so readers only need to look at that many slots. Keys whose
+//! certificates live off the card are found through a URL instead. See
+//! NIST SP 800-73-4 part 1, section 3.3.1.
+
+use crate::apdu::slot_id;
+use crate::error::PivError;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Key History data object tag (NIST SP 800-73-4)
+pub const PIV_TAG_KEYHIST: u32 = 0x5FC10C;
+
+const TAG_ON_CARD: u32 = 0xC1;
+const TAG_OFF_CARD: u32 = 0xC2;
+const TAG_OFF_CARD_URL: u32 = 0xF3;
+/// Error Detection Code, skipped
+const TAG_LRC: u32 = 0xFE;
+
+/// Number of retired key management slots (82-95)
+pub const MAX_RETIRED_KEYS: u8 = slot_id::RETIRED_20 - slot_id::RETIRED_1 + 1;
+
+/// Contents of a Key History object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyHistory {
+    /// Retired keys with certificates on the card
+    pub on_card: u8,
+    /// Retired keys with certificates only at `off_card_url`
+    pub off_card: u8,
+    pub off_card_url: Option<String>,
+}
+
+impl KeyHistory {
+    pub fn new(on_card: u8, off_card: u8, off_card_url: Option<&str>) -> Result<Self, PivError> {
+        let hist = Self {
+            on_card,
+            off_card,
+            off_card_url: off_card_url.map(str::to_string),
+        };
+        hist.validate()?;
+        Ok(hist)
+    }
+
+    /// Parse the content of the object's 0x53 wrapper.
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut hist = Self::default();
+        let mut reader = TlvReader::new(data);
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match tag {
+                TAG_ON_CARD => hist.on_card = count(tag, value)?,
+                TAG_OFF_CARD => hist.off_card = count(tag, value)?,
+                TAG_OFF_CARD_URL => {
+                    let url = String::from_utf8(value.to_vec()).map_err(|_| PivError::Tlv {
+                        message: "key history URL is not text".into(),
+                    })?;
+                    hist.off_card_url = Some(url);
+                }
+                TAG_LRC => {}
+                _ => {
+                    return Err(PivError::Tlv {
+                        message: format!("unexpected tag {:#04X} in key history", tag),
+                    })
+                }
+            }
+        }
+        Ok(hist)
+    }
+
+    /// Encode as the content of the object's 0x53 wrapper.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut tlv = TlvWriter::new();
+        tlv.write_tag_value(TAG_ON_CARD, &[self.on_card]);
+        tlv.write_tag_value(TAG_OFF_CARD, &[self.off_card]);
+        if let Some(url) = &self.off_card_url {
+            tlv.write_tag_value(TAG_OFF_CARD_URL, url.as_bytes());
+        }
+        tlv.into_vec()
+    }
+
+    /// Check the counts fit in the retired slots, and that there's a URL
+    /// if any certificates are off the card.
+    pub fn validate(&self) -> Result<(), PivError> {
+        if self.on_card as u32 + self.off_card as u32 > MAX_RETIRED_KEYS as u32 {
+            return Err(PivError::Other(format!(
+                "key history counts {} on-card + {} off-card keys, at most {} allowed",
+                self.on_card, self.off_card, MAX_RETIRED_KEYS
+            )));
+        }
+        if self.off_card > 0 && self.off_card_url.is_none() {
+            return Err(PivError::Other(
+                "key history needs a URL for off-card certificates".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The retired slots holding keys with on-card certificates.
+    pub fn on_card_slots(&self) -> impl Iterator<Item = u8> {
+        let count = self.on_card.min(MAX_RETIRED_KEYS);
+        (0..count).map(|i| slot_id::RETIRED_1 + i)
+    }
+}
+
+fn count(tag: u32, value: &[u8]) -> Result<u8, PivError> {
+    match value {
+        [n] => Ok(*n),
+        _ => Err(PivError::Tlv {
+            message: format!("key history tag {:#04X} has bad length {}", tag, value.len()),
+        }),
+    }
+}
@@ -1,14 +1,43 @@
+pub mod admin;
 pub mod apdu;
+pub mod attest;
+pub mod cardcap;
 pub mod cert;
+pub mod chuid;
 pub mod context;
+pub mod discovery;
+pub mod ecdh_box;
 pub mod error;
+pub mod fascn;
 pub mod guid;
+pub mod import;
+pub mod keygen;
+pub mod keyhist;
+pub mod object;
+pub mod pin;
+pub mod pinfo;
+pub mod rsa;
 pub mod slot;
 pub mod tlv;
 pub mod token;
+pub mod transport;
+pub mod ykpiv;
 
-pub use context::PivContext;
+pub use attest::Attestation;
+pub use cardcap::CardCapability;
+pub use chuid::Chuid;
+pub use context::{PivContext, ReaderEvent, ReaderMonitor};
+pub use discovery::Discovery;
+pub use ecdh_box::EcdhBox;
 pub use error::PivError;
+pub use fascn::Fascn;
 pub use guid::Guid;
-pub use slot::{PivAlgorithm, PivSlot};
-pub use token::PivToken;
+pub use keygen::GeneratedKey;
+pub use keyhist::KeyHistory;
+pub use object::PivObject;
+pub use pin::{PinStatus, PinType};
+pub use pinfo::PrintedInfo;
+pub use slot::{KeyPolicy, PinPolicy, PivAlgorithm, PivSlot, TouchPolicy};
+pub use token::{PivToken, PivTransaction};
+pub use transport::{CardTransport, Disposition, PcscTransport};
+pub use ykpiv::{KeyOrigin, SlotMetadata, Version};
@@ -0,0 +1,120 @@
+//! Registry of the PIV data objects (SP 800-73-4 part 1, table 3).
+//!
+//! Lists every container a PIV card can hold with who may read it, so
+//! tools can walk a card's objects (to dump or back them up) without
+//! hard-coding tags. All objects are written with PUT DATA after card
+//! management (9B) authentication.
+
+use crate::cardcap::PIV_TAG_CARDCAP;
+use crate::chuid::PIV_TAG_CHUID;
+use crate::discovery::PIV_TAG_DISCOVERY;
+use crate::keyhist::PIV_TAG_KEYHIST;
+use crate::pinfo::PIV_TAG_PRINTED_INFO;
+
+pub const PIV_TAG_CERT_9A: u32 = 0x5FC105;
+pub const PIV_TAG_CERT_9C: u32 = 0x5FC10A;
+pub const PIV_TAG_CERT_9D: u32 = 0x5FC10B;
+pub const PIV_TAG_CERT_9E: u32 = 0x5FC101;
+pub const PIV_TAG_FINGERPRINTS: u32 = 0x5FC103;
+pub const PIV_TAG_SECURITY_OBJECT: u32 = 0x5FC106;
+pub const PIV_TAG_FACIAL_IMAGE: u32 = 0x5FC108;
+/// Retired key management certificates 1-20 are 0x5FC10D-0x5FC120
+pub const PIV_TAG_CERT_RETIRED_1: u32 = 0x5FC10D;
+pub const PIV_TAG_IRIS_IMAGES: u32 = 0x5FC121;
+/// Biometric Information Templates Group Template; like the Discovery
+/// object it isn't wrapped in 0x53
+pub const PIV_TAG_BITGT: u32 = 0x7F61;
+pub const PIV_TAG_SM_CERT_SIGNER: u32 = 0x5FC122;
+pub const PIV_TAG_PAIRING_CODE: u32 = 0x5FC123;
+
+/// Who may read an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessRule {
+    Always,
+    /// PIN (or on-card biometric comparison) verified first
+    Pin,
+}
+
+/// A PIV data object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PivObject {
+    pub tag: u32,
+    pub name: &'static str,
+    pub read: AccessRule,
+    /// Must be present on a conforming card
+    pub mandatory: bool,
+}
+
+impl PivObject {
+    /// Whether the object's contents sit in a 0x53 wrapper (everything
+    /// but the Discovery object and BIT Group Template).
+    pub fn is_wrapped(&self) -> bool {
+        is_wrapped(self.tag)
+    }
+}
+
+const fn object(tag: u32, name: &'static str, read: AccessRule, mandatory: bool) -> PivObject {
+    PivObject {
+        tag,
+        name,
+        read,
+        mandatory,
+    }
+}
+
+const fn retired(n: u32, name: &'static str) -> PivObject {
+    object(PIV_TAG_CERT_RETIRED_1 + n - 1, name, AccessRule::Always, false)
+}
+
+use AccessRule::{Always, Pin};
+
+/// Every SP 800-73-4 data object, in table order.
+pub const OBJECTS: &[PivObject] = &[
+    object(PIV_TAG_CARDCAP, "Card Capability Container", Always, true),
+    object(PIV_TAG_CHUID, "Card Holder Unique Identifier", Always, true),
+    object(PIV_TAG_CERT_9A, "X.509 Certificate for PIV Authentication", Always, true),
+    object(PIV_TAG_FINGERPRINTS, "Cardholder Fingerprints", Pin, true),
+    object(PIV_TAG_SECURITY_OBJECT, "Security Object", Always, true),
+    object(PIV_TAG_FACIAL_IMAGE, "Cardholder Facial Image", Pin, false),
+    object(PIV_TAG_CERT_9E, "X.509 Certificate for Card Authentication", Always, false),
+    object(PIV_TAG_CERT_9C, "X.509 Certificate for Digital Signature", Always, false),
+    object(PIV_TAG_CERT_9D, "X.509 Certificate for Key Management", Always, false),
+    object(PIV_TAG_PRINTED_INFO, "Printed Information", Pin, false),
+    object(PIV_TAG_DISCOVERY, "Discovery Object", Always, false),
+    object(PIV_TAG_KEYHIST, "Key History Object", Always, false),
+    retired(1, "Retired X.509 Certificate for Key Management 1"),
+    retired(2, "Retired X.509 Certificate for Key Management 2"),
+    retired(3, "Retired X.509 Certificate for Key Management 3"),
+    retired(4, "Retired X.509 Certificate for Key Management 4"),
+    retired(5, "Retired X.509 Certificate for Key Management 5"),
+    retired(6, "Retired X.509 Certificate for Key Management 6"),
+    retired(7, "Retired X.509 Certificate for Key Management 7"),
+    retired(8, "Retired X.509 Certificate for Key Management 8"),
+    retired(9, "Retired X.509 Certificate for Key Management 9"),
+    retired(10, "Retired X.509 Certificate for Key Management 10"),
+    retired(11, "Retired X.509 Certificate for Key Management 11"),
+    retired(12, "Retired X.509 Certificate for Key Management 12"),
+    retired(13, "Retired X.509 Certificate for Key Management 13"),
+    retired(14, "Retired X.509 Certificate for Key Management 14"),
+    retired(15, "Retired X.509 Certificate for Key Management 15"),
+    retired(16, "Retired X.509 Certificate for Key Management 16"),
+    retired(17, "Retired X.509 Certificate for Key Management 17"),
+    retired(18, "Retired X.509 Certificate for Key Management 18"),
+    retired(19, "Retired X.509 Certificate for Key Management 19"),
+    retired(20, "Retired X.509 Certificate for Key Management 20"),
+    object(PIV_TAG_IRIS_IMAGES, "Cardholder Iris Images", Pin, false),
+    object(PIV_TAG_BITGT, "Biometric Information Templates Group Template", Always, false),
+    object(PIV_TAG_SM_CERT_SIGNER, "Secure Messaging Certificate Signer", Always, false),
+    object(PIV_TAG_PAIRING_CODE, "Pairing Code Reference Data Container", Pin, false),
+];
+
+/// Look up an object by tag.
+pub fn lookup(tag: u32) -> Option<&'static PivObject> {
+    OBJECTS.iter().find(|o| o.tag == tag)
+}
+
+/// Whether an object with this tag sits in a 0x53 wrapper. Tags outside
+/// the registry (vendor objects) are assumed to.
+pub fn is_wrapped(tag: u32) -> bool {
+    tag != PIV_TAG_DISCOVERY && tag != PIV_TAG_BITGT
+}
@@ -0,0 +1,51 @@
+//! PIN references and retry-counter status (SP 800-73-4 part 2, 3.2).
+
+use zeroize::Zeroizing;
+
+use crate::error::PivError;
+
+/// Which PIN (key reference, sent as P2) a command applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinType {
+    /// PIV application PIN, local to the PIV applet (0x80)
+    Piv,
+    /// Card-wide Global PIN shared by all applets (0x00)
+    Global,
+    /// PIN Unblocking Key, used to reset a forgotten or blocked PIN (0x81)
+    Puk,
+}
+
+impl PinType {
+    pub fn reference(&self) -> u8 {
+        match self {
+            PinType::Piv => 0x80,
+            PinType::Global => 0x00,
+            PinType::Puk => 0x81,
+        }
+    }
+}
+
+/// Result of an empty VERIFY, which reports status without using up an
+/// attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinStatus {
+    /// Already verified in this session
+    Verified,
+    /// Not verified; this many attempts remain before the PIN blocks
+    Unverified { retries: u32 },
+    Blocked,
+}
+
+/// Pad a PIN or PUK to the 8-byte reference data format (0xFF filled).
+pub(crate) fn pad(pin: &str) -> Result<Zeroizing<[u8; 8]>, PivError> {
+    let bytes = pin.as_bytes();
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Err(PivError::Other(format!(
+            "PIN must be 1-8 characters, got {}",
+            bytes.len()
+        )));
+    }
+    let mut padded = Zeroizing::new([0xFF_u8; 8]);
+    padded[..bytes.len()].copy_from_slice(bytes);
+    Ok(padded)
+}
@@ -0,0 +1,235 @@
+//! PIV Printed Information object (0x5FC109).
+//!
+//! Besides the fields printed on a physical card, YubiKey tools keep a
+//! "PIN-protected management key" here under tag 0x88 { 0x89 key }: the
+//! object can only be read after PIN verification, so storing the 9B key
+//! in it lets a user administer the card knowing only their PIN. pivy also
+//! keeps named values under tag 0x90.
+
+use zeroize::Zeroizing;
+
+use crate::error::PivError;
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Printed Information data object tag (NIST SP 800-73-4)
+pub const PIV_TAG_PRINTED_INFO: u32 = 0x5FC109;
+
+const TAG_NAME: u32 = 0x01;
+const TAG_AFFILIATION: u32 = 0x02;
+const TAG_EXPIRY: u32 = 0x04;
+const TAG_SERIAL: u32 = 0x05;
+const TAG_ISSUER: u32 = 0x06;
+const TAG_ORG_LINE_1: u32 = 0x07;
+const TAG_ORG_LINE_2: u32 = 0x08;
+/// Yubico extensions: 0x88 { 0x89 management key }
+const TAG_YUBICO: u32 = 0x88;
+const TAG_YUBICO_ADMIN_KEY: u32 = 0x89;
+/// pivy extension: 0x90 { 0x01 name, 0x02..0x05 value }
+const TAG_KV: u32 = 0x90;
+const TAG_KV_NAME: u32 = 0x01;
+const TAG_KV_BOOL: u32 = 0x02;
+const TAG_KV_UINT: u32 = 0x03;
+const TAG_KV_STRING: u32 = 0x04;
+const TAG_KV_DATA: u32 = 0x05;
+
+/// A named value stored by pivy in the printed information object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinfoValue {
+    /// A flag, set by being present
+    Bool,
+    Uint(u32),
+    String(String),
+    Data(Zeroizing<Vec<u8>>),
+}
+
+/// Contents of the Printed Information object. All fields are optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrintedInfo {
+    pub name: Option<String>,
+    pub affiliation: Option<String>,
+    pub expiry: Option<String>,
+    pub serial: Option<String>,
+    pub issuer: Option<String>,
+    pub org_line_1: Option<String>,
+    pub org_line_2: Option<String>,
+    admin_key: Option<Zeroizing<Vec<u8>>>,
+    kv: Vec<(String, PinfoValue)>,
+}
+
+impl PrintedInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the content of the object's 0x53 wrapper.
+    pub fn decode(data: &[u8]) -> Result<Self, PivError> {
+        let mut info = Self::new();
+        let mut reader = TlvReader::new(data);
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match tag {
+                TAG_NAME => info.name = Some(utf8(value)?),
+                TAG_AFFILIATION => info.affiliation = Some(utf8(value)?),
+                TAG_EXPIRY => info.expiry = Some(utf8(value)?),
+                TAG_SERIAL => info.serial = Some(utf8(value)?),
+                TAG_ISSUER => info.issuer = Some(utf8(value)?),
+                TAG_ORG_LINE_1 => info.org_line_1 = Some(utf8(value)?),
+                TAG_ORG_LINE_2 => info.org_line_2 = Some(utf8(value)?),
+                TAG_YUBICO => {
+                    let mut ext = TlvReader::new(value);
+                    while ext.has_remaining() {
+                        let tag = ext.read_tag()?;
+                        let value = ext.read_value()?;
+                        if tag != TAG_YUBICO_ADMIN_KEY {
+                            return Err(unexpected("Yubico extension", tag));
+                        }
+                        info.admin_key = Some(Zeroizing::new(value.to_vec()));
+                    }
+                }
+                TAG_KV => info.kv.push(decode_kv(value)?),
+                // Tags from later revisions of the object are ignored
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    /// Encode as the content of the object's 0x53 wrapper. The result may
+    /// contain the management key.
+    pub fn encode(&self) -> Zeroizing<Vec<u8>> {
+        let mut tlv = TlvWriter::with_capacity(self.encoded_len_hint());
+        let strings = [
+            (TAG_NAME, &self.name),
+            (TAG_AFFILIATION, &self.affiliation),
+            (TAG_EXPIRY, &self.expiry),
+            (TAG_SERIAL, &self.serial),
+            (TAG_ISSUER, &self.issuer),
+            (TAG_ORG_LINE_1, &self.org_line_1),
+            (TAG_ORG_LINE_2, &self.org_line_2),
+        ];
+        for (tag, value) in strings {
+            if let Some(value) = value {
+                tlv.write_tag_value(tag, value.as_bytes());
+            }
+        }
+        if let Some(key) = self.admin_key.as_ref().filter(|k| !k.is_empty()) {
+            let mut ext = TlvWriter::with_capacity(key.len() + 4);
+            ext.write_tag_value(TAG_YUBICO_ADMIN_KEY, key);
+            let ext = Zeroizing::new(ext.into_vec());
+            tlv.write_tag_value(TAG_YUBICO, &ext);
+        }
+        for (name, value) in &self.kv {
+            let mut kv = TlvWriter::new();
+            kv.write_tag_value(TAG_KV_NAME, name.as_bytes());
+            match value {
+                PinfoValue::Bool => kv.write_tag_value(TAG_KV_BOOL, &[]),
+                PinfoValue::Uint(v) => kv.write_tag_value(TAG_KV_UINT, &minimal_be(*v)),
+                PinfoValue::String(s) => kv.write_tag_value(TAG_KV_STRING, s.as_bytes()),
+                PinfoValue::Data(d) => kv.write_tag_value(TAG_KV_DATA, d),
+            }
+            let kv = Zeroizing::new(kv.into_vec());
+            tlv.write_tag_value(TAG_KV, &kv);
+        }
+        Zeroizing::new(tlv.into_vec())
+    }
+
+    /// Upper bound on the encoded size, so `encode` never reallocates a
+    /// buffer holding the management key.
+    fn encoded_len_hint(&self) -> usize {
+        let strings = [
+            &self.name,
+            &self.affiliation,
+            &self.expiry,
+            &self.serial,
+            &self.issuer,
+            &self.org_line_1,
+            &self.org_line_2,
+        ];
+        let strings: usize = strings.iter().flat_map(|s| s.as_ref()).map(|s| s.len() + 4).sum();
+        let key = self.admin_key.as_ref().map_or(0, |k| k.len() + 8);
+        let kv: usize = self
+            .kv
+            .iter()
+            .map(|(name, value)| {
+                let len = match value {
+                    PinfoValue::Bool => 0,
+                    PinfoValue::Uint(_) => 4,
+                    PinfoValue::String(s) => s.len(),
+                    PinfoValue::Data(d) => d.len(),
+                };
+                name.len() + len + 12
+            })
+            .sum();
+        strings + key + kv
+    }
+
+    /// The PIN-protected management key, if one is stored.
+    pub fn admin_key(&self) -> Option<&[u8]> {
+        self.admin_key.as_deref().map(Vec::as_slice).filter(|k| !k.is_empty())
+    }
+
+    pub fn set_admin_key(&mut self, key: &[u8]) {
+        self.admin_key = Some(Zeroizing::new(key.to_vec()));
+    }
+
+    pub fn clear_admin_key(&mut self) {
+        self.admin_key = None;
+    }
+
+    pub fn kv(&self, name: &str) -> Option<&PinfoValue> {
+        self.kv.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Set a named value, replacing any existing value of that name.
+    pub fn set_kv(&mut self, name: &str, value: PinfoValue) {
+        match self.kv.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = value,
+            None => self.kv.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn unset_kv(&mut self, name: &str) {
+        self.kv.retain(|(n, _)| n != name);
+    }
+}
+
+fn decode_kv(data: &[u8]) -> Result<(String, PinfoValue), PivError> {
+    let mut reader = TlvReader::new(data);
+    let tag = reader.read_tag()?;
+    if tag != TAG_KV_NAME {
+        return Err(unexpected("key/value entry", tag));
+    }
+    let name = utf8(reader.read_value()?)?;
+    let tag = reader.read_tag()?;
+    let value = reader.read_value()?;
+    let value = match tag {
+        TAG_KV_BOOL => PinfoValue::Bool,
+        TAG_KV_UINT if (1..=4).contains(&value.len()) => {
+            PinfoValue::Uint(value.iter().fold(0u32, |acc, &b| acc << 8 | b as u32))
+        }
+        TAG_KV_STRING => PinfoValue::String(utf8(value)?),
+        TAG_KV_DATA => PinfoValue::Data(Zeroizing::new(value.to_vec())),
+        _ => return Err(unexpected("key/value entry", tag)),
+    };
+    Ok((name, value))
+}
+
+/// Big-endian bytes of `v` without leading zeros (at least one byte).
+fn minimal_be(v: u32) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn utf8(value: &[u8]) -> Result<String, PivError> {
+    String::from_utf8(value.to_vec()).map_err(|_| PivError::Tlv {
+        message: "printed information field is not UTF-8".into(),
+    })
+}
+
+fn unexpected(what: &str, tag: u32) -> PivError {
+    PivError::Tlv {
+        message: format!("unexpected tag {:#04X} in printed information {}", tag, what),
+    }
+}
@@ -0,0 +1,119 @@
+//! Padding removal for RSA key transport (RFC 8017 section 7).
+//!
+//! The card only does the raw private-key operation, so the encryption
+//! padding is checked here. The checks run in constant time over the
+//! whole block so that callers can't be turned into a padding oracle;
+//! only the final accept/reject (and the message length) is observable.
+
+use openssl::hash::{hash, MessageDigest};
+use zeroize::Zeroizing;
+
+use crate::error::PivError;
+
+/// 0xFF if `x` is zero, else 0x00.
+fn ct_is_zero(x: u8) -> u8 {
+    ((x as u32).wrapping_sub(1) >> 8) as u8
+}
+
+fn ct_eq(a: u8, b: u8) -> u8 {
+    ct_is_zero(a ^ b)
+}
+
+/// 0xFF if `a < b`, else 0x00. Both must be below `usize::MAX / 2`.
+fn ct_lt(a: usize, b: usize) -> u8 {
+    0u8.wrapping_sub((a.wrapping_sub(b) >> (usize::BITS - 1)) as u8)
+}
+
+/// `a` if `mask` is 0xFF, `b` if it is 0x00.
+fn ct_select(mask: u8, a: usize, b: usize) -> usize {
+    let m = ((mask & 1) as usize).wrapping_neg();
+    (a & m) | (b & !m)
+}
+
+/// Strip PKCS#1 v1.5 encryption padding: `00 02 PS 00 M`, where PS is
+/// at least 8 non-zero bytes. Returns `None` if the block is malformed.
+pub fn unpad_pkcs1_v15(em: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    if em.len() < 11 {
+        return None;
+    }
+    let mut good = ct_is_zero(em[0]) & ct_eq(em[1], 0x02);
+
+    // Find the first zero byte after the block type
+    let mut looking = 0xFF_u8;
+    let mut zero_index = 0;
+    for (i, &b) in em.iter().enumerate().skip(2) {
+        let is_zero = ct_is_zero(b);
+        zero_index = ct_select(looking & is_zero, i, zero_index);
+        looking &= !is_zero;
+    }
+    good &= !looking;
+    good &= !ct_lt(zero_index, 2 + 8);
+
+    if good != 0xFF {
+        return None;
+    }
+    Some(Zeroizing::new(em[zero_index + 1..].to_vec()))
+}
+
+/// Strip OAEP padding (empty label, MGF1 with the same digest).
+/// Returns `None` if the block is malformed.
+pub fn unpad_oaep(
+    em: &[u8],
+    digest: MessageDigest,
+) -> Result<Option<Zeroizing<Vec<u8>>>, PivError> {
+    let h_len = digest.size();
+    if em.len() < 2 * h_len + 2 {
+        return Ok(None);
+    }
+    let (masked_seed, masked_db) = em[1..].split_at(h_len);
+
+    let mut seed = mgf1(digest, masked_db, h_len)?;
+    xor_in_place(&mut seed, masked_seed);
+    let mut db = mgf1(digest, &seed, masked_db.len())?;
+    xor_in_place(&mut db, masked_db);
+
+    let l_hash = hash(digest, &[])?;
+    let mut good = ct_is_zero(em[0]);
+    for (a, b) in db.iter().zip(l_hash.iter()) {
+        good &= ct_eq(*a, *b);
+    }
+
+    // After lHash: zero or more 0x00 bytes, then 0x01, then M
+    let mut looking = 0xFF_u8;
+    let mut one_index = 0;
+    for (i, &b) in db.iter().enumerate().skip(h_len) {
+        let is_one = ct_eq(b, 0x01);
+        let is_zero = ct_is_zero(b);
+        one_index = ct_select(looking & is_one, i, one_index);
+        good &= !(looking & !is_one & !is_zero);
+        looking &= !is_one;
+    }
+    good &= !looking;
+
+    if good != 0xFF {
+        return Ok(None);
+    }
+    Ok(Some(Zeroizing::new(db[one_index + 1..].to_vec())))
+}
+
+/// MGF1 mask generation (RFC 8017 appendix B.2.1).
+fn mgf1(digest: MessageDigest, seed: &[u8], len: usize) -> Result<Zeroizing<Vec<u8>>, PivError> {
+    let mut mask = Zeroizing::new(Vec::with_capacity(len + digest.size()));
+    let mut input = Zeroizing::new(Vec::with_capacity(seed.len() + 4));
+    let mut counter: u32 = 0;
+    while mask.len() < len {
+        input.clear();
+        input.extend_from_slice(seed);
+        input.extend_from_slice(&counter.to_be_bytes());
+        mask.extend_from_slice(&hash(digest, &input)?);
+        counter += 1;
+    }
+    mask.truncate(len);
+    Ok(mask)
+}
+
+fn xor_in_place(dst: &mut [u8], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
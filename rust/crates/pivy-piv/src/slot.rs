@@ -1,5 +1,7 @@
 use ssh_key::PublicKey;
 
+use crate::object;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PivAlgorithm {
     Rsa1024,
@@ -7,6 +9,8 @@ pub enum PivAlgorithm {
     EcP256,
     EcP384,
     Ed25519,
+    /// Key agreement only; can't sign, so never has an SSH identity
+    X25519,
 }
 
 impl PivAlgorithm {
@@ -17,8 +21,93 @@ impl PivAlgorithm {
             PivAlgorithm::EcP256 => 0x11,
             PivAlgorithm::EcP384 => 0x14,
             PivAlgorithm::Ed25519 => 0x22,
+            PivAlgorithm::X25519 => 0x23,
+        }
+    }
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0x06 => Some(PivAlgorithm::Rsa1024),
+            0x07 => Some(PivAlgorithm::Rsa2048),
+            0x11 => Some(PivAlgorithm::EcP256),
+            0x14 => Some(PivAlgorithm::EcP384),
+            0x22 => Some(PivAlgorithm::Ed25519),
+            0x23 => Some(PivAlgorithm::X25519),
+            _ => None,
+        }
+    }
+}
+
+/// YubiKey PIN policy for a key slot (tag 0xAA on generate/import)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PinPolicy {
+    /// Whatever the card uses for the slot (9E: never, 9C: always, else once)
+    #[default]
+    Default,
+    Never,
+    Once,
+    Always,
+}
+
+impl PinPolicy {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            PinPolicy::Default => 0x00,
+            PinPolicy::Never => 0x01,
+            PinPolicy::Once => 0x02,
+            PinPolicy::Always => 0x03,
+        }
+    }
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0x00 => Some(PinPolicy::Default),
+            0x01 => Some(PinPolicy::Never),
+            0x02 => Some(PinPolicy::Once),
+            0x03 => Some(PinPolicy::Always),
+            _ => None,
+        }
+    }
+}
+
+/// YubiKey touch policy for a key slot (tag 0xAB on generate/import)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TouchPolicy {
+    #[default]
+    Default,
+    Never,
+    Always,
+    /// Touch is remembered for 15 seconds (YubiKey 4.3 and later)
+    Cached,
+}
+
+impl TouchPolicy {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            TouchPolicy::Default => 0x00,
+            TouchPolicy::Never => 0x01,
+            TouchPolicy::Always => 0x02,
+            TouchPolicy::Cached => 0x03,
         }
     }
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0x00 => Some(TouchPolicy::Default),
+            0x01 => Some(TouchPolicy::Never),
+            0x02 => Some(TouchPolicy::Always),
+            0x03 => Some(TouchPolicy::Cached),
+            _ => None,
+        }
+    }
+}
+
+/// PIN and touch policies to apply to a new key. Both are YubiKey
+/// extensions; leaving them at `Default` sends plain SP 800-73 commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyPolicy {
+    pub pin: PinPolicy,
+    pub touch: TouchPolicy,
 }
 
 pub struct PivSlot {
@@ -54,6 +143,8 @@ impl PivSlot {
         self.public_key.to_openssh().unwrap_or_default()
     }
 
+    /// The slot's certificate; empty for a key found through YubiKey
+    /// metadata with no certificate written yet.
     pub fn cert_der(&self) -> &[u8] {
         &self.cert_der
     }
@@ -62,11 +153,13 @@ impl PivSlot {
 /// Map PIV slot ID to the data object tag for its certificate
 pub fn slot_to_cert_tag(slot_id: u8) -> Option<u32> {
     match slot_id {
-        0x9A => Some(0x5FC105),
-        0x9C => Some(0x5FC10A),
-        0x9D => Some(0x5FC10B),
-        0x9E => Some(0x5FC101),
-        0x82..=0x95 => Some(0x5FC10D + (slot_id - 0x82) as u32),
+        0x9A => Some(object::PIV_TAG_CERT_9A),
+        0x9C => Some(object::PIV_TAG_CERT_9C),
+        0x9D => Some(object::PIV_TAG_CERT_9D),
+        0x9E => Some(object::PIV_TAG_CERT_9E),
+        0x82..=0x95 => Some(object::PIV_TAG_CERT_RETIRED_1 + (slot_id - 0x82) as u32),
+        // YubiKey attestation key's certificate
+        0xF9 => Some(0x5FFF01),
         _ => None,
     }
 }
@@ -131,6 +131,14 @@ impl TlvWriter {
         Self { buf: Vec::new() }
     }
 
+    /// A writer whose buffer never reallocates (and so never leaves stray
+    /// copies of sensitive data behind) while under `capacity` bytes.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
     pub fn write_tag_value(&mut self, tag: u32, value: &[u8]) {
         self.write_tag(tag);
         self.write_length(value.len());
@@ -1,69 +1,204 @@
-use pcsc::{Protocols, ShareMode};
+use std::ops::{Deref, DerefMut};
 
-use crate::apdu::{ga_tag, Apdu, StatusWord, PIV_AID};
+use openssl::bn::BigNumContext;
+use openssl::ec::PointConversionForm;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKeyRef, Private, Public};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::admin;
+use crate::cardcap::{self, CardCapability};
+use crate::apdu::{
+    alg, ga_tag, ins, slot_id, sw, yk_ins, Apdu, StatusWord, PIV_AID, SHORT_MAX_DATA, YKPIV_AID,
+};
 use crate::cert;
+use crate::chuid::{self, Chuid};
+use crate::discovery::{self, Discovery};
+use crate::ecdh_box::EcdhBox;
 use crate::error::PivError;
 use crate::guid::Guid;
-use crate::slot::{self, PivSlot};
+use crate::import;
+use crate::keygen::{self, GeneratedKey};
+use crate::keyhist::{self, KeyHistory};
+use crate::object;
+use crate::pin::{self, PinStatus, PinType};
+use crate::pinfo::{self, PrintedInfo};
+use crate::rsa;
+use crate::slot::{self, KeyPolicy, PivAlgorithm, PivSlot, PinPolicy, TouchPolicy};
 use crate::tlv::{TlvReader, TlvWriter};
+use crate::transport::{CardTransport, Disposition, PcscTransport};
+use crate::ykpiv::{self, SlotMetadata, Version};
 use crate::PivContext;
 
-/// CHUID data object tag (NIST SP 800-73-4)
-const PIV_TAG_CHUID: u32 = 0x5FC102;
+/// Application Property Template tag in the SELECT response
+const PIV_TAG_APT: u32 = 0x61;
+
+/// Extended length information tag in the SELECT response (ISO 7816-4)
+const ISO_TAG_XLEN: u32 = 0x7F66;
 
-/// Tag for GUID within CHUID
-const CHUID_TAG_GUID: u32 = 0x34;
+/// Conservative extended-length command data limit for cards whose SELECT
+/// response doesn't advertise one (same default as the C library).
+const XLEN_DEFAULT_MAX_DATA: usize = 0x7FF;
 
-pub struct PivToken {
-    card: pcsc::Card,
+pub struct PivToken<T: CardTransport = PcscTransport> {
+    transport: T,
     guid: Guid,
-    reader_name: String,
+    chuid: Option<Chuid>,
+    /// Whether the card accepts extended-length APDUs
+    xapdu: bool,
+    /// Largest command data field to send in one APDU before chaining
+    max_cmd_data: usize,
+    /// An exclusive transaction is open (see `begin_transaction`)
+    in_txn: bool,
+    /// Reset the card when the current transaction ends
+    txn_reset: bool,
+    /// PIN verified during the current transaction, if any
+    used_pin: Option<PinType>,
+    /// The card was reset since the applet was last selected
+    needs_select: bool,
+    /// YubiKey firmware version once asked for; `Some(None)` if the card
+    /// doesn't answer GET VERSION
+    ykver: Option<Option<Version>>,
+    /// Discovery object once read; `Some(None)` if the card has none
+    discovery: Option<Option<Discovery>>,
+    /// Key History object once read; `Some(None)` if the card has none
+    keyhist: Option<Option<KeyHistory>>,
+}
+
+/// An exclusive transaction on a `PivToken`, opened by
+/// [`PivToken::begin_transaction`].
+///
+/// No other PC/SC client can talk to the card until the transaction ends,
+/// which happens when this guard is dropped (or on [`PivTransaction::end`]).
+/// If the PIN was verified inside the transaction, its security status is
+/// cleared and the card reset on the way out, as the C library does.
+pub struct PivTransaction<'a, T: CardTransport = PcscTransport> {
+    token: &'a mut PivToken<T>,
+    /// False for a guard nested inside an already-open transaction, which
+    /// leaves ending it to the outer guard.
+    owned: bool,
 }
 
 impl PivToken {
+    /// Connect to the card in a PC/SC reader and select the PIV applet.
     pub fn connect(ctx: &PivContext, reader: &str) -> Result<Self, PivError> {
-        let cstr =
-            std::ffi::CString::new(reader).map_err(|e| PivError::Other(e.to_string()))?;
-        let card = ctx
-            .pcsc_context()
-            .connect(&cstr, ShareMode::Shared, Protocols::ANY)?;
+        Self::open(PcscTransport::connect(ctx, reader)?)
+    }
+}
+
+impl<T: CardTransport> PivToken<T> {
+    /// Select the PIV applet over an already-connected transport and read
+    /// the card's identity.
+    pub fn open(transport: T) -> Result<Self, PivError> {
         let mut token = Self {
-            card,
+            transport,
             guid: Guid::from_bytes(&[0; 16])?,
-            reader_name: reader.to_string(),
+            chuid: None,
+            xapdu: false,
+            max_cmd_data: SHORT_MAX_DATA,
+            in_txn: false,
+            txn_reset: false,
+            used_pin: None,
+            needs_select: false,
+            ykver: None,
+            discovery: None,
+            keyhist: None,
         };
         token.select_piv()?;
         token.read_chuid()?;
         Ok(token)
     }
 
+    /// Start an exclusive transaction on the card. Operations that take
+    /// several commands (VERIFY followed by GENERAL AUTHENTICATE, for
+    /// instance) should run inside one so another client can't interleave
+    /// its own commands or reset the card between them.
+    ///
+    /// If the card was reset by someone else since we last used it, the
+    /// connection is re-established and the PIV applet selected again.
+    /// Calling this while a transaction is already open returns a guard
+    /// that leaves the outer transaction running when dropped.
+    pub fn begin_transaction(&mut self) -> Result<PivTransaction<'_, T>, PivError> {
+        if self.in_txn {
+            return Ok(PivTransaction {
+                token: self,
+                owned: false,
+            });
+        }
+
+        match self.transport.begin_transaction() {
+            Err(PivError::Pcsc(pcsc::Error::ResetCard)) => {
+                tracing::debug!(reader = %self.reader_name(), "card was reset, reconnecting");
+                self.transport.reconnect(Disposition::Leave)?;
+                self.transport.begin_transaction()?;
+                self.needs_select = true;
+            }
+            r => r?,
+        }
+        self.in_txn = true;
+
+        let txn = PivTransaction {
+            token: self,
+            owned: true,
+        };
+        if txn.token.needs_select {
+            txn.token.select_piv()?;
+            txn.token.needs_select = false;
+        }
+        Ok(txn)
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_txn
+    }
+
+    /// End the open transaction, clearing the PIN status if it was used.
+    fn end_transaction(&mut self) -> Result<(), PivError> {
+        let mut disposition = Disposition::Leave;
+        if let Some(pin_type) = self.used_pin {
+            if !self.txn_reset {
+                // Best effort; the reset below clears it anyway
+                if let Err(e) = self.clear_pin(pin_type) {
+                    tracing::debug!(error = %e, "failed to clear PIN status");
+                }
+            }
+            disposition = Disposition::Reset;
+        }
+        if self.txn_reset {
+            disposition = Disposition::Reset;
+        }
+
+        self.in_txn = false;
+        self.txn_reset = false;
+        self.used_pin = None;
+        if disposition == Disposition::Reset {
+            self.needs_select = true;
+        }
+        self.transport.end_transaction(disposition)
+    }
+
+    /// Send a command, splitting it into a command chain if its data doesn't
+    /// fit in one APDU, and collect any chained response (SW 61xx).
     fn transmit(&self, apdu: &Apdu) -> Result<(Vec<u8>, StatusWord), PivError> {
-        let cmd = apdu.to_bytes();
-        let mut resp_buf = vec![0u8; 4096];
-        let resp = self.card.transmit(&cmd, &mut resp_buf)?;
-        let len = resp.len();
-        if len < 2 {
-            return Err(PivError::Other("response too short for status word".into()));
+        // The chained copies may hold key material (PINs, imported keys)
+        let mut segments = apdu.chain(self.max_cmd_data);
+        let result = self.transmit_segments(&segments);
+        for segment in &mut segments {
+            segment.data.zeroize();
         }
-        let sw = StatusWord::from_bytes(resp[len - 2], resp[len - 1]);
-        let data = resp[..len - 2].to_vec();
+        let (data, sw) = result?;
 
         // Handle GET RESPONSE chaining (SW 61xx)
         if sw.has_more_data() {
             let mut full = data;
             let mut chain_sw = sw;
             while chain_sw.has_more_data() {
-                let mut get_resp = Apdu::new(0x00, 0xC0, 0x00, 0x00);
+                let mut get_resp = Apdu::new(0x00, ins::CONTINUE, 0x00, 0x00);
                 get_resp.le = Some(chain_sw.remaining_bytes() as u16);
-                let cmd2 = get_resp.to_bytes();
-                let mut resp_buf2 = vec![0u8; 4096];
-                let resp2 = self.card.transmit(&cmd2, &mut resp_buf2)?;
-                let len2 = resp2.len();
-                if len2 < 2 {
-                    return Err(PivError::Other("chained response too short".into()));
-                }
-                chain_sw = StatusWord::from_bytes(resp2[len2 - 2], resp2[len2 - 1]);
-                full.extend_from_slice(&resp2[..len2 - 2]);
+                let (data2, sw2) = self.transmit_single(&get_resp)?;
+                chain_sw = sw2;
+                full.extend_from_slice(&data2);
             }
             return Ok((full, chain_sw));
         }
@@ -71,7 +206,73 @@ impl PivToken {
         Ok((data, sw))
     }
 
+    /// Send each segment of a command chain, stopping at the first error.
+    fn transmit_segments(&self, segments: &[Apdu]) -> Result<(Vec<u8>, StatusWord), PivError> {
+        let last = segments.len() - 1;
+        for segment in &segments[..last] {
+            let (data, sw) = self.transmit_single(segment)?;
+            if !sw.is_success() {
+                return Ok((data, sw));
+            }
+        }
+        self.transmit_single(&segments[last])
+    }
+
+    /// Send exactly one APDU, retrying once with the corrected Le if the
+    /// card answers SW 6Cxx.
+    fn transmit_single(&self, apdu: &Apdu) -> Result<(Vec<u8>, StatusWord), PivError> {
+        let (data, sw) = self.transmit_raw(apdu)?;
+        if sw.is_wrong_le() {
+            let mut retry = apdu.clone();
+            retry.le = Some(sw.1 as u16);
+            let result = self.transmit_raw(&retry);
+            retry.data.zeroize();
+            return result;
+        }
+        Ok((data, sw))
+    }
+
+    fn transmit_raw(&self, apdu: &Apdu) -> Result<(Vec<u8>, StatusWord), PivError> {
+        let cmd = Zeroizing::new(if self.xapdu {
+            apdu.to_extended_bytes()
+        } else {
+            apdu.to_bytes()
+        });
+        let resp = self.transport.transmit(&cmd)?;
+        let len = resp.len();
+        if len < 2 {
+            return Err(PivError::Other("response too short for status word".into()));
+        }
+        let sw = StatusWord::from_bytes(resp[len - 2], resp[len - 1]);
+        Ok((resp[..len - 2].to_vec(), sw))
+    }
+
+    /// Select the PIV applet. If the transport can carry them, an
+    /// extended-length SELECT is tried first; if the card accepts it,
+    /// extended-length APDUs are used for the rest of the session, otherwise
+    /// commands are chained.
     fn select_piv(&mut self) -> Result<(), PivError> {
+        if self.transport.supports_extended_apdu() {
+            self.xapdu = true;
+            let mut apdu = Apdu::select(PIV_AID);
+            apdu.le = Some(0);
+            match self.transmit(&apdu) {
+                Ok((data, sw)) if sw.is_success() => {
+                    if let Some(max) = parse_select_xlen(&data) {
+                        self.max_cmd_data = max;
+                        return Ok(());
+                    }
+                }
+                Ok(_) | Err(PivError::Pcsc(_)) => {}
+                Err(e) => return Err(e),
+            }
+            tracing::debug!(
+                reader = %self.reader_name(),
+                "card does not accept extended-length SELECT, using command chaining"
+            );
+            self.xapdu = false;
+        }
+
         let apdu = Apdu::select(PIV_AID);
         let (_, sw) = self.transmit(&apdu)?;
         if !sw.is_success() {
@@ -80,37 +281,35 @@ impl PivToken {
         Ok(())
     }
 
+    /// Read the CHUID and take the card's identity from it: its GUID, or
+    /// the cardholder UUID on cards whose GUID is missing or zero.
     fn read_chuid(&mut self) -> Result<(), PivError> {
-        let apdu = Apdu::get_data(PIV_TAG_CHUID);
-        let (data, sw) = self.transmit(&apdu)?;
-        if !sw.is_success() {
-            return Err(PivError::Apdu { sw: sw.as_u16() });
-        }
-
-        // Response is wrapped in tag 0x53
-        let mut reader = TlvReader::new(&data);
-        let outer_tag = reader.read_tag()?;
-        if outer_tag != 0x53 {
-            return Err(PivError::Tlv {
-                message: format!("expected CHUID outer tag 0x53, got {:#X}", outer_tag),
-            });
-        }
-        let chuid_data = reader.read_value()?;
+        let data = self.get_data(chuid::PIV_TAG_CHUID)?.ok_or(PivError::Apdu {
+            sw: sw::FILE_NOT_FOUND,
+        })?;
+        let chuid = Chuid::decode(&data)?;
+        self.guid = chuid.identity().ok_or_else(|| PivError::Tlv {
+            message: "CHUID has neither a GUID nor a cardholder UUID".into(),
+        })?;
+        self.chuid = Some(chuid);
+        Ok(())
+    }
 
-        // Parse CHUID TLV to find GUID (tag 0x34)
-        let mut chuid_reader = TlvReader::new(chuid_data);
-        while chuid_reader.has_remaining() {
-            let tag = chuid_reader.read_tag()?;
-            let value = chuid_reader.read_value()?;
-            if tag == CHUID_TAG_GUID {
-                self.guid = Guid::from_bytes(value)?;
-                return Ok(());
-            }
-        }
+    /// The CHUID read when the card was opened.
+    pub fn chuid(&self) -> Option<&Chuid> {
+        self.chuid.as_ref()
+    }
 
-        Err(PivError::Tlv {
-            message: "GUID tag (0x34) not found in CHUID".into(),
-        })
+    /// Replace the CHUID, and with it the card's identity. Requires admin
+    /// authentication in the same transaction.
+    pub fn write_chuid(&mut self, chuid: &Chuid) -> Result<(), PivError> {
+        let guid = chuid.identity().ok_or_else(|| {
+            PivError::InvalidGuid("CHUID has neither a GUID nor a cardholder UUID".into())
+        })?;
+        self.put_data(chuid::PIV_TAG_CHUID, &chuid.encode())?;
+        self.guid = guid;
+        self.chuid = Some(chuid.clone());
+        Ok(())
     }
 
     pub fn guid(&self) -> &Guid {
@@ -118,7 +317,11 @@ impl PivToken {
     }
 
     pub fn reader_name(&self) -> &str {
-        &self.reader_name
+        self.transport.reader_name()
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
     }
 
     pub fn transmit_apdu(&self, apdu: &Apdu) -> Result<(Vec<u8>, StatusWord), PivError> {
@@ -126,7 +329,34 @@ impl PivToken {
     }
 
     /// Read a certificate from the given PIV slot and extract the SSH public key.
-    pub fn read_slot(&self, slot_id: u8) -> Result<PivSlot, PivError> {
+    pub fn read_slot(&mut self, slot_id: u8) -> Result<PivSlot, PivError> {
+        let mut txn = self.begin_transaction()?;
+        txn.probe_slot(slot_id)
+    }
+
+    /// Read a slot's certificate, or if it has none, ask a YubiKey that
+    /// supports GET METADATA for the key itself. Keys found that way have
+    /// an empty `cert_der`.
+    fn probe_slot(&mut self, slot_id: u8) -> Result<PivSlot, PivError> {
+        match self.read_slot_cert(slot_id) {
+            Err(PivError::SlotEmpty(_)) if self.supports_metadata() => {}
+            r => return r,
+        }
+        let metadata = self.read_metadata(slot_id)?;
+        let public_key = metadata
+            .public_key()
+            .and_then(|k| k.public_key())
+            .ok_or_else(|| {
+                PivError::UnsupportedAlgorithm(format!(
+                    "{:?} key in slot {:02X} has no SSH form",
+                    metadata.algorithm(),
+                    slot_id
+                ))
+            })?;
+        Ok(PivSlot::new(slot_id, metadata.algorithm(), Vec::new(), public_key.clone()))
+    }
+
+    fn read_slot_cert(&self, slot_id: u8) -> Result<PivSlot, PivError> {
         let cert_tag = slot::slot_to_cert_tag(slot_id)
             .ok_or(PivError::SlotEmpty(slot_id))?;
         let apdu = Apdu::get_data(cert_tag);
@@ -145,39 +375,341 @@ impl PivToken {
         }
         let inner = reader.read_value()?;
 
-        // Parse inner TLV: tag 0x70 = certificate, tag 0x71 = cert info
-        let mut inner_reader = TlvReader::new(inner);
-        let mut cert_der: Option<Vec<u8>> = None;
-        while inner_reader.has_remaining() {
-            let tag = inner_reader.read_tag()?;
-            let value = inner_reader.read_value()?;
-            if tag == 0x70 {
-                cert_der = Some(value.to_vec());
+        // 0x70 = certificate (maybe compressed), 0x71 = certinfo
+        let cert_der = cert::decode_cert_object(inner)?.ok_or(PivError::SlotEmpty(slot_id))?;
+        let (algorithm, public_key) = cert::extract_public_key(&cert_der)?;
+        Ok(PivSlot::new(slot_id, algorithm, cert_der, public_key))
+    }
+
+    /// Ask a YubiKey to attest the key in `slot_id`, returning the DER
+    /// attestation certificate signed by the F9 key. Only keys generated
+    /// on the card can be attested. Check the result with
+    /// [`attest::verify`](crate::attest::verify) and the F9 certificate from
+    /// [`read_attestation_cert`](Self::read_attestation_cert).
+    pub fn attest(&mut self, slot_id: u8) -> Result<Vec<u8>, PivError> {
+        let apdu = Apdu::new(0x00, yk_ins::ATTEST, slot_id, 0x00);
+        let txn = self.begin_transaction()?;
+        let (data, sw) = txn.transmit(&apdu)?;
+        drop(txn);
+        match sw.as_u16() {
+            0x9000 if !data.is_empty() => Ok(data),
+            0x9000 => Err(PivError::Other("card returned an empty attestation".into())),
+            sw::WRONG_DATA | sw::FILE_NOT_FOUND => Err(PivError::SlotEmpty(slot_id)),
+            sw::INS_NOT_SUPPORTED => Err(PivError::Other(
+                "card does not support YubiKey attestation".into(),
+            )),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// Read the certificate for the F9 attestation key (object 0x5FFF01),
+    /// the intermediate between attestations and the Yubico root.
+    pub fn read_attestation_cert(&mut self) -> Result<Vec<u8>, PivError> {
+        let txn = self.begin_transaction()?;
+        Ok(txn.read_slot_cert(slot_id::ATTESTATION)?.cert_der().to_vec())
+    }
+
+    /// YubiKey firmware version (GET VERSION). Cached after the first ask.
+    pub fn version(&mut self) -> Result<Version, PivError> {
+        if let Some(version) = self.ykver {
+            return version.ok_or_else(|| not_yubikey("GET VERSION"));
+        }
+        let apdu = Apdu::new(0x00, yk_ins::GET_VER, 0x00, 0x00);
+        let txn = self.begin_transaction()?;
+        let (data, sw) = txn.transmit(&apdu)?;
+        drop(txn);
+        let version = match sw.as_u16() {
+            0x9000 => Some(Version::from_bytes(&data)?),
+            sw::INS_NOT_SUPPORTED | sw::FUNC_NOT_SUPPORTED => None,
+            other => return Err(PivError::Apdu { sw: other }),
+        };
+        self.ykver = Some(version);
+        version.ok_or_else(|| not_yubikey("GET VERSION"))
+    }
+
+    /// YubiKey serial number. Firmware before 5.0 only reports it through
+    /// the YubiKey management applet, which is selected briefly for it.
+    pub fn serial(&mut self) -> Result<u32, PivError> {
+        let apdu = Apdu::new(0x00, yk_ins::GET_SERIAL, 0x00, 0x00);
+        let mut txn = self.begin_transaction()?;
+        let (data, sw) = txn.transmit(&apdu)?;
+        match sw.as_u16() {
+            0x9000 => return parse_serial(&data),
+            sw::INS_NOT_SUPPORTED | sw::FUNC_NOT_SUPPORTED => {}
+            other => return Err(PivError::Apdu { sw: other }),
+        }
+
+        let (_, sw) = txn.transmit(&Apdu::select(YKPIV_AID))?;
+        let result = if sw.is_success() {
+            match txn.transmit(&apdu)? {
+                (data, sw) if sw.is_success() => parse_serial(&data),
+                _ => Err(not_yubikey("GET SERIAL")),
             }
-            // tag 0x71 = certinfo, tag 0xFE = error detection code -- skip
+        } else {
+            Err(not_yubikey("GET SERIAL"))
+        };
+        txn.select_piv()?;
+        result
+    }
+
+    /// Ask a YubiKey (firmware 5.3 or later) about the key in `slot_id`:
+    /// algorithm, PIN and touch policy, whether it was generated on the
+    /// card, and its public key.
+    pub fn slot_metadata(&mut self, slot_id: u8) -> Result<SlotMetadata, PivError> {
+        let txn = self.begin_transaction()?;
+        txn.read_metadata(slot_id)
+    }
+
+    fn read_metadata(&self, slot_id: u8) -> Result<SlotMetadata, PivError> {
+        let apdu = Apdu::new(0x00, yk_ins::GET_METADATA, 0x00, slot_id);
+        let (data, sw) = self.transmit(&apdu)?;
+        match sw.as_u16() {
+            0x9000 => SlotMetadata::parse(&data),
+            sw::FILE_NOT_FOUND | sw::REFERENCE_NOT_FOUND => Err(PivError::SlotEmpty(slot_id)),
+            sw::INS_NOT_SUPPORTED | sw::FUNC_NOT_SUPPORTED => Err(not_yubikey("GET METADATA")),
+            other => Err(PivError::Apdu { sw: other }),
         }
+    }
 
-        let cert_der = cert_der.ok_or(PivError::SlotEmpty(slot_id))?;
-        let (algorithm, public_key) = cert::extract_public_key(&cert_der)?;
-        Ok(PivSlot::new(slot_id, algorithm, cert_der, public_key))
+    /// Whether GET METADATA is worth asking for (YubiKey 5.3 and later).
+    fn supports_metadata(&mut self) -> bool {
+        self.version().is_ok_and(|v| v >= ykpiv::METADATA)
+    }
+
+    /// Store a certificate for the key in `slot_id`, gzip-compressed if
+    /// `compress` is set (useful for large certificates on cards with small
+    /// object size limits). Requires admin authentication in the same
+    /// transaction.
+    pub fn write_cert(
+        &mut self,
+        slot_id: u8,
+        cert_der: &[u8],
+        compress: bool,
+    ) -> Result<(), PivError> {
+        let cert_tag = slot::slot_to_cert_tag(slot_id).ok_or_else(|| {
+            PivError::Other(format!("slot {:02X} has no certificate object", slot_id))
+        })?;
+        let object = cert::encode_cert_object(cert_der, compress)?;
+        self.put_data(cert_tag, &object)
+    }
+
+    /// Remove the certificate for `slot_id`. The key itself stays on the
+    /// card. Requires admin authentication in the same transaction.
+    pub fn delete_cert(&mut self, slot_id: u8) -> Result<(), PivError> {
+        let cert_tag = slot::slot_to_cert_tag(slot_id).ok_or_else(|| {
+            PivError::Other(format!("slot {:02X} has no certificate object", slot_id))
+        })?;
+        self.put_data(cert_tag, &[])
+    }
+
+    /// Read a data object with GET DATA, returning the whole response, or
+    /// `None` if the card doesn't have it or doesn't support it.
+    fn get_object(&self, tag: u32) -> Result<Option<Vec<u8>>, PivError> {
+        let (data, sw) = self.transmit(&Apdu::get_data(tag))?;
+        match sw.as_u16() {
+            _ if sw.is_success() => Ok(Some(data)),
+            sw::FILE_NOT_FOUND | sw::WRONG_DATA | sw::FUNC_NOT_SUPPORTED => Ok(None),
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::PinRequired),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// Read a data object with GET DATA, returning the content of its
+    /// 0x53 wrapper, or `None` if the card doesn't have it.
+    fn get_data(&self, tag: u32) -> Result<Option<Vec<u8>>, PivError> {
+        let Some(data) = self.get_object(tag)? else {
+            return Ok(None);
+        };
+        let mut reader = TlvReader::new(&data);
+        let outer_tag = reader.read_tag()?;
+        if outer_tag != 0x53 {
+            return Err(PivError::Tlv {
+                message: format!(
+                    "expected object {:06X} outer tag 0x53, got {:#X}",
+                    tag, outer_tag
+                ),
+            });
+        }
+        Ok(Some(reader.read_value()?.to_vec()))
+    }
+
+    /// Read any data object, or `None` if the card doesn't have it. For
+    /// most objects this is the content of the 0x53 wrapper; the Discovery
+    /// object and BIT Group Template, which have no wrapper, are returned
+    /// whole. PIN-protected objects (see [`object::OBJECTS`]) need the PIN
+    /// verified in the same transaction.
+    pub fn read_object(&mut self, tag: u32) -> Result<Option<Vec<u8>>, PivError> {
+        if object::is_wrapped(tag) {
+            self.get_data(tag)
+        } else {
+            self.get_object(tag)
+        }
+    }
+
+    /// Write any data object, in the form [`read_object`] returns it; an
+    /// empty `data` deletes a wrapped object. Requires admin
+    /// authentication in the same transaction.
+    ///
+    /// [`read_object`]: Self::read_object
+    pub fn write_object(&mut self, tag: u32, data: &[u8]) -> Result<(), PivError> {
+        if object::is_wrapped(tag) {
+            self.put_data(tag, data)?;
+        } else {
+            let mut apdu = Apdu::new(0x00, ins::PUT_DATA, 0x3F, 0xFF);
+            apdu.data = data.to_vec();
+            let (_, sw) = self.transmit(&apdu)?;
+            match sw.as_u16() {
+                _ if sw.is_success() => {}
+                sw::SECURITY_STATUS_NOT_SATISFIED => return Err(PivError::AdminRequired),
+                other => return Err(PivError::Apdu { sw: other }),
+            }
+        }
+        // Keep what we know about the card in step. Objects read lazily
+        // are read again next time they're asked for.
+        match tag {
+            chuid::PIV_TAG_CHUID => {
+                let chuid = Chuid::decode(data).ok();
+                if let Some(guid) = chuid.as_ref().and_then(Chuid::identity) {
+                    self.guid = guid;
+                }
+                self.chuid = chuid;
+            }
+            discovery::PIV_TAG_DISCOVERY => self.discovery = None,
+            keyhist::PIV_TAG_KEYHIST => self.keyhist = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Read the Printed Information object, or `None` if the card has
+    /// none. The card only returns it after PIN verification in the same
+    /// transaction.
+    pub fn read_pinfo(&mut self) -> Result<Option<PrintedInfo>, PivError> {
+        match self.get_data(pinfo::PIV_TAG_PRINTED_INFO)? {
+            Some(data) => {
+                let data = Zeroizing::new(data);
+                Ok(Some(PrintedInfo::decode(&data)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the Printed Information object. Requires admin
+    /// authentication in the same transaction.
+    pub fn write_pinfo(&mut self, info: &PrintedInfo) -> Result<(), PivError> {
+        self.put_data(pinfo::PIV_TAG_PRINTED_INFO, &info.encode())
+    }
+
+    /// Read the Card Capability Container, or `None` if the card has none.
+    pub fn read_cardcap(&mut self) -> Result<Option<CardCapability>, PivError> {
+        match self.get_data(cardcap::PIV_TAG_CARDCAP)? {
+            Some(data) => Ok(Some(CardCapability::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the Card Capability Container. Requires admin
+    /// authentication in the same transaction.
+    pub fn write_cardcap(&mut self, cc: &CardCapability) -> Result<(), PivError> {
+        self.put_data(cardcap::PIV_TAG_CARDCAP, &cc.encode())
+    }
+
+    /// The card's Discovery object, or `None` if it has none. Read from
+    /// the card the first time it's asked for.
+    pub fn discovery(&mut self) -> Result<Option<&Discovery>, PivError> {
+        if self.discovery.is_none() {
+            let disc = match self.get_object(discovery::PIV_TAG_DISCOVERY)? {
+                Some(data) => Some(Discovery::decode(&data)?),
+                None => None,
+            };
+            self.discovery = Some(disc);
+        }
+        Ok(self.discovery.as_ref().and_then(Option::as_ref))
     }
 
-    /// Read certificates from all standard PIV slots plus retired slots.
+    /// The PIN [`verify_pin`](Self::verify_pin) uses: the Global PIN if
+    /// the Discovery object says it is preferred, otherwise the PIV PIN.
+    /// A Discovery object we can't read or decode counts as missing, as
+    /// in C.
+    pub fn preferred_pin(&mut self) -> PinType {
+        match self.discovery() {
+            Ok(disc) => disc.map_or(PinType::Piv, Discovery::preferred_pin),
+            Err(e) => {
+                tracing::warn!(error = %e, "can't read Discovery, using the PIV PIN");
+                PinType::Piv
+            }
+        }
+    }
+
+    /// The card's Key History object, or `None` if it has none. Read from
+    /// the card the first time it's asked for.
+    pub fn key_history(&mut self) -> Result<Option<&KeyHistory>, PivError> {
+        if self.keyhist.is_none() {
+            let hist = match self.get_data(keyhist::PIV_TAG_KEYHIST)? {
+                Some(data) => Some(KeyHistory::decode(&data)?),
+                None => None,
+            };
+            self.keyhist = Some(hist);
+        }
+        Ok(self.keyhist.as_ref().and_then(Option::as_ref))
+    }
+
+    /// Replace the Key History object, after checking its counts. Requires
+    /// admin authentication in the same transaction.
+    pub fn write_keyhistory(&mut self, hist: &KeyHistory) -> Result<(), PivError> {
+        hist.validate()?;
+        self.put_data(keyhist::PIV_TAG_KEYHIST, &hist.encode())?;
+        self.keyhist = Some(Some(hist.clone()));
+        Ok(())
+    }
+
+    /// Write a data object with PUT DATA; `value` is the content of the
+    /// 0x53 wrapper, and an empty value deletes the object.
+    fn put_data(&self, tag: u32, value: &[u8]) -> Result<(), PivError> {
+        // Some objects (printed information) can hold key material
+        let mut apdu = Apdu::put_data(tag, value);
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        match sw.as_u16() {
+            _ if sw.is_success() => Ok(()),
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminRequired),
+            sw::OUT_OF_MEMORY => Err(PivError::Other(format!(
+                "card is out of space for object {:06X}",
+                tag
+            ))),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// Read certificates from all standard PIV slots, plus the retired
+    /// slots the Key History object says hold on-card certificates.
     /// Silently skips empty slots.
-    pub fn read_all_slots(&self) -> Result<Vec<PivSlot>, PivError> {
+    pub fn read_all_slots(&mut self) -> Result<Vec<PivSlot>, PivError> {
+        let mut txn = self.begin_transaction()?;
         let mut slots = Vec::new();
 
         // Standard slots
         for &slot_id in slot::STANDARD_SLOTS {
-            match self.read_slot(slot_id) {
+            match txn.probe_slot(slot_id) {
                 Ok(s) => slots.push(s),
                 Err(_) => continue,
             }
         }
 
-        // Retired key management slots 82-95
-        for slot_id in 0x82..=0x95_u8 {
-            match self.read_slot(slot_id) {
+        // Retired key management slots in use, from 82 upwards. Like C,
+        // don't let a Key History object we can't read cost the standard
+        // slots; just skip the retired ones.
+        let retired: Vec<u8> = match txn.key_history() {
+            Ok(Some(hist)) => hist.on_card_slots().collect(),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                tracing::warn!(error = %e, "can't read Key History, skipping retired slots");
+                Vec::new()
+            }
+        };
+        for slot_id in retired {
+            match txn.probe_slot(slot_id) {
                 Ok(s) => slots.push(s),
                 Err(_) => continue,
             }
@@ -189,8 +721,9 @@ impl PivToken {
     /// Sign pre-hashed data with the key in the given slot.
     /// For ECDSA, `data` is the hash digest (32 bytes for P256, 48 for P384).
     /// For RSA, `data` is the PKCS#1 v1.5 padded DigestInfo (128 or 256 bytes).
-    pub fn sign_prehash(&self, slot_id: u8, data: &[u8]) -> Result<Vec<u8>, PivError> {
-        let slot = self.read_slot(slot_id)?;
+    pub fn sign_prehash(&mut self, slot_id: u8, data: &[u8]) -> Result<Vec<u8>, PivError> {
+        let mut txn = self.begin_transaction()?;
+        let slot = txn.probe_slot(slot_id)?;
         let alg_byte = slot.algorithm().to_byte();
 
         // Build GENERAL AUTHENTICATE TLV:
@@ -204,7 +737,8 @@ impl PivToken {
         outer.write_tag_value(0x7C, inner.as_bytes());
 
         let apdu = Apdu::general_authenticate(alg_byte, slot_id, outer.as_bytes());
-        let (resp, sw) = self.transmit(&apdu)?;
+        let (resp, sw) = txn.transmit(&apdu)?;
+        drop(txn);
 
         if sw.as_u16() == 0x6982 {
             return Err(PivError::PinRequired);
@@ -214,6 +748,342 @@ impl PivToken {
         }
 
         // Parse response: 0x7C { 0x82 = signature }
+        Ok(ga_response(&resp)?.to_vec())
+    }
+
+    /// Decrypt a PKCS#1 v1.5 RSA ciphertext with the key in `slot_id`
+    /// (usually 9D, key management). The PIN is needed as for signing.
+    pub fn rsa_decrypt(
+        &mut self,
+        slot_id: u8,
+        ciphertext: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let em = self.rsa_private_op(slot_id, ciphertext)?;
+        rsa::unpad_pkcs1_v15(&em).ok_or_else(|| PivError::Crypto("RSA decryption failed".into()))
+    }
+
+    /// Decrypt an RSA-OAEP ciphertext (empty label, MGF1 using `digest`)
+    /// with the key in `slot_id`. The card does the raw RSA operation and
+    /// the OAEP decoding happens here.
+    pub fn rsa_decrypt_oaep(
+        &mut self,
+        slot_id: u8,
+        ciphertext: &[u8],
+        digest: MessageDigest,
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let em = self.rsa_private_op(slot_id, ciphertext)?;
+        rsa::unpad_oaep(&em, digest)?
+            .ok_or_else(|| PivError::Crypto("RSA decryption failed".into()))
+    }
+
+    /// Raw RSA private-key operation: the card returns `ciphertext^d mod n`,
+    /// left-padded here to the modulus length.
+    fn rsa_private_op(
+        &mut self,
+        slot_id: u8,
+        ciphertext: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let algorithm = match ciphertext.len() {
+            128 => PivAlgorithm::Rsa1024,
+            256 => PivAlgorithm::Rsa2048,
+            n => {
+                return Err(PivError::Crypto(format!(
+                    "RSA ciphertext must be 128 or 256 bytes, got {}",
+                    n
+                )))
+            }
+        };
+
+        // 0x7C { 0x82 (empty), 0x81 = ciphertext }
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(ga_tag::RESPONSE as u32, &[]);
+        inner.write_tag_value(ga_tag::CHALLENGE as u32, ciphertext);
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x7C, inner.as_bytes());
+
+        let apdu = Apdu::general_authenticate(algorithm.to_byte(), slot_id, outer.as_bytes());
+        let txn = self.begin_transaction()?;
+        let (resp, sw) = txn.transmit(&apdu)?;
+        drop(txn);
+        let resp = Zeroizing::new(resp);
+
+        match sw.as_u16() {
+            0x9000 => {}
+            sw::SECURITY_STATUS_NOT_SATISFIED => return Err(PivError::PinRequired),
+            sw::INCORRECT_P1P2 => {
+                return Err(PivError::UnsupportedAlgorithm(format!(
+                    "slot {:02X} does not hold a {:?} key",
+                    slot_id, algorithm
+                )))
+            }
+            other => return Err(PivError::Apdu { sw: other }),
+        }
+        let out = ga_response(&resp)?;
+        if out.len() > ciphertext.len() {
+            return Err(PivError::Crypto(format!(
+                "card returned {} bytes for a {}-byte modulus",
+                out.len(),
+                ciphertext.len()
+            )));
+        }
+        let mut em = Zeroizing::new(vec![0u8; ciphertext.len()]);
+        em[ciphertext.len() - out.len()..].copy_from_slice(out);
+        Ok(em)
+    }
+
+    /// ECDH between the key in `slot_id` and `peer`, returning the shared
+    /// secret. The peer must be on the same curve as the slot's key
+    /// (P-256, P-384 or X25519); the PIN is needed as for signing.
+    pub fn ecdh(
+        &mut self,
+        slot_id: u8,
+        peer: &PKeyRef<Public>,
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let (algorithm, point) = peer_point(peer)?;
+
+        // 0x7C { 0x82 (empty), 0x85 = peer public point }
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(ga_tag::RESPONSE as u32, &[]);
+        inner.write_tag_value(ga_tag::EXPONENT as u32, &point);
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x7C, inner.as_bytes());
+
+        let apdu = Apdu::general_authenticate(algorithm.to_byte(), slot_id, outer.as_bytes());
+        let txn = self.begin_transaction()?;
+        let (resp, sw) = txn.transmit(&apdu)?;
+        drop(txn);
+        let resp = Zeroizing::new(resp);
+
+        match sw.as_u16() {
+            0x9000 => {}
+            sw::SECURITY_STATUS_NOT_SATISFIED => return Err(PivError::PinRequired),
+            sw::INCORRECT_P1P2 => {
+                return Err(PivError::UnsupportedAlgorithm(format!(
+                    "slot {:02X} does not hold a {:?} key",
+                    slot_id, algorithm
+                )))
+            }
+            other => return Err(PivError::Apdu { sw: other }),
+        }
+        Ok(Zeroizing::new(ga_response(&resp)?.to_vec()))
+    }
+
+    /// Open an ECDH box sealed to the key in `slot_id`, doing the ECDH on
+    /// the card. Needs the PIN verified like `ecdh`.
+    pub fn open_box(
+        &mut self,
+        slot_id: u8,
+        ecdh_box: &EcdhBox,
+    ) -> Result<Zeroizing<Vec<u8>>, PivError> {
+        let ephemeral = cert::pkey_from_ssh_public(&ecdh_box.ephemeral())?;
+        let secret = self.ecdh(slot_id, &ephemeral)?;
+        ecdh_box.open_with_secret(&secret)
+    }
+
+    /// Generate a new key pair in `slot_id` on the card, replacing any key
+    /// already there, and return its public half. Requires admin
+    /// authentication (`PivTransaction::auth_admin`) in the same
+    /// transaction. Non-default PIN/touch policies are YubiKey extensions.
+    pub fn generate(
+        &mut self,
+        slot_id: u8,
+        algorithm: PivAlgorithm,
+        policy: KeyPolicy,
+    ) -> Result<GeneratedKey, PivError> {
+        // Control reference template: 0xAC { 0x80 alg, 0xAA pin, 0xAB touch }
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(0x80, &[algorithm.to_byte()]);
+        if policy.pin != PinPolicy::Default {
+            inner.write_tag_value(0xAA, &[policy.pin.to_byte()]);
+        }
+        if policy.touch != TouchPolicy::Default {
+            inner.write_tag_value(0xAB, &[policy.touch.to_byte()]);
+        }
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0xAC, inner.as_bytes());
+
+        let mut apdu = Apdu::new(0x00, ins::GEN_ASYM, 0x00, slot_id);
+        apdu.data = outer.into_vec();
+        let (resp, sw) = self.transmit(&apdu)?;
+        match sw.as_u16() {
+            _ if sw.is_success() => keygen::parse_public_key_template(algorithm, &resp),
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminRequired),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// Import an existing private key into `slot_id`, replacing any key
+    /// already there. Accepted keys are RSA 1024 or 2048 bits with
+    /// exponent 65537, EC on P-256 or P-384, Ed25519 and X25519. Keys in
+    /// PEM or PKCS#8 form can be loaded with `PKey::private_key_from_pem`
+    /// or `private_key_from_pkcs8`, and OpenSSH keys converted with
+    /// [`import::pkey_from_ssh`]. The PIN and touch policies come in a
+    /// `KeyPolicy`, as for `generate`. Requires admin authentication in
+    /// the same transaction. This is a YubiKey extension.
+    pub fn import_key(
+        &mut self,
+        slot_id: u8,
+        key: &PKeyRef<Private>,
+        policy: KeyPolicy,
+    ) -> Result<(), PivError> {
+        let (algorithm, data) = import::encode_import(key, policy)?;
+        let mut apdu = Apdu::new(0x00, yk_ins::IMPORT_ASYM, algorithm.to_byte(), slot_id);
+        apdu.data = data.to_vec();
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        match sw.as_u16() {
+            _ if sw.is_success() => Ok(()),
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminRequired),
+            sw::INCORRECT_P1P2 => Err(PivError::UnsupportedAlgorithm(format!(
+                "card cannot import {:?} keys",
+                algorithm
+            ))),
+            sw::WRONG_DATA => Err(PivError::Other("card rejected the imported key".into())),
+            sw::INS_NOT_SUPPORTED => Err(not_yubikey("key import")),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// Verify the card's preferred PIN (see [`preferred_pin`]), normally
+    /// the PIV PIN. The PIN is padded to 8 bytes with 0xFF per the spec.
+    ///
+    /// Call this inside a transaction together with the operation that
+    /// needs the PIN; the PIN status is then cleared when it ends.
+    ///
+    /// [`preferred_pin`]: Self::preferred_pin
+    pub fn verify_pin(&mut self, pin: &str) -> Result<(), PivError> {
+        let pin_type = self.preferred_pin();
+        self.verify_pin_type(pin_type, pin)
+    }
+
+    /// Verify a specific PIN: the PIV application PIN or the card's Global
+    /// PIN.
+    pub fn verify_pin_type(&mut self, pin_type: PinType, pin: &str) -> Result<(), PivError> {
+        let mut apdu = Apdu::new(0x00, ins::VERIFY, 0x00, pin_type.reference());
+        apdu.data = pin::pad(pin)?.to_vec();
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        if !sw.is_success() {
+            return Err(pin_error(pin_type, sw));
+        }
+        self.mark_pin_used(pin_type);
+        Ok(())
+    }
+
+    /// Ask whether a PIN is verified and how many attempts it has left,
+    /// using an empty VERIFY which doesn't use up an attempt.
+    pub fn pin_status(&mut self, pin_type: PinType) -> Result<PinStatus, PivError> {
+        let txn = self.begin_transaction()?;
+        let apdu = Apdu::new(0x00, ins::VERIFY, 0x00, pin_type.reference());
+        let (_, sw) = txn.transmit(&apdu)?;
+        drop(txn);
+        if sw.is_success() {
+            return Ok(PinStatus::Verified);
+        }
+        match sw.pin_retries_remaining() {
+            Some(0) => Ok(PinStatus::Blocked),
+            Some(retries) => Ok(PinStatus::Unverified {
+                retries: retries as u32,
+            }),
+            None if sw.as_u16() == sw::AUTH_METHOD_BLOCKED => Ok(PinStatus::Blocked),
+            None => Err(pin_error(pin_type, sw)),
+        }
+    }
+
+    /// Change the PIV PIN or Global PIN from `old` to `new`.
+    pub fn change_pin(&mut self, pin_type: PinType, old: &str, new: &str) -> Result<(), PivError> {
+        self.change_reference(pin_type, old, new)?;
+        // A successful change also verifies the PIN
+        self.mark_pin_used(pin_type);
+        Ok(())
+    }
+
+    /// Change the PUK from `old` to `new`.
+    pub fn change_puk(&mut self, old: &str, new: &str) -> Result<(), PivError> {
+        self.change_reference(PinType::Puk, old, new)
+    }
+
+    /// Set a new PIV PIN using the PUK, unblocking the PIN if it was
+    /// blocked. A wrong PUK is reported as `PinIncorrect` with the PUK's
+    /// remaining attempts.
+    pub fn reset_pin_with_puk(&mut self, puk: &str, new_pin: &str) -> Result<(), PivError> {
+        let mut apdu = Apdu::new(0x00, ins::RESET_PIN, 0x00, PinType::Piv.reference());
+        apdu.data = reference_change_data(puk, new_pin)?;
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        if !sw.is_success() {
+            return Err(pin_error(PinType::Puk, sw));
+        }
+        self.mark_pin_used(PinType::Piv);
+        Ok(())
+    }
+
+    /// CHANGE REFERENCE DATA: old value then new value, 8 bytes each.
+    fn change_reference(&mut self, pin_type: PinType, old: &str, new: &str) -> Result<(), PivError> {
+        let mut apdu = Apdu::new(0x00, ins::CHANGE_PIN, 0x00, pin_type.reference());
+        apdu.data = reference_change_data(old, new)?;
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        if !sw.is_success() {
+            return Err(pin_error(pin_type, sw));
+        }
+        Ok(())
+    }
+
+    /// Remember that a PIN's security status needs clearing when the
+    /// transaction ends. If two different PINs were used, resetting the
+    /// card is the only way to clear both.
+    fn mark_pin_used(&mut self, pin_type: PinType) {
+        if !self.in_txn {
+            return;
+        }
+        match self.used_pin {
+            None => self.used_pin = Some(pin_type),
+            Some(used) if used == pin_type => {}
+            Some(_) => self.txn_reset = true,
+        }
+    }
+
+    /// Drop a PIN's verified status (VERIFY with P1 = FF).
+    fn clear_pin(&self, pin_type: PinType) -> Result<(), PivError> {
+        let apdu = Apdu::new(0x00, ins::VERIFY, 0xFF, pin_type.reference());
+        let (_, sw) = self.transmit(&apdu)?;
+        if !sw.is_success() {
+            return Err(PivError::Apdu { sw: sw.as_u16() });
+        }
+        Ok(())
+    }
+}
+
+impl<T: CardTransport> PivTransaction<'_, T> {
+    /// Reset the card when the transaction ends, discarding any PIN or
+    /// admin authentication. Applies to the outermost transaction.
+    pub fn reset_on_end(&mut self) {
+        self.token.txn_reset = true;
+    }
+
+    /// Authenticate as card administrator with the 9B management key
+    /// (`alg_id` is one of `alg::TDEA_3KEY` or `alg::AES128/192/256`).
+    ///
+    /// Uses the single-step challenge-response exchange: the card sends a
+    /// random block, we return it encrypted under the key. The card is
+    /// reset when the transaction ends so admin rights don't outlive it.
+    pub fn auth_admin(&mut self, alg_id: u8, key: &[u8]) -> Result<(), PivError> {
+        admin::check_key(alg_id, key)?;
+
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(ga_tag::CHALLENGE as u32, &[]);
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x7C, inner.as_bytes());
+        let apdu = Apdu::general_authenticate(alg_id, slot_id::ADMIN, outer.as_bytes());
+        let (resp, sw) = self.transmit(&apdu)?;
+        check_admin_sw(alg_id, sw)?;
+
+        // Response: 0x7C { 0x81 = challenge }
         let mut reader = TlvReader::new(&resp);
         let outer_tag = reader.read_tag()?;
         if outer_tag != 0x7C {
@@ -221,36 +1091,321 @@ impl PivToken {
                 message: format!("expected GA response tag 0x7C, got {:#X}", outer_tag),
             });
         }
-        let inner_data = reader.read_value()?;
+        let mut inner_reader = TlvReader::new(reader.read_value()?);
+        let mut challenge = None;
+        while inner_reader.has_remaining() {
+            let tag = inner_reader.read_tag()?;
+            let value = inner_reader.read_value()?;
+            if tag == ga_tag::CHALLENGE as u32 {
+                challenge = Some(value);
+            }
+        }
+        let challenge = challenge.ok_or_else(|| PivError::Tlv {
+            message: "admin challenge tag (0x81) not found".into(),
+        })?;
 
-        let mut inner_reader = TlvReader::new(inner_data);
-        let resp_tag = inner_reader.read_tag()?;
-        if resp_tag != ga_tag::RESPONSE as u32 {
-            return Err(PivError::Tlv {
-                message: format!("expected GA response tag 0x82, got {:#X}", resp_tag),
-            });
+        let response = admin::respond(alg_id, key, challenge)?;
+        let mut inner = TlvWriter::new();
+        inner.write_tag_value(ga_tag::RESPONSE as u32, &response);
+        let mut outer = TlvWriter::new();
+        outer.write_tag_value(0x7C, inner.as_bytes());
+
+        self.token.txn_reset = true;
+        let apdu = Apdu::general_authenticate(alg_id, slot_id::ADMIN, outer.as_bytes());
+        let (_, sw) = self.transmit(&apdu)?;
+        check_admin_sw(alg_id, sw)
+    }
+
+    /// Check whether the card still has the default management key by
+    /// authenticating with it. Returns the algorithm it was accepted with,
+    /// or `None` if the key has been changed; on success the transaction
+    /// is left admin-authenticated.
+    pub fn has_default_admin_key(&mut self) -> Result<Option<u8>, PivError> {
+        for &alg_id in admin::DEFAULT_ADMIN_KEY_ALGS {
+            match self.auth_admin(alg_id, &admin::DEFAULT_ADMIN_KEY) {
+                Ok(()) => return Ok(Some(alg_id)),
+                Err(PivError::AdminAuthFailed) | Err(PivError::UnsupportedAlgorithm(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Authenticate as card administrator knowing only the PIN: verify
+    /// `pin`, then use the PIN-protected management key stored in the
+    /// Printed Information object. Returns the algorithm the key was
+    /// accepted with (a 24-byte key is tried as 3DES, then AES-192).
+    pub fn auth_admin_with_pin(&mut self, pin: &str) -> Result<u8, PivError> {
+        self.verify_pin(pin)?;
+        let info = self.read_pinfo()?.ok_or_else(|| {
+            PivError::Other("card has no printed information object".into())
+        })?;
+        let key = info.admin_key().ok_or_else(|| {
+            PivError::Other("card has no PIN-protected management key".into())
+        })?;
+        let algs: &[u8] = match key.len() {
+            16 => &[alg::AES128],
+            24 => &[alg::TDEA_3KEY, alg::AES192],
+            32 => &[alg::AES256],
+            len => {
+                return Err(PivError::Other(format!(
+                    "PIN-protected management key is {} bytes, not a 3DES or AES key",
+                    len
+                )))
+            }
+        };
+        for &alg_id in algs {
+            match self.auth_admin(alg_id, key) {
+                Ok(()) => return Ok(alg_id),
+                Err(PivError::AdminAuthFailed) | Err(PivError::UnsupportedAlgorithm(_)) => continue,
+                Err(e) => return Err(e),
+            }
         }
-        let signature = inner_reader.read_value()?;
+        Err(PivError::AdminAuthFailed)
+    }
 
-        Ok(signature.to_vec())
+    /// Replace the 9B management key (YubiKey extension). With
+    /// `TouchPolicy::Always` the card will also want a touch for every
+    /// later admin authentication; other touch policies aren't available
+    /// for the management key. Requires `auth_admin` first.
+    pub fn set_admin_key(
+        &mut self,
+        alg_id: u8,
+        key: &[u8],
+        touch: TouchPolicy,
+    ) -> Result<(), PivError> {
+        admin::check_key(alg_id, key)?;
+        let p2 = match touch {
+            TouchPolicy::Default | TouchPolicy::Never => 0xFF,
+            TouchPolicy::Always => 0xFE,
+            other => {
+                return Err(PivError::Other(format!(
+                    "management key touch policy cannot be {:?}",
+                    other
+                )))
+            }
+        };
+
+        // alg, slot 9B, key length, key
+        let mut apdu = Apdu::new(0x00, yk_ins::SET_MGMT, 0xFF, p2);
+        apdu.data = Vec::with_capacity(3 + key.len());
+        apdu.data.extend_from_slice(&[alg_id, slot_id::ADMIN, key.len() as u8]);
+        apdu.data.extend_from_slice(key);
+        let result = self.transmit(&apdu);
+        apdu.data.zeroize();
+        let (_, sw) = result?;
+        match sw.as_u16() {
+            _ if sw.is_success() => {
+                self.token.txn_reset = true;
+                Ok(())
+            }
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminRequired),
+            sw::INS_NOT_SUPPORTED => Err(not_yubikey("SET MANAGEMENT KEY")),
+            other => Err(PivError::Apdu { sw: other }),
+        }
     }
 
-    /// Verify the PIV PIN. The PIN is padded to 8 bytes with 0xFF per the spec.
-    pub fn verify_pin(&self, pin: &str) -> Result<(), PivError> {
-        let apdu = Apdu::verify_pin(pin.as_bytes());
+    /// Set how many wrong attempts the PIN and PUK allow (YubiKey
+    /// extension). The card also resets both to their defaults (PIN
+    /// 123456, PUK 12345678), so change them afterwards. Requires both
+    /// `auth_admin` and `verify_pin` in this transaction.
+    pub fn set_pin_retries(&mut self, pin_tries: u8, puk_tries: u8) -> Result<(), PivError> {
+        if pin_tries == 0 || puk_tries == 0 {
+            return Err(PivError::Other("PIN and PUK retry counts must be at least 1".into()));
+        }
+        let apdu = Apdu::new(0x00, yk_ins::SET_PIN_RETRIES, pin_tries, puk_tries);
         let (_, sw) = self.transmit(&apdu)?;
-        if sw.is_success() {
-            Ok(())
-        } else if sw.is_pin_incorrect() {
-            Err(PivError::PinIncorrect {
-                retries: sw.pin_retries_remaining().unwrap_or(0) as u32,
-            })
-        } else if sw.as_u16() == 0x6983 {
-            Err(PivError::PinBlocked)
-        } else {
-            Err(PivError::Apdu { sw: sw.as_u16() })
+        match sw.as_u16() {
+            _ if sw.is_success() => {
+                self.token.txn_reset = true;
+                Ok(())
+            }
+            sw::SECURITY_STATUS_NOT_SATISFIED if self.token.used_pin.is_none() => {
+                Err(PivError::PinRequired)
+            }
+            sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminRequired),
+            sw::INS_NOT_SUPPORTED => Err(not_yubikey("SET PIN RETRIES")),
+            other => Err(PivError::Apdu { sw: other }),
+        }
+    }
+
+    /// End the transaction now, reporting any error from the card.
+    pub fn end(mut self) -> Result<(), PivError> {
+        if !self.owned {
+            return Ok(());
+        }
+        self.owned = false;
+        self.token.end_transaction()
+    }
+}
+
+impl<T: CardTransport> Deref for PivTransaction<'_, T> {
+    type Target = PivToken<T>;
+
+    fn deref(&self) -> &PivToken<T> {
+        self.token
+    }
+}
+
+impl<T: CardTransport> DerefMut for PivTransaction<'_, T> {
+    fn deref_mut(&mut self) -> &mut PivToken<T> {
+        self.token
+    }
+}
+
+impl<T: CardTransport> Drop for PivTransaction<'_, T> {
+    fn drop(&mut self) {
+        if self.owned {
+            if let Err(e) = self.token.end_transaction() {
+                tracing::warn!(
+                    reader = %self.token.reader_name(),
+                    error = %e,
+                    "failed to end card transaction"
+                );
+            }
+        }
+    }
+}
+
+/// Unwrap the 0x82 response from a GENERAL AUTHENTICATE reply
+/// (0x7C { 0x82 = ... }).
+fn ga_response(resp: &[u8]) -> Result<&[u8], PivError> {
+    let mut reader = TlvReader::new(resp);
+    let outer_tag = reader.read_tag()?;
+    if outer_tag != 0x7C {
+        return Err(PivError::Tlv {
+            message: format!("expected GA response tag 0x7C, got {:#X}", outer_tag),
+        });
+    }
+    let inner_data = reader.read_value()?;
+
+    let mut inner_reader = TlvReader::new(inner_data);
+    let resp_tag = inner_reader.read_tag()?;
+    if resp_tag != ga_tag::RESPONSE as u32 {
+        return Err(PivError::Tlv {
+            message: format!("expected GA response tag 0x82, got {:#X}", resp_tag),
+        });
+    }
+    inner_reader.read_value()
+}
+
+/// The card algorithm and encoded point for an ECDH peer key: an
+/// uncompressed SEC1 point for NIST curves, the raw 32 bytes for X25519.
+fn peer_point(peer: &PKeyRef<Public>) -> Result<(PivAlgorithm, Vec<u8>), PivError> {
+    match peer.id() {
+        Id::EC => {
+            let ec = peer.ec_key()?;
+            let algorithm = match ec.group().curve_name() {
+                Some(Nid::X9_62_PRIME256V1) => PivAlgorithm::EcP256,
+                Some(Nid::SECP384R1) => PivAlgorithm::EcP384,
+                other => {
+                    return Err(PivError::UnsupportedAlgorithm(format!(
+                        "ECDH on curve {:?}",
+                        other
+                    )))
+                }
+            };
+            let mut ctx = BigNumContext::new()?;
+            let point = ec.public_key().to_bytes(
+                ec.group(),
+                PointConversionForm::UNCOMPRESSED,
+                &mut ctx,
+            )?;
+            Ok((algorithm, point))
+        }
+        Id::X25519 => Ok((PivAlgorithm::X25519, peer.raw_public_key()?)),
+        other => Err(PivError::UnsupportedAlgorithm(format!(
+            "ECDH with {:?} key",
+            other
+        ))),
+    }
+}
+
+fn not_yubikey(command: &str) -> PivError {
+    PivError::Other(format!("card does not support YubiKey {}", command))
+}
+
+/// GET SERIAL returns the serial as a big-endian u32.
+fn parse_serial(data: &[u8]) -> Result<u32, PivError> {
+    match data {
+        [a, b, c, d] => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => Err(PivError::Tlv {
+            message: format!("serial response is {} bytes, expected 4", data.len()),
+        }),
+    }
+}
+
+/// Data for CHANGE REFERENCE / RESET RETRY: two padded 8-byte values.
+fn reference_change_data(first: &str, second: &str) -> Result<Vec<u8>, PivError> {
+    let mut data = Vec::with_capacity(16);
+    data.extend_from_slice(&*pin::pad(first)?);
+    data.extend_from_slice(&*pin::pad(second)?);
+    Ok(data)
+}
+
+/// Map a failed VERIFY / CHANGE REFERENCE / RESET RETRY status to an error.
+fn pin_error(pin_type: PinType, status: StatusWord) -> PivError {
+    if let Some(retries) = status.pin_retries_remaining() {
+        return PivError::PinIncorrect {
+            retries: retries as u32,
+        };
+    }
+    match status.as_u16() {
+        sw::AUTH_METHOD_BLOCKED if pin_type == PinType::Puk => PivError::PukBlocked,
+        sw::AUTH_METHOD_BLOCKED => PivError::PinBlocked,
+        sw::REFERENCE_NOT_FOUND => PivError::Other(format!("card has no {:?} PIN", pin_type)),
+        other => PivError::Apdu { sw: other },
+    }
+}
+
+/// Map the status of a 9B GENERAL AUTHENTICATE step to an error.
+fn check_admin_sw(alg_id: u8, status: StatusWord) -> Result<(), PivError> {
+    match status.as_u16() {
+        _ if status.is_success() => Ok(()),
+        // The card's 9B key is a different algorithm (or there is none)
+        sw::INCORRECT_P1P2 => Err(PivError::UnsupportedAlgorithm(format!(
+            "card admin key is not {}",
+            admin::alg_name(alg_id)
+        ))),
+        sw::WRONG_DATA | sw::SECURITY_STATUS_NOT_SATISFIED => Err(PivError::AdminAuthFailed),
+        other => Err(PivError::Apdu { sw: other }),
+    }
+}
+
+/// Inspect the response to an extended-length SELECT. Returns the command
+/// data limit to use, or `None` if the response isn't a PIV Application
+/// Property Template (some cards treat an extended SELECT as a different
+/// command and reply with an unrelated object).
+fn parse_select_xlen(data: &[u8]) -> Option<usize> {
+    let mut reader = TlvReader::new(data);
+    if reader.read_tag().ok()? != PIV_TAG_APT {
+        return None;
+    }
+    reader.read_value().ok()?;
+
+    let mut max_cmd = XLEN_DEFAULT_MAX_DATA;
+    while reader.has_remaining() {
+        let tag = reader.read_tag().ok()?;
+        let value = reader.read_value().ok()?;
+        if tag != ISO_TAG_XLEN {
+            continue;
+        }
+        // 7F66 { 02 max command length, 02 max response length }
+        let mut xlen = TlvReader::new(value);
+        if xlen.read_tag().ok()? != 0x02 {
+            return None;
+        }
+        let cmd = xlen
+            .read_value()
+            .ok()?
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        // Leave room for the header and Lc/Le fields
+        if cmd > 256 + 9 {
+            max_cmd = (cmd - 9).min(0xFFFF);
         }
     }
+    Some(max_cmd)
 }
 
 impl PivContext {
@@ -0,0 +1,192 @@
+use std::ffi::CString;
+
+use ouroboros::self_referencing;
+use pcsc::{Card, Protocol, Protocols, ShareMode, Transaction};
+
+use crate::error::PivError;
+use crate::PivContext;
+
+/// What happens to the card's security state when a transaction ends or
+/// the connection is re-established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Leave the card as it is (PIN and admin status are kept)
+    Leave,
+    /// Reset the card, clearing any PIN or admin authentication
+    Reset,
+}
+
+/// A link to a card that can exchange raw APDUs.
+///
+/// `PivToken` speaks the PIV protocol on top of this; PC/SC is one backend,
+/// but anything that can answer APDUs (an in-process emulator, a recorded
+/// trace, a different reader stack) can stand in for it.
+pub trait CardTransport {
+    /// Send one encoded command APDU and return the raw response,
+    /// including the trailing SW1-SW2 bytes.
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError>;
+
+    /// Start an exclusive transaction: no other client may talk to the
+    /// card until `end_transaction` is called.
+    fn begin_transaction(&mut self) -> Result<(), PivError>;
+
+    /// End the current transaction.
+    fn end_transaction(&mut self, disposition: Disposition) -> Result<(), PivError>;
+
+    /// Re-establish the connection, e.g. after the card was reset by
+    /// another client.
+    fn reconnect(&mut self, disposition: Disposition) -> Result<(), PivError>;
+
+    /// Name of the reader the card is in.
+    fn reader_name(&self) -> &str;
+
+    /// Whether the link can carry extended-length APDUs. PC/SC only
+    /// supports them over T=1.
+    fn supports_extended_apdu(&self) -> bool {
+        true
+    }
+}
+
+impl<T: CardTransport + ?Sized> CardTransport for Box<T> {
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError> {
+        (**self).transmit(cmd)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), PivError> {
+        (**self).begin_transaction()
+    }
+
+    fn end_transaction(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        (**self).end_transaction(disposition)
+    }
+
+    fn reconnect(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        (**self).reconnect(disposition)
+    }
+
+    fn reader_name(&self) -> &str {
+        (**self).reader_name()
+    }
+
+    fn supports_extended_apdu(&self) -> bool {
+        (**self).supports_extended_apdu()
+    }
+}
+
+/// An open PC/SC transaction together with the card it borrows, so the
+/// two can be kept in one struct between `begin_transaction` and
+/// `end_transaction`.
+#[self_referencing]
+struct PcscTransaction {
+    card: Card,
+    /// Only taken when the transaction ends
+    #[borrows(mut card)]
+    #[covariant]
+    txn: Option<Transaction<'this>>,
+}
+
+/// PC/SC backend for `CardTransport`.
+pub struct PcscTransport {
+    /// The card while no transaction is open
+    card: Option<Card>,
+    /// The card inside the open transaction, if any
+    txn: Option<PcscTransaction>,
+    reader_name: String,
+}
+
+impl PcscTransport {
+    pub fn connect(ctx: &PivContext, reader: &str) -> Result<Self, PivError> {
+        let cstr = CString::new(reader).map_err(|e| PivError::Other(e.to_string()))?;
+        let card = ctx
+            .pcsc_context()
+            .connect(&cstr, ShareMode::Shared, Protocols::ANY)?;
+        Ok(Self {
+            card: Some(card),
+            txn: None,
+            reader_name: reader.to_string(),
+        })
+    }
+
+    /// The card handle, routed through the transaction while one is open.
+    fn card(&self) -> &Card {
+        match (&self.card, &self.txn) {
+            (Some(card), _) => card,
+            (None, Some(txn)) => txn
+                .borrow_txn()
+                .as_deref()
+                .expect("transaction is only taken when it ends"),
+            (None, None) => unreachable!("card is either idle or in a transaction"),
+        }
+    }
+
+    fn pcsc_disposition(disposition: Disposition) -> pcsc::Disposition {
+        match disposition {
+            Disposition::Leave => pcsc::Disposition::LeaveCard,
+            Disposition::Reset => pcsc::Disposition::ResetCard,
+        }
+    }
+}
+
+impl CardTransport for PcscTransport {
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError> {
+        let mut resp_buf = vec![0u8; pcsc::MAX_BUFFER_SIZE_EXTENDED];
+        let resp = self.card().transmit(cmd, &mut resp_buf)?;
+        Ok(resp.to_vec())
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), PivError> {
+        let card = self
+            .card
+            .take()
+            .ok_or_else(|| PivError::Other("transaction already in progress".into()))?;
+        let txn = PcscTransaction::try_new_or_recover(card, |card| card.transaction().map(Some));
+        match txn {
+            Ok(txn) => {
+                self.txn = Some(txn);
+                Ok(())
+            }
+            Err((e, heads)) => {
+                self.card = Some(heads.card);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn end_transaction(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        let mut txn = self
+            .txn
+            .take()
+            .ok_or_else(|| PivError::Other("no transaction in progress".into()))?;
+        let disposition = Self::pcsc_disposition(disposition);
+        let result = txn.with_txn_mut(|txn| match txn.take() {
+            Some(txn) => txn.end(disposition).map_err(|(_, e)| PivError::Pcsc(e)),
+            None => Ok(()),
+        });
+        self.card = Some(txn.into_heads().card);
+        result
+    }
+
+    fn reconnect(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        let init = Self::pcsc_disposition(disposition);
+        match (&mut self.card, &mut self.txn) {
+            (Some(card), _) => card.reconnect(ShareMode::Shared, Protocols::ANY, init)?,
+            (None, Some(txn)) => txn.with_txn_mut(|txn| match txn {
+                Some(txn) => txn.reconnect(ShareMode::Shared, Protocols::ANY, init),
+                None => Ok(()),
+            })?,
+            (None, None) => unreachable!("card is either idle or in a transaction"),
+        }
+        Ok(())
+    }
+
+    fn reader_name(&self) -> &str {
+        &self.reader_name
+    }
+
+    fn supports_extended_apdu(&self) -> bool {
+        self.card()
+            .status2_owned()
+            .map(|status| matches!(status.protocol2(), Some(Protocol::T1)))
+            .unwrap_or(false)
+    }
+}
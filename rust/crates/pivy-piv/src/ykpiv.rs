@@ -0,0 +1,143 @@
+//! YubiKey PIV extensions: firmware version, serial number and key
+//! metadata. See
+//! <https://developers.yubico.com/PIV/Introduction/Yubico_extensions.html>.
+
+use std::fmt;
+
+use crate::error::PivError;
+use crate::keygen::{self, GeneratedKey};
+use crate::slot::{PinPolicy, PivAlgorithm, TouchPolicy};
+use crate::tlv::TlvReader;
+
+/// GET METADATA response tags
+const TAG_ALGORITHM: u32 = 0x01;
+const TAG_POLICY: u32 = 0x02;
+const TAG_ORIGIN: u32 = 0x03;
+const TAG_PUBLIC_KEY: u32 = 0x04;
+
+/// YubiKey firmware version, as returned by GET VERSION (INS 0xFD).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Version {
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PivError> {
+        match data {
+            [major, minor, patch, ..] => Ok(Self::new(*major, *minor, *patch)),
+            _ => Err(PivError::Tlv {
+                message: format!("version response is {} bytes, expected 3", data.len()),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// First firmware with the serial number in the PIV applet
+pub const SERIAL_IN_PIV: Version = Version::new(5, 0, 0);
+/// First firmware with GET METADATA
+pub const METADATA: Version = Version::new(5, 3, 0);
+
+/// How a key got into its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrigin {
+    Generated,
+    Imported,
+}
+
+/// What GET METADATA (INS 0xF7) reports about a key slot.
+pub struct SlotMetadata {
+    algorithm: PivAlgorithm,
+    pin_policy: PinPolicy,
+    touch_policy: TouchPolicy,
+    origin: Option<KeyOrigin>,
+    public_key: Option<GeneratedKey>,
+}
+
+impl SlotMetadata {
+    /// Parse a GET METADATA response for an asymmetric key slot.
+    pub fn parse(data: &[u8]) -> Result<Self, PivError> {
+        let mut reader = TlvReader::new(data);
+        let mut algorithm = None;
+        let mut policy = None;
+        let mut origin = None;
+        let mut public_key = None;
+        while reader.has_remaining() {
+            let tag = reader.read_tag()?;
+            let value = reader.read_value()?;
+            match (tag, value) {
+                (TAG_ALGORITHM, [alg]) => {
+                    algorithm = Some(PivAlgorithm::from_byte(*alg).ok_or_else(|| {
+                        PivError::UnsupportedAlgorithm(format!("algorithm {:#04X}", alg))
+                    })?);
+                }
+                (TAG_POLICY, [pin, touch]) => {
+                    policy = Some((
+                        PinPolicy::from_byte(*pin).unwrap_or_default(),
+                        TouchPolicy::from_byte(*touch).unwrap_or_default(),
+                    ));
+                }
+                (TAG_ORIGIN, [0x01]) => origin = Some(KeyOrigin::Generated),
+                (TAG_ORIGIN, [0x02]) => origin = Some(KeyOrigin::Imported),
+                (TAG_PUBLIC_KEY, fields) => public_key = Some(fields),
+                (TAG_ALGORITHM | TAG_POLICY | TAG_ORIGIN, _) => {
+                    return Err(PivError::Tlv {
+                        message: format!("metadata tag {:#04X} has bad value {:02X?}", tag, value),
+                    })
+                }
+                _ => {}
+            }
+        }
+        let algorithm = algorithm.ok_or_else(|| PivError::Tlv {
+            message: "metadata has no algorithm".into(),
+        })?;
+        let (pin_policy, touch_policy) = policy.unwrap_or_default();
+        let public_key = public_key
+            .map(|fields| keygen::parse_public_key_fields(algorithm, fields))
+            .transpose()?;
+        Ok(Self {
+            algorithm,
+            pin_policy,
+            touch_policy,
+            origin,
+            public_key,
+        })
+    }
+
+    pub fn algorithm(&self) -> PivAlgorithm {
+        self.algorithm
+    }
+
+    pub fn pin_policy(&self) -> PinPolicy {
+        self.pin_policy
+    }
+
+    pub fn touch_policy(&self) -> TouchPolicy {
+        self.touch_policy
+    }
+
+    pub fn origin(&self) -> Option<KeyOrigin> {
+        self.origin
+    }
+
+    /// The slot's public key, which the card reports even when no
+    /// certificate has been written for it.
+    pub fn public_key(&self) -> Option<&GeneratedKey> {
+        self.public_key.as_ref()
+    }
+}
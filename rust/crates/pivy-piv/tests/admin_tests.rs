@@ -0,0 +1,156 @@
+use pivy_piv::admin::{self, DEFAULT_ADMIN_KEY};
+use pivy_piv::apdu::alg;
+use pivy_piv::{PivError, TouchPolicy};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+const AES256_KEY: [u8; 32] = [0x42; 32];
+
+#[test]
+fn key_lengths() {
+    assert_eq!(admin::key_len(alg::TDEA_3KEY), Some(24));
+    assert_eq!(admin::key_len(alg::AES128), Some(16));
+    assert_eq!(admin::key_len(alg::AES192), Some(24));
+    assert_eq!(admin::key_len(alg::AES256), Some(32));
+    assert_eq!(admin::key_len(alg::ECCP256), None);
+}
+
+#[test]
+fn auth_with_default_3des_key() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    assert_eq!(reader.with_card_mut(|c| c.is_admin_authenticated()), Some(true));
+
+    // Admin rights end with the transaction
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.is_admin_authenticated()), Some(false));
+}
+
+#[test]
+fn auth_with_aes256_key() {
+    let card = VirtualCard::new().with_admin_key(alg::AES256, &AES256_KEY);
+    let (reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::AES256, &AES256_KEY).unwrap();
+    assert_eq!(reader.with_card_mut(|c| c.is_admin_authenticated()), Some(true));
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.auth_admin(alg::TDEA_3KEY, &[0x11; 24]),
+        Err(PivError::AdminAuthFailed)
+    ));
+    assert_eq!(reader.with_card_mut(|c| c.is_admin_authenticated()), Some(false));
+}
+
+#[test]
+fn wrong_algorithm_is_unsupported() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.auth_admin(alg::AES256, &AES256_KEY),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+    assert!(matches!(
+        txn.auth_admin(alg::ECCP256, &[0; 32]),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
+
+#[test]
+fn key_length_checked_before_sending() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.auth_admin(alg::TDEA_3KEY, &[0x01; 16]),
+        Err(PivError::InvalidKeyLength {
+            expected: 24,
+            actual: 16
+        })
+    ));
+}
+
+#[test]
+fn detects_default_key() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.has_default_admin_key().unwrap(), Some(alg::TDEA_3KEY));
+}
+
+#[test]
+fn detects_default_key_as_aes192() {
+    let card = VirtualCard::new().with_admin_key(alg::AES192, &DEFAULT_ADMIN_KEY);
+    let (_reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.has_default_admin_key().unwrap(), Some(alg::AES192));
+}
+
+#[test]
+fn changed_key_is_not_default() {
+    let card = VirtualCard::new().with_admin_key(alg::AES256, &AES256_KEY);
+    let (_reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.has_default_admin_key().unwrap(), None);
+}
+
+#[test]
+fn set_admin_key_to_aes256() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.set_admin_key(alg::AES256, &AES256_KEY, TouchPolicy::Default).unwrap();
+    drop(txn);
+    assert_eq!(
+        reader.with_card_mut(|c| c.admin_key() == (alg::AES256, &AES256_KEY[..])),
+        Some(true)
+    );
+    assert_eq!(reader.with_card_mut(|c| c.admin_requires_touch()), Some(false));
+
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.has_default_admin_key().unwrap(), None);
+    txn.auth_admin(alg::AES256, &AES256_KEY).unwrap();
+}
+
+#[test]
+fn set_admin_key_with_touch() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.set_admin_key(alg::TDEA_3KEY, &[0x24; 24], TouchPolicy::Always).unwrap();
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.admin_requires_touch()), Some(true));
+}
+
+#[test]
+fn set_admin_key_requires_auth() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.set_admin_key(alg::AES256, &AES256_KEY, TouchPolicy::Default),
+        Err(PivError::AdminRequired)
+    ));
+}
+
+#[test]
+fn set_admin_key_checks_arguments() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    assert!(matches!(
+        txn.set_admin_key(alg::AES128, &AES256_KEY, TouchPolicy::Default),
+        Err(PivError::InvalidKeyLength {
+            expected: 16,
+            actual: 32
+        })
+    ));
+    assert!(matches!(
+        txn.set_admin_key(alg::AES256, &AES256_KEY, TouchPolicy::Cached),
+        Err(PivError::Other(_))
+    ));
+}
@@ -96,3 +96,65 @@ fn apdu_with_le() {
     // Le=256 encoded as 0x00 in short form
     assert_eq!(bytes, &[0x00, 0xC0, 0x00, 0x00, 0x00]);
 }
+
+#[test]
+fn apdu_long_data_uses_extended_lc() {
+    let data = vec![0xAB; 300];
+    let mut apdu = Apdu::new(0x00, 0xDB, 0x3F, 0xFF);
+    apdu.data = data.clone();
+    let bytes = apdu.to_bytes();
+    // 3-byte Lc: 0x00 followed by big-endian length
+    assert_eq!(&bytes[4..7], &[0x00, 0x01, 0x2C]);
+    assert_eq!(&bytes[7..], data.as_slice());
+}
+
+#[test]
+fn apdu_extended_with_data_and_le() {
+    let mut apdu = Apdu::new(0x00, 0x87, 0x07, 0x9A);
+    apdu.data = vec![0x01, 0x02];
+    apdu.le = Some(0);
+    let bytes = apdu.to_extended_bytes();
+    assert_eq!(
+        bytes,
+        &[0x00, 0x87, 0x07, 0x9A, 0x00, 0x00, 0x02, 0x01, 0x02, 0x00, 0x00]
+    );
+}
+
+#[test]
+fn apdu_extended_le_only() {
+    let mut apdu = Apdu::new(0x00, 0xC0, 0x00, 0x00);
+    apdu.le = Some(0x0400);
+    let bytes = apdu.to_extended_bytes();
+    assert_eq!(bytes, &[0x00, 0xC0, 0x00, 0x00, 0x00, 0x04, 0x00]);
+}
+
+#[test]
+fn apdu_chain_splits_data() {
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let mut apdu = Apdu::new(0x00, 0xDB, 0x3F, 0xFF);
+    apdu.data = data.clone();
+    apdu.le = Some(0);
+
+    let chain = apdu.chain(255);
+    assert_eq!(chain.len(), 3);
+    assert_eq!(chain[0].cla, 0x10);
+    assert_eq!(chain[1].cla, 0x10);
+    assert_eq!(chain[2].cla, 0x00);
+    assert_eq!(chain[0].le, None);
+    assert_eq!(chain[2].le, Some(0));
+    assert_eq!(chain[0].data.len(), 255);
+    assert_eq!(chain[2].data.len(), 90);
+
+    let joined: Vec<u8> = chain.iter().flat_map(|a| a.data.clone()).collect();
+    assert_eq!(joined, data);
+    // Each segment fits a short APDU
+    assert!(chain.iter().all(|a| a.to_bytes()[4] as usize == a.data.len()));
+}
+
+#[test]
+fn apdu_chain_fits_unchanged() {
+    let apdu = Apdu::select(PIV_AID);
+    let chain = apdu.chain(255);
+    assert_eq!(chain.len(), 1);
+    assert_eq!(chain[0].to_bytes(), apdu.to_bytes());
+}
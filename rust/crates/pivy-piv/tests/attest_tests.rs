@@ -0,0 +1,104 @@
+use openssl::x509::X509;
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::attest::{self, Attestation};
+use pivy_piv::{
+    KeyPolicy, PinPolicy, PivAlgorithm, PivError, TouchPolicy, Version,
+};
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+
+mod common;
+use common::emulated_token;
+
+fn root(reader: &VirtualReader) -> X509 {
+    X509::from_der(&reader.with_card_mut(|c| c.attestation_root()).unwrap()).unwrap()
+}
+
+#[test]
+fn attest_generated_key() {
+    let card = VirtualCard::new().with_version(5, 7, 1).with_serial(12345678);
+    let (reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let policy = KeyPolicy {
+        pin: PinPolicy::Always,
+        touch: TouchPolicy::Cached,
+    };
+    let key = txn.generate(0x9A, PivAlgorithm::EcP256, policy).unwrap();
+    drop(txn);
+
+    let cert = token.attest(0x9A).unwrap();
+    let f9 = token.read_attestation_cert().unwrap();
+    let attestation = attest::verify(&cert, &f9, &root(&reader)).unwrap();
+
+    assert_eq!(attestation.firmware(), Some(Version::new(5, 7, 1)));
+    assert_eq!(attestation.serial(), Some(12345678));
+    assert_eq!(attestation.pin_policy(), Some(PinPolicy::Always));
+    assert_eq!(attestation.touch_policy(), Some(TouchPolicy::Cached));
+    assert!(attestation.form_factor().is_some());
+    assert_eq!(
+        attestation.public_key().unwrap().public_key_to_der().unwrap(),
+        key.spki()
+    );
+}
+
+#[test]
+fn attest_reports_slot_default_policy() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9E, alg::ECCP256));
+    let cert = token.attest(0x9E).unwrap();
+    let attestation = Attestation::parse(&cert).unwrap();
+    assert_eq!(attestation.pin_policy(), Some(PinPolicy::Never));
+    assert_eq!(attestation.touch_policy(), Some(TouchPolicy::Never));
+}
+
+#[test]
+fn attest_rsa_key() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9D, alg::RSA2048));
+    let cert = token.attest(0x9D).unwrap();
+    let f9 = token.read_attestation_cert().unwrap();
+    attest::verify(&cert, &f9, &root(&reader)).unwrap();
+}
+
+#[test]
+fn attest_empty_slot() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    assert!(matches!(token.attest(0x9C), Err(PivError::SlotEmpty(0x9C))));
+}
+
+#[test]
+fn verify_rejects_other_root() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9A, alg::ECCP256));
+    let cert = token.attest(0x9A).unwrap();
+    let f9 = token.read_attestation_cert().unwrap();
+
+    let other = VirtualCard::new();
+    let other_root = X509::from_der(&other.attestation_root()).unwrap();
+    assert!(matches!(
+        attest::verify(&cert, &f9, &other_root),
+        Err(PivError::Crypto(_))
+    ));
+}
+
+#[test]
+fn verify_rejects_other_intermediate() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9A, alg::ECCP256));
+    let cert = token.attest(0x9A).unwrap();
+
+    // A self-signed slot certificate is not an attestation key
+    let slot_cert = token.read_slot(0x9A).unwrap().cert_der().to_vec();
+    assert!(attest::verify(&cert, &slot_cert, &root(&reader)).is_err());
+}
+
+#[test]
+fn parse_plain_cert_has_no_extensions() {
+    let mut card = VirtualCard::new();
+    let cert = card.generate_with_cert(0x9A, alg::ECCP256);
+    let attestation = Attestation::parse(&cert).unwrap();
+    assert_eq!(attestation.firmware(), None);
+    assert_eq!(attestation.serial(), None);
+    assert_eq!(attestation.pin_policy(), None);
+}
@@ -0,0 +1,101 @@
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::cardcap::{CardType, DATA_MODEL_PIV, PIV_TAG_CARDCAP};
+use pivy_piv::{CardCapability, PivError};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+#[test]
+fn default_encoding() {
+    let cc = CardCapability::new();
+    let expected = [
+        0xF0, 0x07, 0xA0, 0x00, 0x00, 0x01, 0x16, 0xFF, 0x02, 0xF1, 0x01, 0x21, 0xF2, 0x01,
+        0x21, 0xF3, 0x00, 0xF4, 0x01, 0x00, 0xF5, 0x01, 0x10, 0xF6, 0x00, 0xF7, 0x00, 0xFA,
+        0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFD, 0x00, 0xFE, 0x00,
+    ];
+    assert_eq!(cc.encode(), expected);
+    assert_eq!(CardCapability::decode(&expected).unwrap(), cc);
+}
+
+#[test]
+fn random_id() {
+    let cc = CardCapability::with_random_id().unwrap();
+    assert_eq!(cc.card_id().len(), 21);
+    assert_eq!(cc.id().unwrap().len(), 14);
+    assert_eq!(cc.card_type(), Some(CardType::JavaCard));
+    assert_eq!(cc.manufacturer(), Some(0xFF));
+    assert_eq!(cc.data_model, DATA_MODEL_PIV);
+    assert_ne!(cc.id(), CardCapability::with_random_id().unwrap().id());
+
+    let decoded = CardCapability::decode(&cc.encode()).unwrap();
+    assert_eq!(decoded, cc);
+}
+
+#[test]
+fn id_setters() {
+    let mut cc = CardCapability::new();
+    cc.set_card_type(CardType::FileSystem);
+    cc.set_manufacturer(0x01);
+    cc.set_id(&[1, 2, 3]).unwrap();
+    assert_eq!(cc.card_id(), &[0xA0, 0x00, 0x00, 0x01, 0x16, 0x01, 0x01, 1, 2, 3]);
+    assert!(cc.set_id(&[0; 15]).is_err());
+}
+
+#[test]
+fn legacy_fields_are_kept() {
+    let data = [
+        0xF0, 0x02, 0xA0, 0x00, 0xF1, 0x01, 0x10, 0xF2, 0x01, 0x10, 0xF4, 0x01, 0x01, 0xF5,
+        0x01, 0x10, 0xF7, 0x06, 1, 2, 3, 4, 5, 6, 0xFE, 0x01, 0x33,
+    ];
+    let cc = CardCapability::decode(&data).unwrap();
+    assert_eq!(cc.card_type(), None);
+    assert_eq!(cc.id(), None);
+    assert!(cc.pkcs15);
+    assert_eq!(cc.card_apdus, Some([1, 2, 3, 4, 5, 6]));
+    assert_eq!(cc.lrc, Some(0x33));
+}
+
+#[test]
+fn missing_versions_rejected() {
+    let data = [0xF0, 0x00, 0xF1, 0x01, 0x21, 0xF5, 0x01, 0x10];
+    assert!(matches!(CardCapability::decode(&data), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn new_major_version_rejected() {
+    let data = [0xF1, 0x01, 0x31, 0xF2, 0x01, 0x21];
+    assert!(matches!(CardCapability::decode(&data), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn unknown_tag_rejected() {
+    let data = [0xF1, 0x01, 0x21, 0xF2, 0x01, 0x21, 0xF8, 0x00];
+    assert!(matches!(CardCapability::decode(&data), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn write_and_read_back() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    assert_eq!(token.read_cardcap().unwrap(), None);
+
+    let cc = CardCapability::with_random_id().unwrap();
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_cardcap(&cc).unwrap();
+    drop(txn);
+
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.read_cardcap().unwrap(), Some(cc.clone()));
+    drop(txn);
+    let stored = reader.with_card_mut(|c| c.object(PIV_TAG_CARDCAP).unwrap().to_vec()).unwrap();
+    assert_eq!(&stored[2..], cc.encode().as_slice());
+}
+
+#[test]
+fn write_needs_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let result = token.write_cardcap(&CardCapability::new());
+    assert!(matches!(result, Err(PivError::AdminRequired)));
+}
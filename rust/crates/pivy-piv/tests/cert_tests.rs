@@ -0,0 +1,103 @@
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::cert::{decode_cert_object, encode_cert_object, CERTINFO_GZIP};
+use pivy_piv::{PivAlgorithm, PivError, PivToken};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard, VirtualReader};
+
+mod common;
+use common::emulated_token;
+
+/// A token with a key and certificate generated in `slot`.
+fn token_with_cert(
+    slot: u8,
+    alg_id: u8,
+) -> (VirtualReader, PivToken<EmulatedTransport>, Vec<u8>) {
+    let mut card = VirtualCard::new();
+    let cert = card.generate_with_cert(slot, alg_id);
+    let (reader, token) = emulated_token(card);
+    (reader, token, cert)
+}
+
+#[test]
+fn cert_object_roundtrip() {
+    let der = b"not really a certificate".repeat(10);
+    let plain = encode_cert_object(&der, false).unwrap();
+    assert_eq!(plain[0], 0x70);
+    assert_eq!(decode_cert_object(&plain).unwrap().unwrap(), der);
+
+    let compressed = encode_cert_object(&der, true).unwrap();
+    assert!(compressed.len() < plain.len());
+    assert_eq!(decode_cert_object(&compressed).unwrap().unwrap(), der);
+}
+
+#[test]
+fn cert_object_certinfo_is_written() {
+    let object = encode_cert_object(&[0x30, 0x00], true).unwrap();
+    // ... 0x71 0x01 <certinfo> 0xFE 0x00
+    let tail = &object[object.len() - 5..];
+    assert_eq!(tail, &[0x71, 0x01, CERTINFO_GZIP, 0xFE, 0x00]);
+}
+
+#[test]
+fn cert_object_without_cert() {
+    assert_eq!(decode_cert_object(&[0x71, 0x01, 0x00]).unwrap(), None);
+}
+
+#[test]
+fn cert_object_rejects_bad_certinfo() {
+    // Non-X.509 flag
+    assert!(decode_cert_object(&[0x70, 0x01, 0x30, 0x71, 0x01, 0x04]).is_err());
+    // Unknown compression type
+    assert!(decode_cert_object(&[0x70, 0x01, 0x30, 0x71, 0x01, 0x02]).is_err());
+    // Claims gzip but isn't
+    assert!(decode_cert_object(&[0x70, 0x01, 0x30, 0x71, 0x01, 0x01]).is_err());
+}
+
+#[test]
+fn write_compressed_cert_and_read_back() {
+    let (reader, mut token, cert) = token_with_cert(0x9A, alg::RSA2048);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_cert(0x9A, &cert, true).unwrap();
+    drop(txn);
+
+    // The card holds the compressed form...
+    let stored = reader.with_card_mut(|c| c.object(0x5FC105).unwrap().to_vec()).unwrap();
+    assert_eq!(&stored[stored.len() - 5..], &[0x71, 0x01, CERTINFO_GZIP, 0xFE, 0x00]);
+
+    // ...and read_slot transparently inflates it
+    let slot = token.read_slot(0x9A).unwrap();
+    assert_eq!(slot.cert_der(), cert.as_slice());
+    assert_eq!(slot.algorithm(), PivAlgorithm::Rsa2048);
+}
+
+#[test]
+fn write_cert_to_retired_slot() {
+    let (_reader, mut token, cert) = token_with_cert(0x9E, alg::ECCP256);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_cert(0x82, &cert, false).unwrap();
+    assert_eq!(txn.read_slot(0x82).unwrap().cert_der(), cert.as_slice());
+}
+
+#[test]
+fn delete_cert_empties_slot() {
+    let (reader, mut token, _) = token_with_cert(0x9A, alg::ECCP256);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.delete_cert(0x9A).unwrap();
+    drop(txn);
+
+    assert!(reader.with_card_mut(|c| c.object(0x5FC105).is_none()).unwrap());
+    // The key itself is still there, and found without its certificate
+    assert!(token.read_slot(0x9A).unwrap().cert_der().is_empty());
+}
+
+#[test]
+fn write_cert_requires_admin() {
+    let (_reader, mut token, cert) = token_with_cert(0x9A, alg::ECCP256);
+    assert!(matches!(
+        token.write_cert(0x9A, &cert, false),
+        Err(PivError::AdminRequired)
+    ));
+}
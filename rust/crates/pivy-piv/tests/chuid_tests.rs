@@ -0,0 +1,212 @@
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::extension::BasicConstraints;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509NameBuilder, X509};
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::chuid::PIV_TAG_CHUID;
+use pivy_piv::{Chuid, Guid, PivError, PivToken};
+use pivy_piv_emu::{VirtualCard, VirtualReader};
+
+mod common;
+use common::{ec_key, emulated_token};
+
+const UUID: [u8; 16] = [
+    0x3C, 0x1A, 0x76, 0x44, 0x2B, 0x0F, 0x4E, 0x9D, 0x8A, 0x21, 0x55, 0x60, 0x13, 0x7E, 0x42,
+    0x9B,
+];
+
+/// A certificate for `key` named `cn`, signed by `issuer` (or self-signed).
+fn cert(
+    cn: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+    ca: bool,
+) -> X509 {
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(30).unwrap()).unwrap();
+    if ca {
+        builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+    }
+    let (issuer_name, signing_key) = match issuer {
+        Some((cert, key)) => (cert.subject_name(), key),
+        None => (name.as_ref(), key),
+    };
+    builder.set_issuer_name(issuer_name).unwrap();
+    builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+    builder.build()
+}
+
+struct Ca {
+    root: X509,
+    signer: X509,
+    signer_key: PKey<Private>,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let root_key = ec_key(Nid::X9_62_PRIME256V1);
+        let root = cert("Test Root", &root_key, None, true);
+        let signer_key = ec_key(Nid::X9_62_PRIME256V1);
+        let signer = cert("CHUID Signer", &signer_key, Some((&root, &root_key)), false);
+        Self {
+            root,
+            signer,
+            signer_key,
+        }
+    }
+
+    fn store(&self) -> X509Store {
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(self.root.clone()).unwrap();
+        store.build()
+    }
+}
+
+#[test]
+fn decode_default_card_chuid() {
+    let card = VirtualCard::new();
+    let guid = *card.guid();
+    let object = card.object(PIV_TAG_CHUID).unwrap().to_vec();
+    // Strip the 0x53 wrapper
+    let chuid = Chuid::decode(&object[2..]).unwrap();
+    assert_eq!(chuid.guid, Some(Guid::from_bytes(&guid).unwrap()));
+    assert_eq!(chuid.fascn.as_ref().map(|f| f.encode().len()), Some(25));
+    assert_eq!(chuid.expiry.as_deref(), Some("20991231"));
+    assert_eq!(chuid.cardholder_uuid, None);
+    assert!(!chuid.is_signed());
+    assert_eq!(chuid.encode(), &object[2..]);
+}
+
+#[test]
+fn encode_round_trip() {
+    let mut chuid = Chuid::with_random_guid().unwrap();
+    chuid.buffer_length = Some(0x3B);
+    chuid.org_id = Some(b"1234".to_vec());
+    chuid.duns = Some(b"123456789".to_vec());
+    chuid.expiry = Some("20301231".into());
+    chuid.cardholder_uuid = Some(UUID);
+    assert_eq!(Chuid::decode(&chuid.encode()).unwrap(), chuid);
+
+    // The signature and error detection code are not signed
+    let tbs = chuid.tbs();
+    assert!(chuid.encode().starts_with(&tbs));
+    assert_eq!(&chuid.encode()[tbs.len()..], &[0x3E, 0x00, 0xFE, 0x00]);
+}
+
+#[test]
+fn decode_rejects_bad_fields() {
+    assert!(matches!(
+        Chuid::decode(&[0x34, 0x02, 0x00, 0x01]),
+        Err(PivError::Tlv { .. })
+    ));
+    assert!(matches!(Chuid::decode(&[0x99, 0x00]), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn identity_falls_back_to_cardholder_uuid() {
+    let mut chuid = Chuid::new();
+    assert_eq!(chuid.identity(), None);
+    chuid.cardholder_uuid = Some(UUID);
+    assert_eq!(chuid.identity(), Some(Guid::from_bytes(&UUID).unwrap()));
+    chuid.guid = Some(Guid::from_bytes(&[0; 16]).unwrap());
+    assert_eq!(chuid.identity(), Some(Guid::from_bytes(&UUID).unwrap()));
+    chuid.guid = Some(Guid::from_bytes(&[7; 16]).unwrap());
+    assert_eq!(chuid.identity(), Some(Guid::from_bytes(&[7; 16]).unwrap()));
+}
+
+#[test]
+fn sign_and_verify() {
+    let ca = Ca::new();
+    let mut chuid = Chuid::with_random_guid().unwrap();
+    chuid.expiry = Some("20301231".into());
+    chuid.sign(&ca.signer, &ca.signer_key).unwrap();
+    assert!(chuid.is_signed());
+
+    let decoded = Chuid::decode(&chuid.encode()).unwrap();
+    decoded.verify(&[], &ca.store()).unwrap();
+    decoded.verify(std::slice::from_ref(&ca.signer), &ca.store()).unwrap();
+}
+
+#[test]
+fn verify_rejects_tampering_and_other_roots() {
+    let ca = Ca::new();
+    let mut chuid = Chuid::with_random_guid().unwrap();
+    chuid.sign(&ca.signer, &ca.signer_key).unwrap();
+
+    let other = Ca::new();
+    assert!(matches!(
+        chuid.verify(&[], &other.store()),
+        Err(PivError::Crypto(_))
+    ));
+
+    let mut tampered = chuid.clone();
+    tampered.guid = Some(Guid::from_bytes(&[9; 16]).unwrap());
+    assert!(matches!(
+        tampered.verify(&[], &ca.store()),
+        Err(PivError::Crypto(_))
+    ));
+
+    chuid.clear_signature();
+    assert!(matches!(chuid.verify(&[], &ca.store()), Err(PivError::Crypto(_))));
+}
+
+#[test]
+fn token_exposes_chuid() {
+    let card = VirtualCard::new();
+    let guid = *card.guid();
+    let (_reader, token) = emulated_token(card);
+    assert_eq!(token.guid().as_bytes(), &guid);
+    assert_eq!(token.chuid().unwrap().expiry.as_deref(), Some("20991231"));
+}
+
+#[test]
+fn card_without_guid_uses_cardholder_uuid() {
+    let mut card = VirtualCard::new();
+    let mut chuid = Chuid::new();
+    chuid.cardholder_uuid = Some(UUID);
+    card.set_object(PIV_TAG_CHUID, &chuid.encode());
+
+    let (_reader, token) = emulated_token(card);
+    assert_eq!(token.guid().as_bytes(), &UUID);
+}
+
+#[test]
+fn card_without_identity_fails() {
+    let mut card = VirtualCard::new();
+    card.set_object(PIV_TAG_CHUID, &[0x3E, 0x00, 0xFE, 0x00]);
+    let reader = VirtualReader::with_card("Virtual Reader 00", card);
+    assert!(PivToken::open(reader.connect().unwrap()).is_err());
+}
+
+#[test]
+fn write_chuid_changes_identity() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let ca = Ca::new();
+    let mut chuid = Chuid::with_random_guid().unwrap();
+    chuid.sign(&ca.signer, &ca.signer_key).unwrap();
+
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_chuid(&chuid).unwrap();
+    drop(txn);
+    assert_eq!(token.guid(), chuid.guid.as_ref().unwrap());
+
+    // A fresh connection reads back the same signed CHUID
+    let token = PivToken::open(reader.connect().unwrap()).unwrap();
+    assert_eq!(token.guid(), chuid.guid.as_ref().unwrap());
+    token.chuid().unwrap().verify(&[], &ca.store()).unwrap();
+}
@@ -0,0 +1,37 @@
+//! Fixtures shared by the integration tests.
+
+// Each test crate uses only some of these
+#![allow(dead_code)]
+
+use openssl::bn::BigNumContext;
+use openssl::ec::{EcGroup, EcKey, PointConversionForm};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use pivy_piv::PivToken;
+use pivy_piv_emu::{EmulatedTransport, VirtualCard, VirtualReader};
+use ssh_key::public::{EcdsaPublicKey, KeyData};
+
+/// Put `card` in a virtual reader and open a token on it. The reader is
+/// returned too so tests can reach the card behind the token's back.
+pub fn emulated_token(card: VirtualCard) -> (VirtualReader, PivToken<EmulatedTransport>) {
+    let reader = VirtualReader::with_card("Virtual Reader 00", card);
+    let token = PivToken::open(reader.connect().unwrap()).unwrap();
+    (reader, token)
+}
+
+/// A fresh EC key pair on the curve `nid`.
+pub fn ec_key(nid: Nid) -> PKey<Private> {
+    let group = EcGroup::from_curve_name(nid).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+/// The public half of an EC key, as SSH sees it.
+pub fn ssh_public(key: &PKey<Private>) -> KeyData {
+    let ec = key.ec_key().unwrap();
+    let mut ctx = BigNumContext::new().unwrap();
+    let point = ec
+        .public_key()
+        .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+        .unwrap();
+    KeyData::Ecdsa(EcdsaPublicKey::from_sec1_bytes(&point).unwrap())
+}
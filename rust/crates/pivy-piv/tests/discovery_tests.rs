@@ -0,0 +1,108 @@
+use pivy_piv::apdu::PIV_AID;
+use pivy_piv::discovery::PIV_TAG_DISCOVERY;
+use pivy_piv::pin::PinType;
+use pivy_piv::{Discovery, PinStatus, PivError};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+fn discovery(policy: [u8; 2]) -> Vec<u8> {
+    let mut data = vec![0x7E, 0x12, 0x4F, 0x0B];
+    data.extend_from_slice(PIV_AID);
+    data.extend_from_slice(&[0x5F, 0x2F, 0x02]);
+    data.extend_from_slice(&policy);
+    data
+}
+
+#[test]
+fn decode_policies() {
+    let app_only = Discovery::decode(&discovery([0x40, 0x00])).unwrap();
+    assert!(app_only.app_pin && !app_only.global_pin);
+    assert_eq!(app_only.preferred_pin(), PinType::Piv);
+
+    let both = Discovery::decode(&discovery([0x60, 0x10])).unwrap();
+    assert!(both.app_pin && both.global_pin && !both.global_pin_primary);
+    assert_eq!(both.preferred_pin(), PinType::Piv);
+
+    let global_primary = Discovery::decode(&discovery([0x60, 0x20])).unwrap();
+    assert!(global_primary.global_pin_primary);
+    assert_eq!(global_primary.preferred_pin(), PinType::Global);
+
+    let global_only = Discovery::decode(&discovery([0x20, 0x00])).unwrap();
+    assert_eq!(global_only.preferred_pin(), PinType::Global);
+
+    let occ = Discovery::decode(&discovery([0x58, 0x00])).unwrap();
+    assert!(occ.occ && occ.vci);
+}
+
+#[test]
+fn encode_round_trip() {
+    for policy in [[0x40, 0x00], [0x60, 0x10], [0x60, 0x20], [0x20, 0x00], [0x58, 0x00]] {
+        let data = discovery(policy);
+        assert_eq!(Discovery::decode(&data).unwrap().encode(), data);
+    }
+}
+
+#[test]
+fn truncated_aid_accepted() {
+    let data = [0x7E, 0x0B, 0x4F, 0x04, 0xA0, 0x00, 0x00, 0x03, 0x5F, 0x2F, 0x02, 0x40, 0x00];
+    assert!(Discovery::decode(&data).unwrap().app_pin);
+}
+
+#[test]
+fn other_aid_rejected() {
+    let data = [0x7E, 0x0B, 0x4F, 0x04, 0xA0, 0x00, 0x00, 0x05, 0x5F, 0x2F, 0x02, 0x40, 0x00];
+    assert!(matches!(Discovery::decode(&data), Err(PivError::Tlv { .. })));
+    assert!(Discovery::decode(&[0x53, 0x00]).is_err());
+}
+
+#[test]
+fn card_discovery() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let disc = token.discovery().unwrap().unwrap();
+    assert!(disc.app_pin && !disc.global_pin);
+    assert_eq!(token.preferred_pin(), PinType::Piv);
+}
+
+#[test]
+fn card_without_discovery_uses_piv_pin() {
+    let mut card = VirtualCard::new();
+    card.remove_object(PIV_TAG_DISCOVERY);
+    let (_reader, mut token) = emulated_token(card);
+    assert_eq!(token.discovery().unwrap(), None);
+    assert_eq!(token.preferred_pin(), PinType::Piv);
+}
+
+#[test]
+fn card_with_bad_discovery_uses_piv_pin() {
+    let mut card = VirtualCard::new().with_global_pin("13579").with_global_pin_primary();
+    card.set_object(PIV_TAG_DISCOVERY, &[0x7E, 0x12, 0x4F]);
+    let (_reader, mut token) = emulated_token(card);
+    assert!(token.discovery().is_err());
+    assert_eq!(token.preferred_pin(), PinType::Piv);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(txn.pin_status(PinType::Piv).unwrap(), PinStatus::Verified);
+}
+
+#[test]
+fn verify_pin_uses_piv_pin_when_primary() {
+    let card = VirtualCard::new().with_global_pin("13579");
+    let (_reader, mut token) = emulated_token(card);
+    assert_eq!(token.preferred_pin(), PinType::Piv);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(txn.pin_status(PinType::Piv).unwrap(), PinStatus::Verified);
+}
+
+#[test]
+fn verify_pin_uses_global_pin_when_primary() {
+    let card = VirtualCard::new().with_global_pin("13579").with_global_pin_primary();
+    let (_reader, mut token) = emulated_token(card);
+    assert_eq!(token.preferred_pin(), PinType::Global);
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(txn.verify_pin("123456"), Err(PivError::PinIncorrect { .. })));
+    txn.verify_pin("13579").unwrap();
+    assert_eq!(txn.pin_status(PinType::Global).unwrap(), PinStatus::Verified);
+}
@@ -0,0 +1,107 @@
+use openssl::derive::Deriver;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::cert::pkey_from_ssh_public;
+use pivy_piv::{KeyPolicy, PivAlgorithm, PivError};
+use pivy_piv_emu::VirtualCard;
+use ssh_key::public::{Ed25519PublicKey, KeyData};
+
+mod common;
+use common::{ec_key, emulated_token, ssh_public};
+
+/// Generate a key in 9D, then check the card and a local ephemeral key
+/// agree on the same secret.
+fn agree(algorithm: PivAlgorithm, ephemeral: PKey<Private>) {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let card_key = txn.generate(0x9D, algorithm, KeyPolicy::default()).unwrap();
+    txn.verify_pin("123456").unwrap();
+
+    let peer = PKey::public_key_from_der(&ephemeral.public_key_to_der().unwrap()).unwrap();
+    let secret = txn.ecdh(0x9D, &peer).unwrap();
+
+    let card_pub = card_key.pkey().unwrap();
+    let mut deriver = Deriver::new(&ephemeral).unwrap();
+    deriver.set_peer(&card_pub).unwrap();
+    assert_eq!(secret.as_slice(), deriver.derive_to_vec().unwrap().as_slice());
+}
+
+#[test]
+fn ecdh_p256() {
+    agree(PivAlgorithm::EcP256, ec_key(Nid::X9_62_PRIME256V1));
+}
+
+#[test]
+fn ecdh_p384() {
+    agree(PivAlgorithm::EcP384, ec_key(Nid::SECP384R1));
+}
+
+#[test]
+fn ecdh_x25519() {
+    agree(PivAlgorithm::X25519, PKey::generate_x25519().unwrap());
+}
+
+#[test]
+fn ecdh_needs_pin() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9D, alg::ECCP256));
+    let peer = ec_key(Nid::X9_62_PRIME256V1);
+    let peer = PKey::public_key_from_der(&peer.public_key_to_der().unwrap()).unwrap();
+    assert!(matches!(token.ecdh(0x9D, &peer), Err(PivError::PinRequired)));
+}
+
+#[test]
+fn ecdh_curve_mismatch() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9D, alg::ECCP256));
+    let peer = ec_key(Nid::SECP384R1);
+    let peer = PKey::public_key_from_der(&peer.public_key_to_der().unwrap()).unwrap();
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert!(matches!(
+        txn.ecdh(0x9D, &peer),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
+
+#[test]
+fn ecdh_rejects_rsa_peer() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let peer = PKey::public_key_from_der(&rsa.public_key_to_der().unwrap()).unwrap();
+    assert!(matches!(
+        token.ecdh(0x9D, &peer),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
+
+#[test]
+fn ecdh_with_ssh_peer() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let card_key = txn.generate(0x9D, PivAlgorithm::EcP384, KeyPolicy::default()).unwrap();
+    txn.verify_pin("123456").unwrap();
+
+    let ephemeral = ec_key(Nid::SECP384R1);
+    let peer = pkey_from_ssh_public(&ssh_public(&ephemeral)).unwrap();
+    let secret = txn.ecdh(0x9D, &peer).unwrap();
+
+    let card_pub = card_key.pkey().unwrap();
+    let mut deriver = Deriver::new(&ephemeral).unwrap();
+    deriver.set_peer(&card_pub).unwrap();
+    assert_eq!(secret.as_slice(), deriver.derive_to_vec().unwrap().as_slice());
+}
+
+#[test]
+fn ssh_peer_must_be_ec() {
+    let ed25519 = KeyData::Ed25519(Ed25519PublicKey([7; 32]));
+    assert!(matches!(
+        pkey_from_ssh_public(&ed25519),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
@@ -0,0 +1,126 @@
+use pivy_piv::chuid::PIV_TAG_CHUID;
+use pivy_piv::fascn::{Association, OrgCategory, FASCN_LEN};
+use pivy_piv::{Chuid, Fascn, PivError};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+/// FASC-N written by pivy-tool on unprovisioned cards
+const NOBODY: [u8; 25] = [
+    0xD4, 0xE7, 0x39, 0xDA, 0x73, 0x9C, 0xED, 0x39, 0xCE, 0x73, 0x9D, 0x83, 0x68, 0x58, 0x21,
+    0x08, 0x42, 0x10, 0x84, 0x21, 0xC8, 0x42, 0x10, 0xC3, 0xEB,
+];
+
+fn sample() -> Fascn {
+    let mut fascn = Fascn::default();
+    fascn.set_agency_code("70").unwrap();
+    fascn.set_system_code("1234").unwrap();
+    fascn.set_credential_number("4567").unwrap();
+    fascn.set_credential_series("2").unwrap();
+    fascn.set_individual_credential_issue("1").unwrap();
+    fascn.set_person(Association::Contractor, "123456789").unwrap();
+    fascn.set_org(OrgCategory::Federal, "7000").unwrap();
+    fascn
+}
+
+#[test]
+fn decode_nobody() {
+    let fascn = Fascn::decode(&NOBODY).unwrap();
+    assert!(!fascn.is_zero());
+    assert_eq!(fascn.agency_code(), "9999");
+    assert_eq!(fascn.system_code(), "9999");
+    assert_eq!(fascn.credential_number(), "999999");
+    assert_eq!(fascn.encode(), NOBODY);
+}
+
+#[test]
+fn round_trip() {
+    let fascn = sample();
+    let encoded = fascn.encode();
+    assert_eq!(encoded.len(), FASCN_LEN);
+    let decoded = Fascn::decode(&encoded).unwrap();
+    assert_eq!(decoded, fascn);
+    assert_eq!(decoded.credential_number(), "004567");
+    assert_eq!(decoded.person_identifier(), "0123456789");
+    assert_eq!(decoded.org_category(), OrgCategory::Federal);
+    assert_eq!(decoded.association(), Association::Contractor);
+}
+
+#[test]
+fn display() {
+    assert_eq!(
+        sample().to_string(),
+        "0070-1234-004567-2-1/federal:7000/contractor:0123456789"
+    );
+}
+
+#[test]
+fn all_zero() {
+    let fascn = Fascn::decode(&[0; 25]).unwrap();
+    assert!(fascn.is_zero());
+    assert_eq!(fascn.encode(), vec![0; 25]);
+    assert_eq!(
+        fascn.to_string(),
+        "0000-0000-000000-0-1/commercial:0000/employee:0000000000"
+    );
+
+    // Setting a field gives a real encoding
+    let mut fascn = fascn;
+    fascn.set_agency_code("1").unwrap();
+    assert!(!fascn.is_zero());
+    let decoded = Fascn::decode(&fascn.encode()).unwrap();
+    assert_eq!(decoded.agency_code(), "0001");
+}
+
+#[test]
+fn bad_lrc_rejected() {
+    let mut encoded = sample().encode();
+    // The LRC character starts at bit 195
+    encoded[24] ^= 0x18;
+    assert!(matches!(Fascn::decode(&encoded), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn bad_parity_rejected() {
+    let mut encoded = sample().encode();
+    encoded[3] ^= 0x01;
+    assert!(matches!(Fascn::decode(&encoded), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn truncated_rejected() {
+    let encoded = sample().encode();
+    assert!(Fascn::decode(&encoded[..20]).is_err());
+    assert!(Fascn::decode(&[encoded.as_slice(), &[0x12]].concat()).is_err());
+}
+
+#[test]
+fn setters_validate() {
+    let mut fascn = Fascn::default();
+    assert!(fascn.set_agency_code("12345").is_err());
+    assert!(fascn.set_system_code("12a").is_err());
+    assert!(fascn.set_person(Association::Civil, "12345678901").is_err());
+}
+
+#[test]
+fn card_fascn() {
+    let (_reader, token) = emulated_token(VirtualCard::new());
+    let fascn = token.chuid().unwrap().fascn.as_ref().unwrap();
+    assert_eq!(fascn.encode(), NOBODY);
+}
+
+#[test]
+fn identity_falls_back_to_fascn() {
+    let mut chuid = Chuid::new();
+    chuid.fascn = Some(sample());
+    let guid = chuid.identity().unwrap();
+    // agency, system, credential number, series and issue
+    let expected = openssl::sha::sha256(b"0070123400456721");
+    assert_eq!(guid.as_bytes(), &expected[..16]);
+
+    let mut card = VirtualCard::new();
+    card.set_object(PIV_TAG_CHUID, &chuid.encode());
+    let (_reader, token) = emulated_token(card);
+    assert_eq!(token.guid().as_bytes(), &expected[..16]);
+}
@@ -0,0 +1,181 @@
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::import;
+use pivy_piv::{
+    KeyOrigin, KeyPolicy, PinPolicy, PivAlgorithm, PivError, PivToken, TouchPolicy,
+};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard};
+use ssh_key::private::{Ed25519Keypair, KeypairData, RsaKeypair, RsaPrivateKey};
+use ssh_key::public::RsaPublicKey;
+use ssh_key::{Mpint, PrivateKey};
+
+mod common;
+use common::{ec_key, emulated_token};
+
+fn import(
+    token: &mut PivToken<EmulatedTransport>,
+    slot: u8,
+    key: &PKey<Private>,
+    policy: KeyPolicy,
+) -> Result<(), PivError> {
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.import_key(slot, key, policy)
+}
+
+/// Import `key` and check the card now holds the same public key.
+fn import_and_compare(key: &PKey<Private>, algorithm: PivAlgorithm) {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    import(&mut token, 0x9D, key, KeyPolicy::default()).unwrap();
+
+    let metadata = token.slot_metadata(0x9D).unwrap();
+    assert_eq!(metadata.algorithm(), algorithm);
+    assert_eq!(metadata.origin(), Some(KeyOrigin::Imported));
+    assert_eq!(
+        metadata.public_key().unwrap().spki(),
+        key.public_key_to_der().unwrap().as_slice()
+    );
+}
+
+#[test]
+fn import_rsa() {
+    for bits in [1024, 2048] {
+        let key = PKey::from_rsa(Rsa::generate(bits).unwrap()).unwrap();
+        let algorithm = if bits == 1024 {
+            PivAlgorithm::Rsa1024
+        } else {
+            PivAlgorithm::Rsa2048
+        };
+        import_and_compare(&key, algorithm);
+    }
+}
+
+#[test]
+fn import_ec() {
+    import_and_compare(&ec_key(Nid::X9_62_PRIME256V1), PivAlgorithm::EcP256);
+    import_and_compare(&ec_key(Nid::SECP384R1), PivAlgorithm::EcP384);
+}
+
+#[test]
+fn import_curve25519() {
+    import_and_compare(&PKey::generate_ed25519().unwrap(), PivAlgorithm::Ed25519);
+    import_and_compare(&PKey::generate_x25519().unwrap(), PivAlgorithm::X25519);
+}
+
+#[test]
+fn import_from_pem() {
+    let pem = ec_key(Nid::X9_62_PRIME256V1).private_key_to_pem_pkcs8().unwrap();
+    let key = PKey::private_key_from_pem(&pem).unwrap();
+    import_and_compare(&key, PivAlgorithm::EcP256);
+}
+
+#[test]
+fn imported_key_signs_with_policy() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let key = ec_key(Nid::X9_62_PRIME256V1);
+    let policy = KeyPolicy {
+        pin: PinPolicy::Never,
+        touch: TouchPolicy::Never,
+    };
+    import(&mut token, 0x9A, &key, policy).unwrap();
+    assert_eq!(token.slot_metadata(0x9A).unwrap().pin_policy(), PinPolicy::Never);
+
+    let digest = hash(MessageDigest::sha256(), b"escrow").unwrap();
+    let sig = token.sign_prehash(0x9A, &digest).unwrap();
+    let sig = EcdsaSig::from_der(&sig).unwrap();
+    assert!(sig.verify(&digest, &key.ec_key().unwrap()).unwrap());
+}
+
+#[test]
+fn same_key_on_two_cards() {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let (_primary_reader, mut primary) = emulated_token(VirtualCard::new());
+    let (_backup_reader, mut backup) = emulated_token(VirtualCard::new());
+    import(&mut primary, 0x9D, &key, KeyPolicy::default()).unwrap();
+    import(&mut backup, 0x9D, &key, KeyPolicy::default()).unwrap();
+    assert_eq!(
+        primary.slot_metadata(0x9D).unwrap().public_key().unwrap().spki(),
+        backup.slot_metadata(0x9D).unwrap().public_key().unwrap().spki()
+    );
+}
+
+#[test]
+fn import_requires_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let key = ec_key(Nid::X9_62_PRIME256V1);
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.import_key(0x9A, &key, KeyPolicy::default()),
+        Err(PivError::AdminRequired)
+    ));
+}
+
+#[test]
+fn import_rejects_unsupported_keys() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let p521 = ec_key(Nid::SECP521R1);
+    assert!(matches!(
+        import(&mut token, 0x9A, &p521, KeyPolicy::default()),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+    let rsa3 = Rsa::generate_with_e(1024, &openssl::bn::BigNum::from_u32(3).unwrap()).unwrap();
+    assert!(matches!(
+        import(&mut token, 0x9A, &PKey::from_rsa(rsa3).unwrap(), KeyPolicy::default()),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
+
+#[test]
+fn imported_key_cannot_be_attested() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    import(&mut token, 0x9A, &ec_key(Nid::X9_62_PRIME256V1), KeyPolicy::default()).unwrap();
+    assert!(matches!(token.attest(0x9A), Err(PivError::SlotEmpty(0x9A))));
+}
+
+#[test]
+fn import_ssh_ed25519() {
+    let key = PKey::generate_ed25519().unwrap();
+    let seed: [u8; 32] = key.raw_private_key().unwrap().try_into().unwrap();
+    let ssh = PrivateKey::new(
+        KeypairData::Ed25519(Ed25519Keypair::from_seed(&seed)),
+        "escrow",
+    )
+    .unwrap();
+
+    let converted = import::pkey_from_ssh(&ssh).unwrap();
+    assert_eq!(
+        converted.public_key_to_der().unwrap(),
+        key.public_key_to_der().unwrap()
+    );
+    import_and_compare(&converted, PivAlgorithm::Ed25519);
+}
+
+#[test]
+fn import_ssh_rsa() {
+    let rsa = Rsa::generate(2048).unwrap();
+    let mpint = |bn: &openssl::bn::BigNumRef| Mpint::from_positive_bytes(&bn.to_vec()).unwrap();
+    let keypair = RsaKeypair {
+        public: RsaPublicKey {
+            e: mpint(rsa.e()),
+            n: mpint(rsa.n()),
+        },
+        private: RsaPrivateKey {
+            d: mpint(rsa.d()),
+            iqmp: mpint(rsa.iqmp().unwrap()),
+            p: mpint(rsa.p().unwrap()),
+            q: mpint(rsa.q().unwrap()),
+        },
+    };
+    let ssh = PrivateKey::new(KeypairData::Rsa(keypair), "escrow").unwrap();
+
+    let converted = import::pkey_from_ssh(&ssh).unwrap();
+    let converted_rsa = converted.rsa().unwrap();
+    assert_eq!(converted_rsa.dmp1().unwrap(), rsa.dmp1().unwrap());
+    assert_eq!(converted_rsa.dmq1().unwrap(), rsa.dmq1().unwrap());
+    import_and_compare(&converted, PivAlgorithm::Rsa2048);
+}
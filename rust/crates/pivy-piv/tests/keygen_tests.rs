@@ -0,0 +1,97 @@
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::keygen::parse_public_key_template;
+use pivy_piv::{KeyPolicy, PinPolicy, PivAlgorithm, PivError, TouchPolicy};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+fn generate(algorithm: PivAlgorithm, slot: u8) {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let key = txn.generate(slot, algorithm, KeyPolicy::default()).unwrap();
+    drop(txn);
+
+    assert_eq!(key.algorithm(), algorithm);
+    let card_spki = reader
+        .with_card_mut(|c| c.key(slot).unwrap().public_key_der())
+        .unwrap();
+    assert_eq!(key.spki(), card_spki.as_slice());
+    assert_eq!(key.public_key().is_some(), algorithm != PivAlgorithm::X25519);
+}
+
+#[test]
+fn generate_rsa2048() {
+    generate(PivAlgorithm::Rsa2048, 0x9A);
+}
+
+#[test]
+fn generate_p256() {
+    generate(PivAlgorithm::EcP256, 0x9E);
+}
+
+#[test]
+fn generate_p384() {
+    generate(PivAlgorithm::EcP384, 0x9C);
+}
+
+#[test]
+fn generate_ed25519() {
+    generate(PivAlgorithm::Ed25519, 0x9A);
+}
+
+#[test]
+fn generate_x25519() {
+    generate(PivAlgorithm::X25519, 0x9D);
+}
+
+#[test]
+fn generate_sets_policies() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let policy = KeyPolicy {
+        pin: PinPolicy::Never,
+        touch: TouchPolicy::Never,
+    };
+    txn.generate(0x9A, PivAlgorithm::EcP256, policy).unwrap();
+    drop(txn);
+
+    let (pin, touch, generated) = reader
+        .with_card_mut(|c| {
+            let key = c.key(0x9A).unwrap();
+            (key.pin_policy, key.touch_policy, key.generated)
+        })
+        .unwrap();
+    assert_eq!(pin, 0x01);
+    assert_eq!(touch, 0x01);
+    assert!(generated);
+}
+
+#[test]
+fn generate_requires_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    assert!(matches!(
+        token.generate(0x9A, PivAlgorithm::EcP256, KeyPolicy::default()),
+        Err(PivError::AdminRequired)
+    ));
+}
+
+#[test]
+fn template_with_wrong_point_length_rejected() {
+    // 0x7F49 { 0x86 <31 bytes> }
+    let mut data = vec![0x7F, 0x49, 0x21, 0x86, 0x1F];
+    data.extend_from_slice(&[0x11; 31]);
+    assert!(parse_public_key_template(PivAlgorithm::Ed25519, &data).is_err());
+}
+
+#[test]
+fn template_with_wrong_outer_tag_rejected() {
+    let data = [0x53, 0x03, 0x86, 0x01, 0x00];
+    assert!(matches!(
+        parse_public_key_template(PivAlgorithm::EcP256, &data),
+        Err(PivError::Tlv { .. })
+    ));
+}
@@ -0,0 +1,137 @@
+use std::cell::Cell;
+
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::{alg, ins};
+use pivy_piv::keyhist::PIV_TAG_KEYHIST;
+use pivy_piv::{CardTransport, Disposition, KeyHistory, PivError, PivToken};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard, VirtualReader};
+
+mod common;
+use common::emulated_token;
+
+/// Counts the GET DATA commands sent to the card.
+struct Counting {
+    inner: EmulatedTransport,
+    get_data: Cell<usize>,
+}
+
+impl CardTransport for Counting {
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError> {
+        if cmd.get(1) == Some(&ins::GET_DATA) {
+            self.get_data.set(self.get_data.get() + 1);
+        }
+        self.inner.transmit(cmd)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), PivError> {
+        self.inner.begin_transaction()
+    }
+
+    fn end_transaction(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        self.inner.end_transaction(disposition)
+    }
+
+    fn reconnect(&mut self, disposition: Disposition) -> Result<(), PivError> {
+        self.inner.reconnect(disposition)
+    }
+
+    fn reader_name(&self) -> &str {
+        self.inner.reader_name()
+    }
+}
+
+/// A card with certificates in 9A and the first `retired` retired slots.
+fn card_with_retired(retired: u8) -> VirtualCard {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9A, alg::ECCP256);
+    for slot in 0x82..0x82 + retired {
+        card.generate_with_cert(slot, alg::ECCP256);
+    }
+    card
+}
+
+#[test]
+fn decode_and_encode() {
+    let data = [
+        0xC1, 0x01, 0x02, 0xC2, 0x01, 0x01, 0xF3, 0x0B, b'h', b't', b't', b'p', b':', b'/',
+        b'/', b'x', b'.', b'y', b'/',
+    ];
+    let hist = KeyHistory::decode(&data).unwrap();
+    assert_eq!(hist.on_card, 2);
+    assert_eq!(hist.off_card, 1);
+    assert_eq!(hist.off_card_url.as_deref(), Some("http://x.y/"));
+    assert_eq!(hist.on_card_slots().collect::<Vec<_>>(), vec![0x82, 0x83]);
+    assert_eq!(hist.encode(), data);
+
+    // Trailing error detection code is skipped
+    let hist = KeyHistory::decode(&[0xC1, 0x01, 0x00, 0xC2, 0x01, 0x00, 0xFE, 0x00]).unwrap();
+    assert_eq!(hist, KeyHistory::default());
+}
+
+#[test]
+fn bad_objects_rejected() {
+    assert!(matches!(
+        KeyHistory::decode(&[0xC1, 0x02, 0x00, 0x01]),
+        Err(PivError::Tlv { .. })
+    ));
+    assert!(matches!(KeyHistory::decode(&[0xC3, 0x01, 0x00]), Err(PivError::Tlv { .. })));
+}
+
+#[test]
+fn counts_validated() {
+    assert!(KeyHistory::new(20, 0, None).is_ok());
+    assert!(KeyHistory::new(15, 6, Some("http://x.y/")).is_err());
+    assert!(KeyHistory::new(1, 1, None).is_err());
+}
+
+#[test]
+fn read_all_slots_follows_key_history() {
+    let reader = VirtualReader::with_card("Virtual Reader 00", card_with_retired(3));
+    let transport = Counting {
+        inner: reader.connect().unwrap(),
+        get_data: Cell::new(0),
+    };
+    let mut token = PivToken::open(transport).unwrap();
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_keyhistory(&KeyHistory::new(2, 0, None).unwrap()).unwrap();
+    drop(txn);
+
+    token.transport().get_data.set(0);
+    let slots = token.read_all_slots().unwrap();
+    let ids: Vec<u8> = slots.iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A, 0x82, 0x83]);
+    // Four standard slots and two retired ones; the key history written
+    // above is remembered
+    assert_eq!(token.transport().get_data.get(), 6);
+}
+
+#[test]
+fn no_key_history_skips_retired_slots() {
+    let (_reader, mut token) = emulated_token(card_with_retired(2));
+    assert_eq!(token.key_history().unwrap(), None);
+    let ids: Vec<u8> = token.read_all_slots().unwrap().iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A]);
+}
+
+#[test]
+fn key_history_read_from_card() {
+    let mut card = card_with_retired(1);
+    card.set_object(PIV_TAG_KEYHIST, &KeyHistory::new(1, 0, None).unwrap().encode());
+    let (_reader, mut token) = emulated_token(card);
+    assert_eq!(token.key_history().unwrap().map(|h| h.on_card), Some(1));
+    let ids: Vec<u8> = token.read_all_slots().unwrap().iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A, 0x82]);
+}
+
+#[test]
+fn write_needs_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let hist = KeyHistory::new(1, 0, None).unwrap();
+    assert!(matches!(token.write_keyhistory(&hist), Err(PivError::AdminRequired)));
+    let bad = KeyHistory {
+        on_card: 21,
+        ..KeyHistory::default()
+    };
+    assert!(matches!(token.write_keyhistory(&bad), Err(PivError::Other(_))));
+}
@@ -0,0 +1,126 @@
+use std::collections::HashSet;
+
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::chuid::PIV_TAG_CHUID;
+use pivy_piv::discovery::PIV_TAG_DISCOVERY;
+use pivy_piv::object::{
+    self, AccessRule, OBJECTS, PIV_TAG_BITGT, PIV_TAG_FACIAL_IMAGE, PIV_TAG_SECURITY_OBJECT,
+};
+use pivy_piv::slot::slot_to_cert_tag;
+use pivy_piv::{Discovery, PivError};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+#[test]
+fn registry_is_complete() {
+    assert_eq!(OBJECTS.len(), 36);
+    let tags: HashSet<u32> = OBJECTS.iter().map(|o| o.tag).collect();
+    assert_eq!(tags.len(), OBJECTS.len());
+    for slot in [0x9A, 0x9C, 0x9D, 0x9E].into_iter().chain(0x82..=0x95) {
+        let tag = slot_to_cert_tag(slot).unwrap();
+        assert_eq!(object::lookup(tag).unwrap().read, AccessRule::Always);
+    }
+    let mandatory: Vec<&str> = OBJECTS.iter().filter(|o| o.mandatory).map(|o| o.name).collect();
+    assert_eq!(mandatory.len(), 5);
+    assert_eq!(object::lookup(PIV_TAG_CHUID).unwrap().name, "Card Holder Unique Identifier");
+    assert!(object::lookup(0x5FFF01).is_none());
+}
+
+#[test]
+fn pin_protected_objects() {
+    let pin: Vec<u32> =
+        OBJECTS.iter().filter(|o| o.read == AccessRule::Pin).map(|o| o.tag).collect();
+    assert_eq!(pin, vec![0x5FC103, 0x5FC108, 0x5FC109, 0x5FC121, 0x5FC123]);
+    let unwrapped: Vec<u32> = OBJECTS.iter().filter(|o| !o.is_wrapped()).map(|o| o.tag).collect();
+    assert_eq!(unwrapped, vec![PIV_TAG_DISCOVERY, PIV_TAG_BITGT]);
+}
+
+#[test]
+fn read_wrapped_and_bare_objects() {
+    let card = VirtualCard::new();
+    let chuid = card.object(PIV_TAG_CHUID).unwrap().to_vec();
+    let (_reader, mut token) = emulated_token(card);
+
+    assert_eq!(token.read_object(PIV_TAG_CHUID).unwrap().unwrap(), &chuid[2..]);
+    let discovery = token.read_object(PIV_TAG_DISCOVERY).unwrap().unwrap();
+    assert!(Discovery::decode(&discovery).unwrap().app_pin);
+    assert_eq!(token.read_object(PIV_TAG_SECURITY_OBJECT).unwrap(), None);
+}
+
+#[test]
+fn pin_protected_read() {
+    let mut card = VirtualCard::new();
+    card.set_object(PIV_TAG_FACIAL_IMAGE, b"face");
+    let (_reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(txn.read_object(PIV_TAG_FACIAL_IMAGE), Err(PivError::PinRequired)));
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(txn.read_object(PIV_TAG_FACIAL_IMAGE).unwrap().unwrap(), b"face");
+}
+
+#[test]
+fn write_read_and_delete() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+
+    // Large enough to need command chaining
+    let big = vec![0xA5; 3000];
+    txn.write_object(PIV_TAG_SECURITY_OBJECT, &big).unwrap();
+    assert_eq!(txn.read_object(PIV_TAG_SECURITY_OBJECT).unwrap().unwrap(), big);
+    txn.write_object(PIV_TAG_SECURITY_OBJECT, &[]).unwrap();
+    assert_eq!(txn.read_object(PIV_TAG_SECURITY_OBJECT).unwrap(), None);
+
+    let bitgt = [0x7F, 0x61, 0x03, 0x02, 0x01, 0x00];
+    txn.write_object(PIV_TAG_BITGT, &bitgt).unwrap();
+    assert_eq!(txn.read_object(PIV_TAG_BITGT).unwrap().unwrap(), bitgt);
+}
+
+#[test]
+fn write_needs_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    assert!(matches!(
+        token.write_object(PIV_TAG_SECURITY_OBJECT, b"x"),
+        Err(PivError::AdminRequired)
+    ));
+    assert!(matches!(
+        token.write_object(PIV_TAG_BITGT, &[0x7F, 0x61, 0x00]),
+        Err(PivError::AdminRequired)
+    ));
+}
+
+#[test]
+fn back_up_and_restore() {
+    let mut source = VirtualCard::new();
+    source.generate_with_cert(0x9A, alg::ECCP256);
+    source.set_object(PIV_TAG_SECURITY_OBJECT, b"security object");
+    let (_src_reader, mut src) = emulated_token(source);
+
+    let mut backup = Vec::new();
+    let mut txn = src.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    for obj in OBJECTS {
+        if let Some(data) = txn.read_object(obj.tag).unwrap() {
+            backup.push((obj.tag, data));
+        }
+    }
+    drop(txn);
+    let tags: Vec<u32> = backup.iter().map(|(t, _)| *t).collect();
+    assert_eq!(tags, vec![PIV_TAG_CHUID, 0x5FC105, PIV_TAG_SECURITY_OBJECT, PIV_TAG_DISCOVERY]);
+
+    let (dst_reader, mut dst) = emulated_token(VirtualCard::new());
+    let mut txn = dst.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    for (tag, data) in backup.iter().filter(|(t, _)| *t != PIV_TAG_DISCOVERY) {
+        txn.write_object(*tag, data).unwrap();
+    }
+    drop(txn);
+    // Restoring the CHUID carries the identity over
+    assert_eq!(dst.guid(), src.guid());
+    assert_eq!(dst.chuid(), src.chuid());
+    let copied = dst_reader.with_card_mut(|c| c.object(0x5FC105).map(<[u8]>::to_vec));
+    assert!(copied.flatten().is_some());
+}
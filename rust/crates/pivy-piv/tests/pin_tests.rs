@@ -0,0 +1,183 @@
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::{PinStatus, PinType, PivError};
+use pivy_piv_emu::VirtualCard;
+
+mod common;
+use common::emulated_token;
+
+#[test]
+fn change_pin_then_verify_new() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.change_pin(PinType::Piv, "123456", "654321").unwrap();
+    assert!(matches!(
+        txn.verify_pin("123456"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    txn.verify_pin("654321").unwrap();
+}
+
+#[test]
+fn change_pin_wrong_old_counts_down() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.change_pin(PinType::Piv, "000000", "654321"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.pin_retries()), Some(2));
+}
+
+#[test]
+fn change_puk() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.change_puk("12345678", "87654321").unwrap();
+    assert!(matches!(
+        txn.change_puk("12345678", "11111111"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    txn.reset_pin_with_puk("87654321", "222222").unwrap();
+}
+
+#[test]
+fn reset_with_puk_unblocks_pin() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    for _ in 0..3 {
+        assert!(txn.verify_pin("000000").is_err());
+    }
+    assert!(matches!(txn.verify_pin("123456"), Err(PivError::PinBlocked)));
+    assert_eq!(txn.pin_status(PinType::Piv).unwrap(), PinStatus::Blocked);
+
+    txn.reset_pin_with_puk("12345678", "246810").unwrap();
+    txn.verify_pin("246810").unwrap();
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.pin_retries()), Some(3));
+}
+
+#[test]
+fn wrong_puk_blocks_puk() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.reset_pin_with_puk("00000000", "246810"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    assert!(txn.reset_pin_with_puk("00000000", "246810").is_err());
+    assert!(txn.reset_pin_with_puk("00000000", "246810").is_err());
+    assert!(matches!(
+        txn.reset_pin_with_puk("12345678", "246810"),
+        Err(PivError::PukBlocked)
+    ));
+    assert!(matches!(
+        txn.change_puk("12345678", "87654321"),
+        Err(PivError::PukBlocked)
+    ));
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.puk_retries()), Some(0));
+}
+
+#[test]
+fn pin_status_does_not_use_attempts() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(txn.verify_pin("000000").is_err());
+    for _ in 0..3 {
+        assert_eq!(
+            txn.pin_status(PinType::Piv).unwrap(),
+            PinStatus::Unverified { retries: 2 }
+        );
+    }
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(txn.pin_status(PinType::Piv).unwrap(), PinStatus::Verified);
+
+    // Verified status is cleared when the transaction ends
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+    assert_eq!(
+        token.pin_status(PinType::Piv).unwrap(),
+        PinStatus::Unverified { retries: 3 }
+    );
+}
+
+#[test]
+fn global_pin_verifies() {
+    let card = VirtualCard::new().with_global_pin("13579");
+    let (reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.verify_pin_type(PinType::Global, "123456"),
+        Err(PivError::PinIncorrect { .. })
+    ));
+    txn.verify_pin_type(PinType::Global, "13579").unwrap();
+    assert_eq!(txn.pin_status(PinType::Global).unwrap(), PinStatus::Verified);
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+}
+
+#[test]
+fn both_pins_in_one_transaction() {
+    let card = VirtualCard::new().with_global_pin("13579");
+    let (reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin_type(PinType::Global, "13579").unwrap();
+    txn.verify_pin("123456").unwrap();
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+}
+
+#[test]
+fn global_pin_missing_is_reported() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.verify_pin_type(PinType::Global, "123456"),
+        Err(PivError::Other(_))
+    ));
+    assert!(txn.pin_status(PinType::Global).is_err());
+}
+
+#[test]
+fn pin_length_checked_before_sending() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(txn.verify_pin("123456789").is_err());
+    assert!(txn.verify_pin("").is_err());
+    assert!(txn.change_pin(PinType::Piv, "123456", "123456789").is_err());
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.pin_retries()), Some(3));
+}
+
+#[test]
+fn set_pin_retries_resets_pin_and_puk() {
+    let card = VirtualCard::new().with_pin("777777");
+    let (reader, mut token) = emulated_token(card);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.verify_pin("777777").unwrap();
+    txn.set_pin_retries(5, 8).unwrap();
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.pin_retries()), Some(5));
+    assert_eq!(reader.with_card_mut(|c| c.puk_retries()), Some(8));
+
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    txn.change_puk("12345678", "87654321").unwrap();
+}
+
+#[test]
+fn set_pin_retries_needs_pin_and_admin() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    assert!(matches!(txn.set_pin_retries(5, 5), Err(PivError::PinRequired)));
+    drop(txn);
+
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert!(matches!(txn.set_pin_retries(5, 5), Err(PivError::AdminRequired)));
+    assert!(txn.set_pin_retries(0, 5).is_err());
+}
@@ -0,0 +1,128 @@
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::pinfo::PinfoValue;
+use pivy_piv::{PivError, PivToken, PrintedInfo, TouchPolicy};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard};
+
+mod common;
+use common::emulated_token;
+
+const AES256_KEY: [u8; 32] = [0x42; 32];
+
+/// Change the management key and keep a PIN-protected copy, as
+/// pivy-tool does.
+fn protect_admin_key(token: &mut PivToken<EmulatedTransport>, alg_id: u8, key: &[u8]) {
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.set_admin_key(alg_id, key, TouchPolicy::Default).unwrap();
+    let mut info = PrintedInfo::new();
+    info.set_admin_key(key);
+    txn.write_pinfo(&info).unwrap();
+}
+
+#[test]
+fn decode_c_encoding() {
+    // Name, Yubico admin key, a uint and a bool kv entry
+    let data = [
+        0x01, 0x03, b'b', b'o', b'b', 0x88, 0x06, 0x89, 0x04, 0xAA, 0xBB, 0xCC, 0xDD, 0x90,
+        0x08, 0x01, 0x03, b'g', b'e', b'n', 0x03, 0x01, 0x07, 0x90, 0x05, 0x01, 0x01, b'x',
+        0x02, 0x00,
+    ];
+    let info = PrintedInfo::decode(&data).unwrap();
+    assert_eq!(info.name.as_deref(), Some("bob"));
+    assert_eq!(info.admin_key(), Some(&[0xAA, 0xBB, 0xCC, 0xDD][..]));
+    assert_eq!(info.kv("gen"), Some(&PinfoValue::Uint(7)));
+    assert_eq!(info.kv("x"), Some(&PinfoValue::Bool));
+    assert_eq!(info.encode().as_slice(), &data[..]);
+}
+
+#[test]
+fn encode_round_trip() {
+    let mut info = PrintedInfo::new();
+    info.name = Some("Alice".into());
+    info.expiry = Some("2030JAN01".into());
+    info.org_line_1 = Some("Example Org".into());
+    info.set_admin_key(&AES256_KEY);
+    info.set_kv("uint", PinfoValue::Uint(0x10000));
+    info.set_kv("text", PinfoValue::String("hi".into()));
+    info.set_kv("text", PinfoValue::String("replaced".into()));
+    assert_eq!(PrintedInfo::decode(&info.encode()).unwrap(), info);
+
+    info.clear_admin_key();
+    info.unset_kv("uint");
+    let decoded = PrintedInfo::decode(&info.encode()).unwrap();
+    assert_eq!(decoded.admin_key(), None);
+    assert_eq!(decoded.kv("uint"), None);
+    assert_eq!(decoded.kv("text"), Some(&PinfoValue::String("replaced".into())));
+}
+
+#[test]
+fn decode_rejects_unknown_yubico_tag() {
+    assert!(matches!(
+        PrintedInfo::decode(&[0x88, 0x02, 0x8A, 0x00]),
+        Err(PivError::Tlv { .. })
+    ));
+}
+
+#[test]
+fn pinfo_needs_pin_to_read() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    protect_admin_key(&mut token, alg::AES256, &AES256_KEY);
+
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(txn.read_pinfo(), Err(PivError::PinRequired)));
+    txn.verify_pin("123456").unwrap();
+    let info = txn.read_pinfo().unwrap().unwrap();
+    assert_eq!(info.admin_key(), Some(&AES256_KEY[..]));
+}
+
+#[test]
+fn missing_pinfo_is_none() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert!(txn.read_pinfo().unwrap().is_none());
+}
+
+#[test]
+fn auth_admin_with_pin() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    protect_admin_key(&mut token, alg::AES256, &AES256_KEY);
+
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.auth_admin_with_pin("123456").unwrap(), alg::AES256);
+    assert_eq!(reader.with_card_mut(|c| c.is_admin_authenticated()), Some(true));
+}
+
+#[test]
+fn auth_admin_with_pin_tries_aes192() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    protect_admin_key(&mut token, alg::AES192, &[0x33; 24]);
+    let mut txn = token.begin_transaction().unwrap();
+    assert_eq!(txn.auth_admin_with_pin("123456").unwrap(), alg::AES192);
+}
+
+#[test]
+fn auth_admin_with_pin_errors() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.auth_admin_with_pin("000000"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    assert!(matches!(txn.auth_admin_with_pin("123456"), Err(PivError::Other(_))));
+    drop(txn);
+
+    // A stale copy of an old key
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let mut info = PrintedInfo::new();
+    info.set_admin_key(&AES256_KEY);
+    txn.write_pinfo(&info).unwrap();
+    drop(txn);
+    let mut txn = token.begin_transaction().unwrap();
+    assert!(matches!(
+        txn.auth_admin_with_pin("123456"),
+        Err(PivError::AdminAuthFailed)
+    ));
+}
@@ -0,0 +1,127 @@
+use openssl::encrypt::Encrypter;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Padding;
+use pivy_piv::apdu::alg;
+use pivy_piv::rsa::{unpad_oaep, unpad_pkcs1_v15};
+use pivy_piv::{PivError, PivToken};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard};
+
+mod common;
+use common::emulated_token;
+
+fn token_with_rsa_key(alg_id: u8) -> (PivToken<EmulatedTransport>, PKey<Public>) {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9D, alg_id);
+    let der = card.key(0x9D).unwrap().public_key_der();
+    let (_reader, token) = emulated_token(card);
+    (token, PKey::public_key_from_der(&der).unwrap())
+}
+
+fn encrypt(key: &PKey<Public>, padding: Padding, md: Option<MessageDigest>, msg: &[u8]) -> Vec<u8> {
+    let mut enc = Encrypter::new(key).unwrap();
+    enc.set_rsa_padding(padding).unwrap();
+    if let Some(md) = md {
+        enc.set_rsa_oaep_md(md).unwrap();
+        enc.set_rsa_mgf1_md(md).unwrap();
+    }
+    let mut out = vec![0u8; enc.encrypt_len(msg).unwrap()];
+    let len = enc.encrypt(msg, &mut out).unwrap();
+    out.truncate(len);
+    out
+}
+
+#[test]
+fn decrypt_pkcs1_rsa2048() {
+    let (mut token, key) = token_with_rsa_key(alg::RSA2048);
+    let ct = encrypt(&key, Padding::PKCS1, None, b"wrapped file key");
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    let pt = txn.rsa_decrypt(0x9D, &ct).unwrap();
+    assert_eq!(pt.as_slice(), b"wrapped file key");
+}
+
+#[test]
+fn decrypt_pkcs1_rsa1024() {
+    let (mut token, key) = token_with_rsa_key(alg::RSA1024);
+    let ct = encrypt(&key, Padding::PKCS1, None, b"k");
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(txn.rsa_decrypt(0x9D, &ct).unwrap().as_slice(), b"k");
+}
+
+#[test]
+fn decrypt_oaep() {
+    let (mut token, key) = token_with_rsa_key(alg::RSA2048);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    for md in [MessageDigest::sha1(), MessageDigest::sha256()] {
+        let ct = encrypt(&key, Padding::PKCS1_OAEP, Some(md), b"age file key");
+        let pt = txn.rsa_decrypt_oaep(0x9D, &ct, md).unwrap();
+        assert_eq!(pt.as_slice(), b"age file key");
+    }
+}
+
+#[test]
+fn oaep_ciphertext_fails_pkcs1() {
+    let (mut token, key) = token_with_rsa_key(alg::RSA2048);
+    let ct = encrypt(&key, Padding::PKCS1_OAEP, Some(MessageDigest::sha1()), b"x");
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert!(matches!(txn.rsa_decrypt(0x9D, &ct), Err(PivError::Crypto(_))));
+}
+
+#[test]
+fn decrypt_needs_pin() {
+    let (mut token, key) = token_with_rsa_key(alg::RSA2048);
+    let ct = encrypt(&key, Padding::PKCS1, None, b"x");
+    assert!(matches!(token.rsa_decrypt(0x9D, &ct), Err(PivError::PinRequired)));
+}
+
+#[test]
+fn decrypt_wrong_length() {
+    let (mut token, _) = token_with_rsa_key(alg::RSA2048);
+    assert!(matches!(
+        token.rsa_decrypt(0x9D, &[0u8; 100]),
+        Err(PivError::Crypto(_))
+    ));
+    assert!(matches!(
+        token.rsa_decrypt(0x9D, &[0u8; 128]),
+        Err(PivError::UnsupportedAlgorithm(_))
+    ));
+}
+
+#[test]
+fn pkcs1_unpad_rules() {
+    let mut em = vec![0x00, 0x02];
+    em.extend_from_slice(&[0x11; 8]);
+    em.push(0x00);
+    em.extend_from_slice(b"msg");
+    assert_eq!(unpad_pkcs1_v15(&em).unwrap().as_slice(), b"msg");
+
+    // Empty message is allowed
+    let mut empty = em[..11].to_vec();
+    assert_eq!(unpad_pkcs1_v15(&empty).unwrap().len(), 0);
+
+    // Padding string shorter than 8 bytes
+    empty[9] = 0x00;
+    assert!(unpad_pkcs1_v15(&empty).is_none());
+
+    let mut bad = em.clone();
+    bad[0] = 0x01;
+    assert!(unpad_pkcs1_v15(&bad).is_none());
+    let mut bad = em.clone();
+    bad[1] = 0x01;
+    assert!(unpad_pkcs1_v15(&bad).is_none());
+    // No zero separator
+    let bad: Vec<u8> = [0x00, 0x02].into_iter().chain([0x22; 20]).collect();
+    assert!(unpad_pkcs1_v15(&bad).is_none());
+    assert!(unpad_pkcs1_v15(&[0x00, 0x02, 0x00]).is_none());
+}
+
+#[test]
+fn oaep_unpad_rejects_garbage() {
+    let md = MessageDigest::sha256();
+    assert!(unpad_oaep(&[0u8; 256], md).unwrap().is_none());
+    assert!(unpad_oaep(&[0u8; 10], md).unwrap().is_none());
+}
@@ -1,4 +1,14 @@
-use pivy_piv::PivContext;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::rsa::Padding;
+use openssl::x509::X509;
+
+use pivy_piv::apdu::alg;
+use pivy_piv::{PivContext, PivError, PivToken};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard, VirtualReader};
+
+mod common;
+use common::emulated_token;
 
 #[test]
 fn sign_with_9e_no_pin() {
@@ -11,7 +21,7 @@ fn sign_with_9e_no_pin() {
         }
     };
     let tokens = ctx.enumerate_tokens().unwrap_or_default();
-    let token = match tokens.into_iter().next() {
+    let mut token = match tokens.into_iter().next() {
         Some(t) => t,
         None => {
             eprintln!("No PIV tokens found, skipping");
@@ -31,3 +41,80 @@ fn sign_with_9e_no_pin() {
         Err(_) => eprintln!("Slot 9E empty, skipping"),
     }
 }
+
+fn token_with_cert(slot: u8, alg_id: u8) -> (VirtualReader, PivToken<EmulatedTransport>, X509) {
+    let mut card = VirtualCard::new();
+    let cert = card.generate_with_cert(slot, alg_id);
+    let (reader, token) = emulated_token(card);
+    (reader, token, X509::from_der(&cert).unwrap())
+}
+
+#[test]
+fn sign_9e_emulated_without_pin() {
+    let (_reader, mut token, cert) = token_with_cert(0x9E, alg::ECCP256);
+    let digest = hash(MessageDigest::sha256(), b"test data to sign").unwrap();
+    let sig = token.sign_prehash(0x9E, &digest).unwrap();
+
+    let ec = cert.public_key().unwrap().ec_key().unwrap();
+    let sig = EcdsaSig::from_der(&sig).unwrap();
+    assert!(sig.verify(&digest, &ec).unwrap());
+}
+
+#[test]
+fn sign_9a_emulated_requires_pin() {
+    let (_reader, mut token, cert) = token_with_cert(0x9A, alg::ECCP384);
+    let digest = hash(MessageDigest::sha384(), b"test data to sign").unwrap();
+    assert!(matches!(
+        token.sign_prehash(0x9A, &digest),
+        Err(PivError::PinRequired)
+    ));
+
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    let sig = txn.sign_prehash(0x9A, &digest).unwrap();
+    drop(txn);
+    let ec = cert.public_key().unwrap().ec_key().unwrap();
+    assert!(EcdsaSig::from_der(&sig).unwrap().verify(&digest, &ec).unwrap());
+}
+
+#[test]
+fn sign_rsa_emulated() {
+    let (_reader, mut token, cert) = token_with_cert(0x9A, alg::RSA2048);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+
+    // PKCS#1 v1.5 block built by hand, as the agent does
+    let mut block = vec![0xFF; 256];
+    block[0] = 0x00;
+    block[1] = 0x01;
+    block[255 - 32] = 0x00;
+    block[256 - 32..].copy_from_slice(&[0x5A; 32]);
+    let sig = txn.sign_prehash(0x9A, &block).unwrap();
+    drop(txn);
+
+    let rsa = cert.public_key().unwrap().rsa().unwrap();
+    let mut recovered = vec![0u8; 256];
+    let len = rsa
+        .public_decrypt(&sig, &mut recovered, Padding::NONE)
+        .unwrap();
+    assert_eq!(&recovered[..len], block.as_slice());
+}
+
+#[test]
+fn wrong_pin_counts_down_and_blocks() {
+    let (reader, mut token, _) = token_with_cert(0x9A, alg::ECCP256);
+    assert!(matches!(
+        token.verify_pin("000000"),
+        Err(PivError::PinIncorrect { retries: 2 })
+    ));
+    assert!(matches!(
+        token.verify_pin("000000"),
+        Err(PivError::PinIncorrect { retries: 1 })
+    ));
+    assert!(matches!(
+        token.verify_pin("000000"),
+        Err(PivError::PinIncorrect { retries: 0 }) | Err(PivError::PinBlocked)
+    ));
+    assert!(matches!(token.verify_pin("123456"), Err(PivError::PinBlocked)));
+    assert_eq!(reader.with_card_mut(|c| c.pin_retries()), Some(0));
+}
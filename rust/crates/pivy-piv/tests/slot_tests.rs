@@ -9,8 +9,8 @@ fn read_slots_from_token() {
             return;
         }
     };
-    let tokens = ctx.enumerate_tokens().unwrap_or_default();
-    for token in &tokens {
+    let mut tokens = ctx.enumerate_tokens().unwrap_or_default();
+    for token in &mut tokens {
         let slots = token.read_all_slots().unwrap_or_default();
         for slot in &slots {
             println!(
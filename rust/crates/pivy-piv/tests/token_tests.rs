@@ -1,4 +1,7 @@
-use pivy_piv::PivContext;
+use pivy_piv::apdu::alg;
+use pivy_piv::keyhist::PIV_TAG_KEYHIST;
+use pivy_piv::{KeyHistory, PivAlgorithm, PivContext, PivToken};
+use pivy_piv_emu::{VirtualCard, VirtualReader};
 
 #[test]
 fn connect_and_select() {
@@ -18,3 +21,53 @@ fn connect_and_select() {
         );
     }
 }
+
+#[test]
+fn connect_emulated_card() {
+    let guid = [0x11; 16];
+    let reader = VirtualReader::with_card("Virtual Reader 00", VirtualCard::new().with_guid(guid));
+    let token = PivToken::open(reader.connect().unwrap()).unwrap();
+    assert_eq!(token.guid().as_bytes(), &guid);
+    assert_eq!(token.reader_name(), "Virtual Reader 00");
+}
+
+#[test]
+fn read_all_slots_emulated() {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9A, alg::ECCP256);
+    card.generate_with_cert(0x9C, alg::RSA2048);
+    card.generate_with_cert(0x9E, alg::ECCP384);
+    card.generate_with_cert(0x82, alg::ECCP256);
+    card.set_object(PIV_TAG_KEYHIST, &KeyHistory::new(1, 0, None).unwrap().encode());
+    let reader = VirtualReader::with_card("Virtual Reader 00", card);
+    let mut token = PivToken::open(reader.connect().unwrap()).unwrap();
+
+    let slots = token.read_all_slots().unwrap();
+    let ids: Vec<u8> = slots.iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A, 0x9C, 0x9E, 0x82]);
+    assert_eq!(slots[0].algorithm(), PivAlgorithm::EcP256);
+    assert_eq!(slots[1].algorithm(), PivAlgorithm::Rsa2048);
+    assert_eq!(slots[2].algorithm(), PivAlgorithm::EcP384);
+}
+
+#[test]
+fn read_all_slots_with_bad_key_history() {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(0x9A, alg::ECCP256);
+    card.generate_with_cert(0x9E, alg::ECCP256);
+    // Truncated TLV
+    card.set_object(PIV_TAG_KEYHIST, &[0xC1, 0x05, 0x01]);
+    let reader = VirtualReader::with_card("Virtual Reader 00", card);
+    let mut token = PivToken::open(reader.connect().unwrap()).unwrap();
+
+    let slots = token.read_all_slots().unwrap();
+    let ids: Vec<u8> = slots.iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A, 0x9E]);
+    assert!(token.key_history().is_err());
+}
+
+#[test]
+fn connect_fails_without_card() {
+    let reader = VirtualReader::new("Virtual Reader 00");
+    assert!(reader.connect().is_err());
+}
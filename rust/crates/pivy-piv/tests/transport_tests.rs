@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use pivy_piv::apdu::Apdu;
+use pivy_piv::{CardTransport, Disposition, PivError, PivToken};
+
+/// Transport that replays canned responses and records every command.
+struct ScriptedTransport {
+    responses: RefCell<VecDeque<Vec<u8>>>,
+    sent: RefCell<Vec<Vec<u8>>>,
+    extended: bool,
+}
+
+impl ScriptedTransport {
+    fn new(extended: bool, responses: &[&[u8]]) -> Self {
+        Self {
+            responses: RefCell::new(responses.iter().map(|r| r.to_vec()).collect()),
+            sent: RefCell::new(Vec::new()),
+            extended,
+        }
+    }
+}
+
+impl CardTransport for ScriptedTransport {
+    fn transmit(&self, cmd: &[u8]) -> Result<Vec<u8>, PivError> {
+        self.sent.borrow_mut().push(cmd.to_vec());
+        self.responses
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| PivError::Other("script exhausted".into()))
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), PivError> {
+        Ok(())
+    }
+
+    fn end_transaction(&mut self, _disposition: Disposition) -> Result<(), PivError> {
+        Ok(())
+    }
+
+    fn reconnect(&mut self, _disposition: Disposition) -> Result<(), PivError> {
+        Ok(())
+    }
+
+    fn reader_name(&self) -> &str {
+        "Scripted Reader 00 00"
+    }
+
+    fn supports_extended_apdu(&self) -> bool {
+        self.extended
+    }
+}
+
+const GUID: [u8; 16] = [
+    0x99, 0x5E, 0x17, 0x13, 0x83, 0x02, 0x9C, 0xDA, 0x0D, 0x9C, 0xDB, 0xDB, 0xAD, 0x58, 0x08,
+    0x13,
+];
+
+/// GET DATA response for a CHUID containing only a GUID, plus SW 9000
+fn chuid_response() -> Vec<u8> {
+    let mut resp = vec![0x53, 0x12, 0x34, 0x10];
+    resp.extend_from_slice(&GUID);
+    resp.extend_from_slice(&[0x90, 0x00]);
+    resp
+}
+
+fn open_short(extra: &[&[u8]]) -> PivToken<ScriptedTransport> {
+    let chuid = chuid_response();
+    let mut script: Vec<&[u8]> = vec![&[0x90, 0x00], &chuid];
+    script.extend_from_slice(extra);
+    PivToken::open(ScriptedTransport::new(false, &script)).unwrap()
+}
+
+#[test]
+fn open_reads_guid() {
+    let token = open_short(&[]);
+    assert_eq!(token.guid().to_hex(), hex::encode_upper(GUID));
+    assert_eq!(token.reader_name(), "Scripted Reader 00 00");
+    let sent = token.transport().sent.borrow();
+    assert_eq!(&sent[0][..4], &[0x00, 0xA4, 0x04, 0x00]);
+    assert_eq!(&sent[1][..2], &[0x00, 0xCB]);
+}
+
+#[test]
+fn open_falls_back_when_extended_select_rejected() {
+    let chuid = chuid_response();
+    let script: Vec<&[u8]> = vec![&[0x67, 0x00], &[0x90, 0x00], &chuid];
+    let token = PivToken::open(ScriptedTransport::new(true, &script)).unwrap();
+    let sent = token.transport().sent.borrow();
+    // Extended SELECT: Lc is 0x00 0x00 0x0B, Le is 0x00 0x00
+    assert_eq!(&sent[0][4..7], &[0x00, 0x00, 0x0B]);
+    // Short SELECT retry
+    assert_eq!(sent[1][4], 0x0B);
+}
+
+#[test]
+fn long_command_is_chained_on_short_only_card() {
+    let token = open_short(&[&[0x90, 0x00], &[0x90, 0x00]]);
+    let mut apdu = Apdu::new(0x00, 0xDB, 0x3F, 0xFF);
+    apdu.data = vec![0x55; 400];
+    let (_, sw) = token.transmit_apdu(&apdu).unwrap();
+    assert!(sw.is_success());
+
+    let sent = token.transport().sent.borrow();
+    let chained = &sent[2..];
+    assert_eq!(chained.len(), 2);
+    assert_eq!(chained[0][0], 0x10);
+    assert_eq!(chained[0][4], 0xFF);
+    assert_eq!(chained[1][0], 0x00);
+    assert_eq!(chained[1][4], 145);
+}
+
+#[test]
+fn long_command_is_extended_on_capable_card() {
+    let chuid = chuid_response();
+    // APT with no 7F66 -> conservative default limit (0x7FF)
+    let select: &[u8] = &[0x61, 0x03, 0x4F, 0x01, 0x00, 0x90, 0x00];
+    let script: Vec<&[u8]> = vec![select, &chuid, &[0x90, 0x00]];
+    let token = PivToken::open(ScriptedTransport::new(true, &script)).unwrap();
+
+    let mut apdu = Apdu::new(0x00, 0xDB, 0x3F, 0xFF);
+    apdu.data = vec![0x55; 400];
+    let (_, sw) = token.transmit_apdu(&apdu).unwrap();
+    assert!(sw.is_success());
+
+    let sent = token.transport().sent.borrow();
+    assert_eq!(sent.len(), 3);
+    assert_eq!(&sent[2][4..7], &[0x00, 0x01, 0x90]);
+}
+
+#[test]
+fn chained_response_is_collected() {
+    let token = open_short(&[&[0xAA, 0xBB, 0x61, 0x02], &[0xCC, 0xDD, 0x90, 0x00]]);
+    let apdu = Apdu::get_data(0x5FC105);
+    let (data, sw) = token.transmit_apdu(&apdu).unwrap();
+    assert!(sw.is_success());
+    assert_eq!(data, &[0xAA, 0xBB, 0xCC, 0xDD]);
+
+    let sent = token.transport().sent.borrow();
+    // GET RESPONSE with Le from SW2
+    assert_eq!(sent[3], &[0x00, 0xC0, 0x00, 0x00, 0x02]);
+}
@@ -0,0 +1,86 @@
+use openssl::hash::{hash, MessageDigest};
+
+use pivy_piv::apdu::{alg, Apdu, PIV_AID};
+use pivy_piv::{CardTransport, PivError, PivToken};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard, VirtualReader};
+
+mod common;
+use common::emulated_token;
+
+fn token_with_key(slot: u8) -> (VirtualReader, PivToken<EmulatedTransport>) {
+    let mut card = VirtualCard::new();
+    card.generate_with_cert(slot, alg::ECCP256);
+    emulated_token(card)
+}
+
+fn digest() -> Vec<u8> {
+    hash(MessageDigest::sha256(), b"test data to sign")
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn transaction_excludes_other_clients() {
+    let (reader, mut token) = token_with_key(0x9E);
+    let other = reader.connect().unwrap();
+    let select = Apdu::select(PIV_AID).to_bytes();
+
+    let txn = token.begin_transaction().unwrap();
+    assert!(txn.in_transaction());
+    assert!(matches!(
+        other.transmit(&select),
+        Err(PivError::Pcsc(pcsc::Error::SharingViolation))
+    ));
+    drop(txn);
+
+    assert!(!token.in_transaction());
+    assert!(other.transmit(&select).is_ok());
+}
+
+#[test]
+fn pin_is_cleared_when_transaction_ends() {
+    let (reader, mut token) = token_with_key(0x9A);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(true));
+    txn.sign_prehash(0x9A, &digest()).unwrap();
+    txn.end().unwrap();
+
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+    // The card was reset on the way out; the next operation reselects
+    assert!(matches!(
+        token.sign_prehash(0x9A, &digest()),
+        Err(PivError::PinRequired)
+    ));
+}
+
+#[test]
+fn nested_transaction_leaves_outer_open() {
+    let (reader, mut token) = token_with_key(0x9A);
+    let mut txn = token.begin_transaction().unwrap();
+    txn.verify_pin("123456").unwrap();
+    // sign_prehash opens its own transaction, which must not end ours
+    txn.sign_prehash(0x9A, &digest()).unwrap();
+    assert!(txn.in_transaction());
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(true));
+    txn.sign_prehash(0x9A, &digest()).unwrap();
+}
+
+#[test]
+fn reset_on_end_clears_security_status() {
+    let (reader, mut token) = token_with_key(0x9E);
+    // Verified outside a transaction, so nothing would clear it
+    token.verify_pin("123456").unwrap();
+    let mut txn = token.begin_transaction().unwrap();
+    txn.reset_on_end();
+    drop(txn);
+    assert_eq!(reader.with_card_mut(|c| c.is_pin_verified()), Some(false));
+    assert!(token.sign_prehash(0x9E, &digest()).is_ok());
+}
+
+#[test]
+fn reset_by_other_client_reconnects_and_reselects() {
+    let (reader, mut token) = token_with_key(0x9E);
+    reader.reset_card();
+    assert!(token.sign_prehash(0x9E, &digest()).is_ok());
+}
@@ -0,0 +1,121 @@
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use pivy_piv::admin::DEFAULT_ADMIN_KEY;
+use pivy_piv::apdu::alg;
+use pivy_piv::{
+    KeyHistory, KeyOrigin, KeyPolicy, PinPolicy, PivAlgorithm, PivError, PivToken, TouchPolicy,
+    Version,
+};
+use pivy_piv_emu::{EmulatedTransport, VirtualCard};
+
+mod common;
+use common::emulated_token;
+
+/// Generate a key on the card without writing a certificate for it.
+fn generate_bare(token: &mut PivToken<EmulatedTransport>, slot: u8, policy: KeyPolicy) -> Vec<u8> {
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    let key = txn.generate(slot, PivAlgorithm::EcP256, policy).unwrap();
+    key.spki().to_vec()
+}
+
+#[test]
+fn version_is_reported() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new().with_version(5, 7, 2));
+    assert_eq!(token.version().unwrap(), Version::new(5, 7, 2));
+    assert_eq!(token.version().unwrap().to_string(), "5.7.2");
+    assert!(Version::new(5, 7, 2) > Version::new(5, 3, 0));
+    assert!(Version::new(4, 9, 9) < Version::new(5, 0, 0));
+}
+
+#[test]
+fn serial_from_piv_applet() {
+    let card = VirtualCard::new().with_serial(0x00BC614E);
+    let (_reader, mut token) = emulated_token(card);
+    assert_eq!(token.serial().unwrap(), 12345678);
+}
+
+#[test]
+fn serial_falls_back_to_management_applet() {
+    let card = VirtualCard::new().with_version(4, 3, 7).with_serial(9876543);
+    let (reader, mut token) = emulated_token(card);
+    reader.with_card_mut(|c| c.generate_with_cert(0x9A, alg::ECCP256));
+    assert_eq!(token.serial().unwrap(), 9876543);
+    // The PIV applet is selected again afterwards
+    token.read_slot(0x9A).unwrap();
+}
+
+#[test]
+fn metadata_of_generated_key() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let policy = KeyPolicy {
+        pin: PinPolicy::Never,
+        touch: TouchPolicy::Always,
+    };
+    let spki = generate_bare(&mut token, 0x9A, policy);
+
+    let metadata = token.slot_metadata(0x9A).unwrap();
+    assert_eq!(metadata.algorithm(), PivAlgorithm::EcP256);
+    assert_eq!(metadata.pin_policy(), PinPolicy::Never);
+    assert_eq!(metadata.touch_policy(), TouchPolicy::Always);
+    assert_eq!(metadata.origin(), Some(KeyOrigin::Generated));
+    assert_eq!(metadata.public_key().unwrap().spki(), spki.as_slice());
+}
+
+#[test]
+fn metadata_resolves_default_policy() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    generate_bare(&mut token, 0x9C, KeyPolicy::default());
+    let metadata = token.slot_metadata(0x9C).unwrap();
+    assert_eq!(metadata.pin_policy(), PinPolicy::Always);
+    assert_eq!(metadata.touch_policy(), TouchPolicy::Never);
+}
+
+#[test]
+fn metadata_of_empty_slot() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    assert!(matches!(token.slot_metadata(0x9D), Err(PivError::SlotEmpty(0x9D))));
+}
+
+#[test]
+fn metadata_needs_firmware_5_3() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new().with_version(5, 2, 7));
+    generate_bare(&mut token, 0x9A, KeyPolicy::default());
+    assert!(matches!(token.slot_metadata(0x9A), Err(PivError::Other(_))));
+    assert!(matches!(token.read_slot(0x9A), Err(PivError::SlotEmpty(0x9A))));
+}
+
+#[test]
+fn read_all_slots_finds_keys_without_certs() {
+    let (reader, mut token) = emulated_token(VirtualCard::new());
+    reader.with_card_mut(|c| c.generate_with_cert(0x9E, alg::ECCP256));
+    generate_bare(&mut token, 0x9A, KeyPolicy::default());
+    generate_bare(&mut token, 0x85, KeyPolicy::default());
+    let mut txn = token.begin_transaction().unwrap();
+    txn.auth_admin(alg::TDEA_3KEY, &DEFAULT_ADMIN_KEY).unwrap();
+    txn.write_keyhistory(&KeyHistory::new(4, 0, None).unwrap()).unwrap();
+    drop(txn);
+
+    let slots = token.read_all_slots().unwrap();
+    let ids: Vec<u8> = slots.iter().map(|s| s.id()).collect();
+    assert_eq!(ids, vec![0x9A, 0x9E, 0x85]);
+    assert!(slots[0].cert_der().is_empty());
+    assert!(!slots[1].cert_der().is_empty());
+}
+
+#[test]
+fn sign_with_key_without_cert() {
+    let (_reader, mut token) = emulated_token(VirtualCard::new());
+    let policy = KeyPolicy {
+        pin: PinPolicy::Never,
+        ..KeyPolicy::default()
+    };
+    let spki = generate_bare(&mut token, 0x9A, policy);
+
+    let digest = hash(MessageDigest::sha256(), b"hello").unwrap();
+    let sig = token.sign_prehash(0x9A, &digest).unwrap();
+
+    let pkey = openssl::pkey::PKey::public_key_from_der(&spki).unwrap();
+    let sig = EcdsaSig::from_der(&sig).unwrap();
+    assert!(sig.verify(&digest, &pkey.ec_key().unwrap()).unwrap());
+}
@@ -0,0 +1,141 @@
+#! /usr/bin/env bats
+#
+# pivy-agent-rust against the in-process emulated PIV card (built with the
+# `emulator` feature). Exercises listing, signing and PIN lockout without
+# pcscd or a physical token.
+
+setup() {
+  load "$(dirname "$BATS_TEST_FILE")/common.bash"
+  export output
+
+  if [[ -n ${PIVY_AGENT_RUST_EMULATED:-} ]]; then
+    RUST_AGENT="$PIVY_AGENT_RUST_EMULATED"
+  else
+    RUST_AGENT="$(dirname "$BATS_TEST_FILE")/../result-rust-emulated/bin/pivy-agent-rust"
+  fi
+
+  if [[ ! -x $RUST_AGENT ]]; then
+    skip "emulated pivy-agent-rust not found at $RUST_AGENT (run: nix build .#pivy-rust-emulated -o result-rust-emulated)"
+  fi
+
+  PIVY_TMPDIR="$(mktemp -d /tmp/pivy-test.XXXXXX)"
+  export SSH_AUTH_SOCK="$PIVY_TMPDIR/agent.sock"
+  CARD_GUID="E0E1E2E3E4E5E6E7E8E9EAEBECEDEEEF"
+
+  start_agent -A
+
+  echo "signed by the emulated card" >"$PIVY_TMPDIR/message"
+}
+
+# (Re)start the agent on the emulated card with the given options.
+start_agent() {
+  if [[ -n ${AGENT_PID:-} ]]; then
+    kill "$AGENT_PID" 2>/dev/null || true
+    wait "$AGENT_PID" 2>/dev/null || true
+    rm -f "$SSH_AUTH_SOCK"
+  fi
+
+  PIVY_AGENT_EMULATOR="$CARD_GUID" "$RUST_AGENT" "$@" -D -a "$SSH_AUTH_SOCK" \
+    >/dev/null 2>&1 &
+  AGENT_PID=$!
+
+  local tries=0
+  while [[ ! -S $SSH_AUTH_SOCK ]] && ((tries < 10)); do
+    sleep 0.2
+    tries=$((tries + 1))
+  done
+  [[ -S $SSH_AUTH_SOCK ]] || fail "agent socket did not appear"
+}
+
+teardown() {
+  if [[ -n ${AGENT_PID:-} ]]; then
+    kill "$AGENT_PID" 2>/dev/null || true
+    wait "$AGENT_PID" 2>/dev/null || true
+  fi
+  if [[ -n ${PIVY_TMPDIR:-} ]]; then
+    rm -rf "$PIVY_TMPDIR"
+  fi
+}
+
+# Store a PIN in the agent the way users do (ssh-add -X).
+unlock_with_pin() {
+  printf '#!/bin/sh\necho %s\n' "$1" >"$PIVY_TMPDIR/askpass"
+  chmod +x "$PIVY_TMPDIR/askpass"
+  SSH_ASKPASS="$PIVY_TMPDIR/askpass" SSH_ASKPASS_REQUIRE=force DISPLAY=:0 \
+    ssh-add -X </dev/null
+}
+
+sign_with_slot() {
+  ssh-add -L | grep "PIV_slot_$1" >"$PIVY_TMPDIR/key.pub"
+  rm -f "$PIVY_TMPDIR/message.sig"
+  ssh-keygen -Y sign -f "$PIVY_TMPDIR/key.pub" -n test "$PIVY_TMPDIR/message"
+}
+
+function lists_emulated_identities { # @test
+  run ssh-add -l
+  assert_success
+  assert_output --partial "PIV_slot_9A E0E1E2E3"
+  assert_output --partial "PIV_slot_9E E0E1E2E3"
+}
+
+function lists_only_allowed_slots { # @test
+  start_agent -S 9e
+  run ssh-add -l
+  assert_success
+  assert_output --partial "PIV_slot_9E E0E1E2E3"
+  refute_output --partial "PIV_slot_9A"
+}
+
+function lists_nothing_for_another_guid { # @test
+  start_agent -g 00000000
+  run ssh-add -l
+  assert_failure
+  assert_output --partial "no identities"
+}
+
+function signs_with_9e_without_pin { # @test
+  run sign_with_slot 9E
+  assert_success
+
+  echo "card $(cat "$PIVY_TMPDIR/key.pub")" >"$PIVY_TMPDIR/allowed"
+  run ssh-keygen -Y verify -f "$PIVY_TMPDIR/allowed" -I card -n test \
+    -s "$PIVY_TMPDIR/message.sig" <"$PIVY_TMPDIR/message"
+  assert_success
+  assert_output --partial "Good \"test\" signature"
+}
+
+function refuses_9a_without_pin { # @test
+  run sign_with_slot 9A
+  assert_failure
+  assert_output --partial "agent refused operation"
+}
+
+function signs_with_9a_after_unlock { # @test
+  run unlock_with_pin 123456
+  assert_success
+  run sign_with_slot 9A
+  assert_success
+}
+
+function signs_repeatedly_on_one_connection { # @test
+  run unlock_with_pin 123456
+  assert_success
+  for slot in 9A 9E 9A; do
+    run sign_with_slot "$slot"
+    assert_success
+  done
+}
+
+function wrong_pin_locks_card { # @test
+  run unlock_with_pin 000000
+  assert_success
+  for _ in 1 2 3; do
+    run sign_with_slot 9A
+    assert_failure
+  done
+
+  run unlock_with_pin 123456
+  assert_success
+  run sign_with_slot 9A
+  assert_failure
+}
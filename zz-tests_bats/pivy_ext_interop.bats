@@ -4,13 +4,16 @@
 # Requires a YubiKey plugged in.
 #
 # Run:  just zz-tests_bats/test-tags hardware
+#
+# Set PIVY_AGENT_RUST to run the same tests against pivy-agent-rust
+# (just zz-tests_bats/test-hardware-rust).
 
 setup() {
   load "$(dirname "$BATS_TEST_FILE")/common.bash"
   export output
 
   PIVY_DIR="${PIVY_DIR:-$(dirname "$BATS_TEST_FILE")/../result}"
-  PIVY_AGENT="$PIVY_DIR/bin/pivy-agent"
+  PIVY_AGENT="${PIVY_AGENT_RUST:-$PIVY_DIR/bin/pivy-agent}"
   PIVY_TOOL="$PIVY_DIR/bin/pivy-tool"
   PIVY_BOX="$PIVY_DIR/bin/pivy-box"
 